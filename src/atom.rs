@@ -4,12 +4,13 @@ use std::convert::TryFrom;
 
 use x11rb::connection::Connection;
 use x11rb::errors::ConnectionError;
-use x11rb::properties::WmSizeHints;
+use x11rb::properties::{WmHints, WmHintsState, WmSizeHints};
 use x11rb::protocol::xproto;
 use x11rb::protocol::xproto::ConnectionExt as _;
 use x11rb::rust_connection::ReplyError;
 use x11rb::wrapper::ConnectionExt as _;
 
+use crate::config::TitleEncoding;
 use crate::Result;
 
 /// A client's WM_PROTOCOLS. We ignore the deprecated WM_SAVE_YOURSELF protocol.
@@ -59,6 +60,31 @@ impl TryFrom<&[u32]> for WmState {
     }
 }
 
+/// A client's WM_CLASS.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Hash, Debug)]
+pub(crate) struct WmClass {
+    /// The instance name, usually taken from the program's `argv[0]`.
+    pub(crate) instance: String,
+    /// The class name, shared by all instances of the application.
+    pub(crate) class: String,
+}
+
+impl TryFrom<&[u8]> for WmClass {
+    type Error = ();
+
+    /// Parse the two consecutive NUL-terminated strings in a WM_CLASS value.
+    fn try_from(value: &[u8]) -> std::result::Result<Self, Self::Error> {
+        let mut parts = value.split(|&b| b == 0);
+        match (parts.next(), parts.next()) {
+            (Some(instance), Some(class)) => Ok(WmClass {
+                instance: decode_text(instance),
+                class: decode_text(class),
+            }),
+            _ => Err(()),
+        }
+    }
+}
+
 /// Possible values for WM_STATE.state.
 #[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Hash, Debug)]
 pub(crate) enum WmStateState {
@@ -93,9 +119,135 @@ impl TryFrom<u32> for WmStateState {
     }
 }
 
-/// Keeps track of standard ICCCM atoms, and provides a few functions for
-/// getting/setting certain properties.
+impl WmStateState {
+    /// The state that a window should enter when it leaves the Withdrawn
+    /// state, according to the initial_state field of its WM_HINTS.
+    pub(crate) fn initial(hints: &WmHints) -> Self {
+        match hints.initial_state {
+            Some(WmHintsState::Iconic) => WmStateState::Iconic,
+            Some(WmHintsState::Normal) | None => WmStateState::Normal,
+        }
+    }
+}
+
+/// Possible actions in a _NET_WM_STATE client message.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Hash, Debug)]
+pub(crate) enum NetWmStateAction {
+    /// Remove the state.
+    Remove,
+    /// Add the state.
+    Add,
+    /// Toggle the state.
+    Toggle,
+}
+
+impl NetWmStateAction {
+    /// Apply the action to a state that is currently `current`.
+    pub(crate) fn apply(self, current: bool) -> bool {
+        match self {
+            NetWmStateAction::Remove => false,
+            NetWmStateAction::Add => true,
+            NetWmStateAction::Toggle => !current,
+        }
+    }
+}
+
+impl TryFrom<u32> for NetWmStateAction {
+    type Error = ();
+
+    fn try_from(value: u32) -> std::result::Result<Self, Self::Error> {
+        match value {
+            0 => Ok(NetWmStateAction::Remove),
+            1 => Ok(NetWmStateAction::Add),
+            2 => Ok(NetWmStateAction::Toggle),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Actions that may be listed in a window's _NET_WM_ALLOWED_ACTIONS.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Hash, Debug)]
+pub(crate) enum AllowedAction {
+    /// Moving the window.
+    Move,
+    /// Resizing the window.
+    Resize,
+    /// Making the window fullscreen.
+    Fullscreen,
+    /// Iconifying the window.
+    Minimize,
+    /// Closing the window.
+    Close,
+}
+
+/// The actions that we allow on a window. Locked windows can't be moved or
+/// resized.
+pub(crate) fn allowed_actions(locked: bool) -> Vec<AllowedAction> {
+    let mut actions = Vec::new();
+    if !locked {
+        actions.push(AllowedAction::Move);
+        actions.push(AllowedAction::Resize);
+    }
+    actions.push(AllowedAction::Fullscreen);
+    actions.push(AllowedAction::Minimize);
+    actions.push(AllowedAction::Close);
+    actions
+}
+
+/// One of the images in a window's _NET_WM_ICON.
+#[derive(PartialEq, Eq, Clone, Hash, Debug)]
+pub(crate) struct NetWmIcon {
+    /// Width, in pixels.
+    pub(crate) width: u32,
+    /// Height, in pixels.
+    pub(crate) height: u32,
+    /// The pixels, as ARGB, row by row.
+    pub(crate) pixels: Vec<u32>,
+}
+
+/// Parse a _NET_WM_ICON value, which is any number of images, each being a
+/// width, a height and then width * height pixels. A truncated image at the
+/// end is ignored.
+pub(crate) fn parse_net_wm_icon(mut data: &[u32]) -> Vec<NetWmIcon> {
+    let mut icons = Vec::new();
+    while let [width, height, ref rest @ ..] = *data {
+        let len = match (width as usize).checked_mul(height as usize) {
+            Some(len) if len <= rest.len() => len,
+            _ => break,
+        };
+        icons.push(NetWmIcon {
+            width,
+            height,
+            pixels: rest[..len].to_vec(),
+        });
+        data = &rest[len..];
+    }
+    icons
+}
+
+/// Pick the icon whose larger dimension is closest to `size`, preferring to
+/// scale down rather than up when two are equally close.
+pub(crate) fn closest_icon(icons: &[NetWmIcon], size: u32) -> Option<&NetWmIcon> {
+    icons.iter().min_by_key(|icon| {
+        let icon_size = icon.width.max(icon.height);
+        (
+            (i64::from(icon_size) - i64::from(size)).abs(),
+            icon_size < size,
+        )
+    })
+}
+
+/// Keeps track of standard ICCCM and EWMH atoms, and provides a few functions
+/// for getting/setting certain properties.
 pub(crate) struct Atoms {
+    /// The interned UTF8_STRING atom.
+    pub(crate) utf8_string: xproto::Atom,
+    /// The interned _NET_SUPPORTED atom.
+    pub(crate) net_supported: xproto::Atom,
+    /// The interned _NET_SUPPORTING_WM_CHECK atom.
+    pub(crate) net_supporting_wm_check: xproto::Atom,
+    /// The interned _NET_WM_NAME atom.
+    pub(crate) net_wm_name: xproto::Atom,
     /// The interned WM_DELETE_WINDOW atom.
     pub(crate) wm_delete_window: xproto::Atom,
     /// The interned WM_PROTOCOLS atom.
@@ -106,6 +258,56 @@ pub(crate) struct Atoms {
     pub(crate) wm_state: xproto::Atom,
     /// The interned WM_TAKE_FOCUS atom.
     pub(crate) wm_take_focus: xproto::Atom,
+    /// The interned WM_WINDOW_ROLE atom.
+    pub(crate) wm_window_role: xproto::Atom,
+    /// The interned _NET_WM_STATE atom.
+    pub(crate) net_wm_state: xproto::Atom,
+    /// The interned _NET_WM_STATE_DEMANDS_ATTENTION atom.
+    pub(crate) net_wm_state_demands_attention: xproto::Atom,
+    /// The interned SM_CLIENT_ID atom.
+    pub(crate) sm_client_id: xproto::Atom,
+    /// The interned WM_CLIENT_LEADER atom.
+    pub(crate) wm_client_leader: xproto::Atom,
+    /// The interned _NET_WM_STATE_MODAL atom.
+    pub(crate) net_wm_state_modal: xproto::Atom,
+    /// The interned _NET_WM_USER_TIME atom.
+    pub(crate) net_wm_user_time: xproto::Atom,
+    /// The interned _NET_FRAME_EXTENTS atom.
+    pub(crate) net_frame_extents: xproto::Atom,
+    /// The interned _NET_WM_STATE_FULLSCREEN atom.
+    pub(crate) net_wm_state_fullscreen: xproto::Atom,
+    /// The interned _GTK_FRAME_EXTENTS atom.
+    pub(crate) gtk_frame_extents: xproto::Atom,
+    /// The interned WM_CHANGE_STATE atom.
+    pub(crate) wm_change_state: xproto::Atom,
+    /// The interned _NET_CLIENT_LIST atom.
+    pub(crate) net_client_list: xproto::Atom,
+    /// The interned _NET_CLIENT_LIST_STACKING atom.
+    pub(crate) net_client_list_stacking: xproto::Atom,
+    /// The interned _NET_WM_WINDOW_TYPE atom.
+    pub(crate) net_wm_window_type: xproto::Atom,
+    /// The interned _NET_WM_WINDOW_TYPE_DOCK atom.
+    pub(crate) net_wm_window_type_dock: xproto::Atom,
+    /// The interned _NET_WM_WINDOW_TYPE_DESKTOP atom.
+    pub(crate) net_wm_window_type_desktop: xproto::Atom,
+    /// The interned _NET_WM_WINDOW_OPACITY atom.
+    pub(crate) net_wm_window_opacity: xproto::Atom,
+    /// The interned _NET_WM_ICON atom.
+    pub(crate) net_wm_icon: xproto::Atom,
+    /// The interned _NET_WM_ALLOWED_ACTIONS atom.
+    pub(crate) net_wm_allowed_actions: xproto::Atom,
+    /// The interned _NET_WM_ACTION_MOVE atom.
+    pub(crate) net_wm_action_move: xproto::Atom,
+    /// The interned _NET_WM_ACTION_RESIZE atom.
+    pub(crate) net_wm_action_resize: xproto::Atom,
+    /// The interned _NET_WM_ACTION_FULLSCREEN atom.
+    pub(crate) net_wm_action_fullscreen: xproto::Atom,
+    /// The interned _NET_WM_ACTION_MINIMIZE atom.
+    pub(crate) net_wm_action_minimize: xproto::Atom,
+    /// The interned _NET_WM_ACTION_CLOSE atom.
+    pub(crate) net_wm_action_close: xproto::Atom,
+    /// The interned _NET_ACTIVE_WINDOW atom.
+    pub(crate) net_active_window: xproto::Atom,
 }
 
 impl Atoms {
@@ -114,6 +316,26 @@ impl Atoms {
     where
         Conn: Connection,
     {
+        log::trace!("Interning UTF8_STRING.");
+        let utf8_string = conn
+            .intern_atom(false, "UTF8_STRING".as_bytes())?
+            .reply()?
+            .atom;
+        log::trace!("Interning _NET_SUPPORTED.");
+        let net_supported = conn
+            .intern_atom(false, "_NET_SUPPORTED".as_bytes())?
+            .reply()?
+            .atom;
+        log::trace!("Interning _NET_SUPPORTING_WM_CHECK.");
+        let net_supporting_wm_check = conn
+            .intern_atom(false, "_NET_SUPPORTING_WM_CHECK".as_bytes())?
+            .reply()?
+            .atom;
+        log::trace!("Interning _NET_WM_NAME.");
+        let net_wm_name = conn
+            .intern_atom(false, "_NET_WM_NAME".as_bytes())?
+            .reply()?
+            .atom;
         log::trace!("Interning WM_DELETE_WINDOW.");
         let wm_delete_window = conn
             .intern_atom(false, "WM_DELETE_WINDOW".as_bytes())?
@@ -139,13 +361,167 @@ impl Atoms {
             .intern_atom(false, "WM_TAKE_FOCUS".as_bytes())?
             .reply()?
             .atom;
+        log::trace!("Interning WM_WINDOW_ROLE.");
+        let wm_window_role = conn
+            .intern_atom(false, "WM_WINDOW_ROLE".as_bytes())?
+            .reply()?
+            .atom;
+        log::trace!("Interning _NET_WM_STATE.");
+        let net_wm_state = conn
+            .intern_atom(false, "_NET_WM_STATE".as_bytes())?
+            .reply()?
+            .atom;
+        log::trace!("Interning _NET_WM_STATE_DEMANDS_ATTENTION.");
+        let net_wm_state_demands_attention = conn
+            .intern_atom(false, "_NET_WM_STATE_DEMANDS_ATTENTION".as_bytes())?
+            .reply()?
+            .atom;
+        log::trace!("Interning SM_CLIENT_ID.");
+        let sm_client_id = conn
+            .intern_atom(false, "SM_CLIENT_ID".as_bytes())?
+            .reply()?
+            .atom;
+        log::trace!("Interning WM_CLIENT_LEADER.");
+        let wm_client_leader = conn
+            .intern_atom(false, "WM_CLIENT_LEADER".as_bytes())?
+            .reply()?
+            .atom;
+        log::trace!("Interning _NET_WM_STATE_MODAL.");
+        let net_wm_state_modal = conn
+            .intern_atom(false, "_NET_WM_STATE_MODAL".as_bytes())?
+            .reply()?
+            .atom;
+        log::trace!("Interning _NET_WM_USER_TIME.");
+        let net_wm_user_time = conn
+            .intern_atom(false, "_NET_WM_USER_TIME".as_bytes())?
+            .reply()?
+            .atom;
+        log::trace!("Interning _NET_FRAME_EXTENTS.");
+        let net_frame_extents = conn
+            .intern_atom(false, "_NET_FRAME_EXTENTS".as_bytes())?
+            .reply()?
+            .atom;
+        log::trace!("Interning _NET_WM_STATE_FULLSCREEN.");
+        let net_wm_state_fullscreen = conn
+            .intern_atom(false, "_NET_WM_STATE_FULLSCREEN".as_bytes())?
+            .reply()?
+            .atom;
+        log::trace!("Interning _GTK_FRAME_EXTENTS.");
+        let gtk_frame_extents = conn
+            .intern_atom(false, "_GTK_FRAME_EXTENTS".as_bytes())?
+            .reply()?
+            .atom;
+        log::trace!("Interning WM_CHANGE_STATE.");
+        let wm_change_state = conn
+            .intern_atom(false, "WM_CHANGE_STATE".as_bytes())?
+            .reply()?
+            .atom;
+        log::trace!("Interning _NET_CLIENT_LIST.");
+        let net_client_list = conn
+            .intern_atom(false, "_NET_CLIENT_LIST".as_bytes())?
+            .reply()?
+            .atom;
+        log::trace!("Interning _NET_CLIENT_LIST_STACKING.");
+        let net_client_list_stacking = conn
+            .intern_atom(false, "_NET_CLIENT_LIST_STACKING".as_bytes())?
+            .reply()?
+            .atom;
+        log::trace!("Interning _NET_WM_WINDOW_TYPE.");
+        let net_wm_window_type = conn
+            .intern_atom(false, "_NET_WM_WINDOW_TYPE".as_bytes())?
+            .reply()?
+            .atom;
+        log::trace!("Interning _NET_WM_WINDOW_TYPE_DOCK.");
+        let net_wm_window_type_dock = conn
+            .intern_atom(false, "_NET_WM_WINDOW_TYPE_DOCK".as_bytes())?
+            .reply()?
+            .atom;
+        log::trace!("Interning _NET_WM_WINDOW_TYPE_DESKTOP.");
+        let net_wm_window_type_desktop = conn
+            .intern_atom(false, "_NET_WM_WINDOW_TYPE_DESKTOP".as_bytes())?
+            .reply()?
+            .atom;
+        log::trace!("Interning _NET_WM_WINDOW_OPACITY.");
+        let net_wm_window_opacity = conn
+            .intern_atom(false, "_NET_WM_WINDOW_OPACITY".as_bytes())?
+            .reply()?
+            .atom;
+        log::trace!("Interning _NET_WM_ICON.");
+        let net_wm_icon = conn
+            .intern_atom(false, "_NET_WM_ICON".as_bytes())?
+            .reply()?
+            .atom;
+        log::trace!("Interning _NET_WM_ALLOWED_ACTIONS.");
+        let net_wm_allowed_actions = conn
+            .intern_atom(false, "_NET_WM_ALLOWED_ACTIONS".as_bytes())?
+            .reply()?
+            .atom;
+        log::trace!("Interning _NET_WM_ACTION_MOVE.");
+        let net_wm_action_move = conn
+            .intern_atom(false, "_NET_WM_ACTION_MOVE".as_bytes())?
+            .reply()?
+            .atom;
+        log::trace!("Interning _NET_WM_ACTION_RESIZE.");
+        let net_wm_action_resize = conn
+            .intern_atom(false, "_NET_WM_ACTION_RESIZE".as_bytes())?
+            .reply()?
+            .atom;
+        log::trace!("Interning _NET_WM_ACTION_FULLSCREEN.");
+        let net_wm_action_fullscreen = conn
+            .intern_atom(false, "_NET_WM_ACTION_FULLSCREEN".as_bytes())?
+            .reply()?
+            .atom;
+        log::trace!("Interning _NET_WM_ACTION_MINIMIZE.");
+        let net_wm_action_minimize = conn
+            .intern_atom(false, "_NET_WM_ACTION_MINIMIZE".as_bytes())?
+            .reply()?
+            .atom;
+        log::trace!("Interning _NET_WM_ACTION_CLOSE.");
+        let net_wm_action_close = conn
+            .intern_atom(false, "_NET_WM_ACTION_CLOSE".as_bytes())?
+            .reply()?
+            .atom;
+        log::trace!("Interning _NET_ACTIVE_WINDOW.");
+        let net_active_window = conn
+            .intern_atom(false, "_NET_ACTIVE_WINDOW".as_bytes())?
+            .reply()?
+            .atom;
         log::trace!("All atoms successfully interned.");
         Ok(Atoms {
+            utf8_string,
+            net_supported,
+            net_supporting_wm_check,
+            net_wm_name,
             wm_delete_window,
             wm_protocols,
             wm_save_yourself,
             wm_state,
             wm_take_focus,
+            wm_window_role,
+            net_wm_state,
+            net_wm_state_demands_attention,
+            sm_client_id,
+            wm_client_leader,
+            net_wm_state_modal,
+            net_wm_user_time,
+            net_frame_extents,
+            net_wm_state_fullscreen,
+            gtk_frame_extents,
+            wm_change_state,
+            net_client_list,
+            net_client_list_stacking,
+            net_wm_window_type,
+            net_wm_window_type_dock,
+            net_wm_window_type_desktop,
+            net_wm_window_opacity,
+            net_wm_icon,
+            net_wm_allowed_actions,
+            net_wm_action_move,
+            net_wm_action_resize,
+            net_wm_action_fullscreen,
+            net_wm_action_minimize,
+            net_wm_action_close,
+            net_active_window,
         })
     }
 
@@ -234,6 +610,170 @@ impl Atoms {
         }
     }
 
+    /// Get a window's WM_HINTS property.
+    pub(crate) fn get_wm_hints<Conn>(&self, conn: &Conn, window: xproto::Window) -> Result<WmHints>
+    where
+        Conn: Connection,
+    {
+        match WmHints::get(conn, window)?.reply() {
+            Ok(x) => Ok(x),
+            Err(ReplyError::ConnectionError(ConnectionError::ParseError(_))) => Ok(WmHints::new()),
+            Err(err) => Err(Box::new(err)),
+        }
+    }
+
+    /// Get a window's WM_CLASS property.
+    pub(crate) fn get_wm_class<Conn>(
+        &self,
+        conn: &Conn,
+        window: xproto::Window,
+    ) -> Result<Option<WmClass>>
+    where
+        Conn: Connection,
+    {
+        let value = get_text_property(conn, window, xproto::AtomEnum::WM_CLASS.into())?;
+        Ok(value.and_then(|value| WmClass::try_from(&value[..]).ok()))
+    }
+
+    /// Get a window's WM_WINDOW_ROLE property.
+    pub(crate) fn get_wm_window_role<Conn>(
+        &self,
+        conn: &Conn,
+        window: xproto::Window,
+    ) -> Result<Option<String>>
+    where
+        Conn: Connection,
+    {
+        let value = get_text_property(conn, window, self.wm_window_role)?;
+        Ok(value.map(|value| decode_text(&value)))
+    }
+
+    /// Get a window's title, preferring _NET_WM_NAME over WM_NAME. A WM_NAME
+    /// of type STRING is decoded according to `encoding`.
+    pub(crate) fn get_title<Conn>(
+        &self,
+        conn: &Conn,
+        window: xproto::Window,
+        encoding: TitleEncoding,
+    ) -> Result<Option<String>>
+    where
+        Conn: Connection,
+    {
+        if let Some(value) = get_text_property(conn, window, self.net_wm_name)? {
+            return Ok(Some(decode_text(&value)));
+        }
+        let value = get_typed_text_property(conn, window, xproto::AtomEnum::WM_NAME.into())?;
+        Ok(value.map(|(type_, value)| {
+            if type_ == xproto::Atom::from(xproto::AtomEnum::STRING) {
+                decode_string(&value, encoding)
+            } else {
+                decode_text(&value)
+            }
+        }))
+    }
+
+    /// Get a window's WM_COMMAND property, falling back to its client leader's.
+    pub(crate) fn get_wm_command<Conn>(
+        &self,
+        conn: &Conn,
+        window: xproto::Window,
+    ) -> Result<Option<Vec<String>>>
+    where
+        Conn: Connection,
+    {
+        let value = self.get_session_property(conn, window, xproto::AtomEnum::WM_COMMAND.into())?;
+        Ok(value.map(|value| parse_wm_command(&value)))
+    }
+
+    /// Get the window that a window is transient for, from its
+    /// WM_TRANSIENT_FOR property.
+    pub(crate) fn get_wm_transient_for<Conn>(
+        &self,
+        conn: &Conn,
+        window: xproto::Window,
+    ) -> Result<Option<xproto::Window>>
+    where
+        Conn: Connection,
+    {
+        let reply = conn
+            .get_property(
+                false,
+                window,
+                xproto::AtomEnum::WM_TRANSIENT_FOR,
+                xproto::AtomEnum::WINDOW,
+                0,
+                1,
+            )?
+            .reply()?;
+        let transient_for = match reply.value32() {
+            None => None,
+            Some(mut x) => x.next(),
+        };
+        Ok(transient_for.filter(|&parent| parent != x11rb::NONE))
+    }
+
+    /// Get the X resource database, from the root window's RESOURCE_MANAGER
+    /// property.
+    pub(crate) fn get_resource_manager<Conn>(
+        &self,
+        conn: &Conn,
+        root: xproto::Window,
+    ) -> Result<Option<String>>
+    where
+        Conn: Connection,
+    {
+        let value = get_text_property(conn, root, xproto::AtomEnum::RESOURCE_MANAGER.into())?;
+        Ok(value.map(|value| decode_text(&value)))
+    }
+
+    /// Get a window's SM_CLIENT_ID property, falling back to its client
+    /// leader's.
+    pub(crate) fn get_sm_client_id<Conn>(
+        &self,
+        conn: &Conn,
+        window: xproto::Window,
+    ) -> Result<Option<String>>
+    where
+        Conn: Connection,
+    {
+        let value = self.get_session_property(conn, window, self.sm_client_id)?;
+        Ok(value.map(|value| decode_text(&value)))
+    }
+
+    /// Read a session management text property. ICCCM says these belong on the
+    /// client leader window, but plenty of clients set them on their top-level
+    /// windows, so we try both.
+    fn get_session_property<Conn>(
+        &self,
+        conn: &Conn,
+        window: xproto::Window,
+        property: xproto::Atom,
+    ) -> Result<Option<Vec<u8>>>
+    where
+        Conn: Connection,
+    {
+        if let Some(value) = get_text_property(conn, window, property)? {
+            return Ok(Some(value));
+        }
+        let reply = conn
+            .get_property(
+                false,
+                window,
+                self.wm_client_leader,
+                xproto::AtomEnum::WINDOW,
+                0,
+                1,
+            )?
+            .reply()?;
+        let leader = reply.value32().and_then(|mut x| x.next());
+        match leader {
+            Some(leader) if leader != window && leader != x11rb::NONE => {
+                get_text_property(conn, leader, property)
+            }
+            _ => Ok(None),
+        }
+    }
+
     /// Get a window's WM_STATE property.
     pub(crate) fn get_wm_state<Conn>(
         &self,
@@ -257,6 +797,392 @@ impl Atoms {
         })
     }
 
+    /// Set up the EWMH supporting WM check: point the root window and the
+    /// check window at the check window, and name the check window after the
+    /// window manager, with the given version.
+    pub(crate) fn set_supporting_wm_check<Conn>(
+        &self,
+        conn: &Conn,
+        root: xproto::Window,
+        check_window: xproto::Window,
+        version: &str,
+    ) -> Result<()>
+    where
+        Conn: Connection,
+    {
+        for &window in &[root, check_window] {
+            conn.change_property32(
+                xproto::PropMode::REPLACE,
+                window,
+                self.net_supporting_wm_check,
+                xproto::AtomEnum::WINDOW,
+                &[check_window],
+            )?
+            .check()?;
+        }
+        conn.change_property8(
+            xproto::PropMode::REPLACE,
+            check_window,
+            self.net_wm_name,
+            self.utf8_string,
+            &net_wm_name(version),
+        )?
+        .check()?;
+        Ok(())
+    }
+
+    /// Set the root window's _NET_SUPPORTED property, advertising the EWMH
+    /// hints that we support.
+    pub(crate) fn set_net_supported<Conn>(&self, conn: &Conn, root: xproto::Window) -> Result<()>
+    where
+        Conn: Connection,
+    {
+        let supported = [
+            self.net_supported,
+            self.net_supporting_wm_check,
+            self.net_wm_state,
+            self.net_wm_state_demands_attention,
+            self.net_wm_state_modal,
+            self.net_wm_state_fullscreen,
+            self.net_wm_user_time,
+            self.net_frame_extents,
+            self.gtk_frame_extents,
+            self.net_client_list,
+            self.net_client_list_stacking,
+            self.net_wm_window_type,
+            self.net_wm_window_type_dock,
+            self.net_wm_window_type_desktop,
+            self.net_wm_allowed_actions,
+            self.net_wm_action_move,
+            self.net_wm_action_resize,
+            self.net_wm_action_fullscreen,
+            self.net_wm_action_minimize,
+            self.net_wm_action_close,
+            self.net_active_window,
+        ];
+        conn.change_property32(
+            xproto::PropMode::REPLACE,
+            root,
+            self.net_supported,
+            xproto::AtomEnum::ATOM,
+            &supported,
+        )?
+        .check()?;
+        Ok(())
+    }
+
+    /// Get a window's _NET_WM_STATE property. If the property is not set, the
+    /// window has no states.
+    pub(crate) fn get_net_wm_state<Conn>(
+        &self,
+        conn: &Conn,
+        window: xproto::Window,
+    ) -> Result<Vec<xproto::Atom>>
+    where
+        Conn: Connection,
+    {
+        let reply = conn
+            .get_property(
+                false,
+                window,
+                self.net_wm_state,
+                xproto::AtomEnum::ATOM,
+                0,
+                1024,
+            )?
+            .reply()?;
+        let states = match reply.value32() {
+            None => Vec::new(),
+            Some(x) => x.collect(),
+        };
+        Ok(states)
+    }
+
+    /// Decide from a window's _NET_WM_WINDOW_TYPE property whether it should
+    /// ever get the input focus. Docks and desktops shouldn't.
+    pub(crate) fn get_focusable<Conn>(&self, conn: &Conn, window: xproto::Window) -> Result<bool>
+    where
+        Conn: Connection,
+    {
+        let types = self.get_net_wm_window_type(conn, window)?;
+        Ok(!types
+            .iter()
+            .any(|&t| t == self.net_wm_window_type_dock || t == self.net_wm_window_type_desktop))
+    }
+
+    /// Decide from a window's _NET_WM_WINDOW_TYPE property whether it's a
+    /// desktop, which belongs underneath every other window.
+    pub(crate) fn get_desktop<Conn>(&self, conn: &Conn, window: xproto::Window) -> Result<bool>
+    where
+        Conn: Connection,
+    {
+        let types = self.get_net_wm_window_type(conn, window)?;
+        Ok(types.contains(&self.net_wm_window_type_desktop))
+    }
+
+    /// Get a window's _NET_WM_WINDOW_TYPE property. If the property is not
+    /// set, the window has no types.
+    fn get_net_wm_window_type<Conn>(
+        &self,
+        conn: &Conn,
+        window: xproto::Window,
+    ) -> Result<Vec<xproto::Atom>>
+    where
+        Conn: Connection,
+    {
+        let reply = conn
+            .get_property(
+                false,
+                window,
+                self.net_wm_window_type,
+                xproto::AtomEnum::ATOM,
+                0,
+                1024,
+            )?
+            .reply()?;
+        let types = match reply.value32() {
+            None => Vec::new(),
+            Some(x) => x.collect(),
+        };
+        Ok(types)
+    }
+
+    /// Get the images in a window's _NET_WM_ICON property.
+    pub(crate) fn get_net_wm_icon<Conn>(
+        &self,
+        conn: &Conn,
+        window: xproto::Window,
+    ) -> Result<Vec<NetWmIcon>>
+    where
+        Conn: Connection,
+    {
+        let reply = conn
+            .get_property(
+                false,
+                window,
+                self.net_wm_icon,
+                xproto::AtomEnum::CARDINAL,
+                0,
+                u32::MAX,
+            )?
+            .reply()?;
+        let data: Vec<u32> = match reply.value32() {
+            None => Vec::new(),
+            Some(x) => x.collect(),
+        };
+        Ok(parse_net_wm_icon(&data))
+    }
+
+    /// Get a window's _NET_WM_USER_TIME property, the time of the last user
+    /// interaction with it.
+    pub(crate) fn get_net_wm_user_time<Conn>(
+        &self,
+        conn: &Conn,
+        window: xproto::Window,
+    ) -> Result<Option<xproto::Timestamp>>
+    where
+        Conn: Connection,
+    {
+        let reply = conn
+            .get_property(
+                false,
+                window,
+                self.net_wm_user_time,
+                xproto::AtomEnum::CARDINAL,
+                0,
+                1,
+            )?
+            .reply()?;
+        let time = match reply.value32() {
+            None => None,
+            Some(mut x) => x.next(),
+        };
+        Ok(time)
+    }
+
+    /// Add a state to or remove a state from a window's _NET_WM_STATE property.
+    pub(crate) fn set_net_wm_state<Conn>(
+        &self,
+        conn: &Conn,
+        window: xproto::Window,
+        state: xproto::Atom,
+        on: bool,
+    ) -> Result<()>
+    where
+        Conn: Connection,
+    {
+        let mut states = self.get_net_wm_state(conn, window)?;
+        states.retain(|&s| s != state);
+        if on {
+            states.push(state);
+        }
+        conn.change_property32(
+            xproto::PropMode::REPLACE,
+            window,
+            self.net_wm_state,
+            xproto::AtomEnum::ATOM,
+            &states,
+        )?
+        .check()?;
+        Ok(())
+    }
+
+    /// Get a window's _GTK_FRAME_EXTENTS property: the left, right, top and
+    /// bottom widths of the client-side shadow that isn't really part of the
+    /// window. Missing or malformed extents are all zero.
+    pub(crate) fn get_gtk_frame_extents<Conn>(
+        &self,
+        conn: &Conn,
+        window: xproto::Window,
+    ) -> Result<[u32; 4]>
+    where
+        Conn: Connection,
+    {
+        let reply = conn
+            .get_property(
+                false,
+                window,
+                self.gtk_frame_extents,
+                xproto::AtomEnum::CARDINAL,
+                0,
+                4,
+            )?
+            .reply()?;
+        let extents = match reply.value32() {
+            None => Vec::new(),
+            Some(x) => x.collect(),
+        };
+        Ok(match extents[..] {
+            [left, right, top, bottom] => [left, right, top, bottom],
+            _ => [0; 4],
+        })
+    }
+
+    /// Set the _NET_CLIENT_LIST and _NET_CLIENT_LIST_STACKING properties on the
+    /// root window.
+    pub(crate) fn set_net_client_list<Conn>(
+        &self,
+        conn: &Conn,
+        root: xproto::Window,
+        client_list: &[xproto::Window],
+        client_list_stacking: &[xproto::Window],
+    ) -> Result<()>
+    where
+        Conn: Connection,
+    {
+        for &(property, windows) in &[
+            (self.net_client_list, client_list),
+            (self.net_client_list_stacking, client_list_stacking),
+        ] {
+            conn.change_property32(
+                xproto::PropMode::REPLACE,
+                root,
+                property,
+                xproto::AtomEnum::WINDOW,
+                windows,
+            )?
+            .check()?;
+        }
+        Ok(())
+    }
+
+    /// Set the _NET_ACTIVE_WINDOW property on the root window to the focused
+    /// window, or to `None` if no window has the focus.
+    pub(crate) fn set_net_active_window<Conn>(
+        &self,
+        conn: &Conn,
+        root: xproto::Window,
+        window: Option<xproto::Window>,
+    ) -> Result<()>
+    where
+        Conn: Connection,
+    {
+        conn.change_property32(
+            xproto::PropMode::REPLACE,
+            root,
+            self.net_active_window,
+            xproto::AtomEnum::WINDOW,
+            &[window.unwrap_or(x11rb::NONE)],
+        )?
+        .check()?;
+        Ok(())
+    }
+
+    /// Set a window's _NET_WM_WINDOW_OPACITY property, which compositors use
+    /// to make it translucent.
+    pub(crate) fn set_net_wm_window_opacity<Conn>(
+        &self,
+        conn: &Conn,
+        window: xproto::Window,
+        opacity: f64,
+    ) -> Result<()>
+    where
+        Conn: Connection,
+    {
+        conn.change_property32(
+            xproto::PropMode::REPLACE,
+            window,
+            self.net_wm_window_opacity,
+            xproto::AtomEnum::CARDINAL,
+            &[window_opacity(opacity)],
+        )?
+        .check()?;
+        Ok(())
+    }
+
+    /// Set a window's _NET_FRAME_EXTENTS property to match its border.
+    pub(crate) fn set_net_frame_extents<Conn>(
+        &self,
+        conn: &Conn,
+        window: xproto::Window,
+        border_width: u16,
+    ) -> Result<()>
+    where
+        Conn: Connection,
+    {
+        conn.change_property32(
+            xproto::PropMode::REPLACE,
+            window,
+            self.net_frame_extents,
+            xproto::AtomEnum::CARDINAL,
+            &frame_extents(border_width),
+        )?
+        .check()?;
+        Ok(())
+    }
+
+    /// Set a window's _NET_WM_ALLOWED_ACTIONS property, according to whether
+    /// it's locked.
+    pub(crate) fn set_net_wm_allowed_actions<Conn>(
+        &self,
+        conn: &Conn,
+        window: xproto::Window,
+        locked: bool,
+    ) -> Result<()>
+    where
+        Conn: Connection,
+    {
+        let actions: Vec<xproto::Atom> = allowed_actions(locked)
+            .into_iter()
+            .map(|action| match action {
+                AllowedAction::Move => self.net_wm_action_move,
+                AllowedAction::Resize => self.net_wm_action_resize,
+                AllowedAction::Fullscreen => self.net_wm_action_fullscreen,
+                AllowedAction::Minimize => self.net_wm_action_minimize,
+                AllowedAction::Close => self.net_wm_action_close,
+            })
+            .collect();
+        conn.change_property32(
+            xproto::PropMode::REPLACE,
+            window,
+            self.net_wm_allowed_actions,
+            xproto::AtomEnum::ATOM,
+            &actions,
+        )?
+        .check()?;
+        Ok(())
+    }
+
     /// Set a window's WM_STATE property.
     pub(crate) fn set_wm_state<Conn>(
         &self,
@@ -279,3 +1205,267 @@ impl Atoms {
         Ok(())
     }
 }
+
+/// The _NET_FRAME_EXTENTS (left, right, top, bottom) of a window with the given
+/// border width. We don't draw any other decorations, so the border is the
+/// whole frame.
+pub(crate) fn frame_extents(border_width: u16) -> [u32; 4] {
+    let width = u32::from(border_width);
+    [width, width, width, width]
+}
+
+/// The _NET_WM_NAME value that we advertise on the supporting WM check window,
+/// including our version.
+fn net_wm_name(version: &str) -> Vec<u8> {
+    format!("OxWM {}", version).into_bytes()
+}
+
+/// The _NET_WM_WINDOW_OPACITY value for an opacity between 0 (transparent) and
+/// 1 (opaque). Opacities outside that range are clamped to it.
+pub(crate) fn window_opacity(opacity: f64) -> u32 {
+    (opacity.clamp(0.0, 1.0) * f64::from(u32::MAX)).round() as u32
+}
+
+/// Read the raw bytes of an 8-bit text property. Returns `None` if the property
+/// isn't set.
+fn get_text_property<Conn>(
+    conn: &Conn,
+    window: xproto::Window,
+    property: xproto::Atom,
+) -> Result<Option<Vec<u8>>>
+where
+    Conn: Connection,
+{
+    let value = get_typed_text_property(conn, window, property)?;
+    Ok(value.map(|(_, value)| value))
+}
+
+/// Like `get_text_property`, but also return the property's type.
+fn get_typed_text_property<Conn>(
+    conn: &Conn,
+    window: xproto::Window,
+    property: xproto::Atom,
+) -> Result<Option<(xproto::Atom, Vec<u8>)>>
+where
+    Conn: Connection,
+{
+    let reply = conn
+        .get_property(
+            false,
+            window,
+            property,
+            xproto::AtomEnum::ANY,
+            0,
+            // Arbitrary length, but no sane text property should be this long.
+            1_000_000,
+        )?
+        .reply()?;
+    Ok(match reply.format {
+        8 => Some((reply.type_, reply.value)),
+        _ => None,
+    })
+}
+
+/// Parse a WM_COMMAND value, which is the program's argv as a sequence of
+/// NUL-terminated strings.
+pub(crate) fn parse_wm_command(value: &[u8]) -> Vec<String> {
+    let value = value.strip_suffix(&[0]).unwrap_or(value);
+    if value.is_empty() {
+        return Vec::new();
+    }
+    value
+        .split(|&b| b == 0)
+        .map(|arg| String::from_utf8_lossy(arg).into_owned())
+        .collect()
+}
+
+/// Decode the value of a text property, dropping any trailing NUL bytes.
+pub(crate) fn decode_text(value: &[u8]) -> String {
+    let end = value.iter().rposition(|&b| b != 0).map_or(0, |i| i + 1);
+    String::from_utf8_lossy(&value[..end]).into_owned()
+}
+
+/// Decode the value of a STRING property, dropping any trailing NUL bytes.
+/// The ICCCM says that it's Latin-1, but legacy clients often use their
+/// locale's encoding, which these days is usually UTF-8 but may be anything.
+pub(crate) fn decode_string(value: &[u8], encoding: TitleEncoding) -> String {
+    let end = value.iter().rposition(|&b| b != 0).map_or(0, |i| i + 1);
+    let value = &value[..end];
+    let latin1 = || value.iter().map(|&b| char::from(b)).collect();
+    match encoding {
+        TitleEncoding::Auto => std::str::from_utf8(value)
+            .map(str::to_owned)
+            .unwrap_or_else(|_| latin1()),
+        TitleEncoding::Utf8 => String::from_utf8_lossy(value).into_owned(),
+        TitleEncoding::Latin1 => latin1(),
+        TitleEncoding::Named(encoding) => {
+            encoding.decode_without_bom_handling(value).0.into_owned()
+        }
+    }
+}
+
+/// Confirm that WM_CLASS values are split into instance and class correctly.
+#[test]
+fn check_wm_class_parse() {
+    let wm_class = WmClass::try_from(&b"navigator\0Firefox\0"[..]).unwrap();
+    assert_eq!(wm_class.instance, "navigator");
+    assert_eq!(wm_class.class, "Firefox");
+
+    // Some clients leave off the final NUL.
+    let wm_class = WmClass::try_from(&b"xterm\0XTerm"[..]).unwrap();
+    assert_eq!(wm_class.instance, "xterm");
+    assert_eq!(wm_class.class, "XTerm");
+
+    assert!(WmClass::try_from(&b"xterm"[..]).is_err());
+    assert_eq!(decode_text(b"browser\0"), "browser");
+    assert_eq!(decode_text(b""), "");
+}
+
+/// Confirm that the _NET_WM_NAME we advertise is our name followed by the
+/// version, as UTF-8.
+#[test]
+fn check_net_wm_name() {
+    assert_eq!(net_wm_name("0.1.0"), b"OxWM 0.1.0");
+    assert_eq!(net_wm_name("1.0.0-rc.1"), b"OxWM 1.0.0-rc.1");
+    assert_eq!(net_wm_name(""), b"OxWM ");
+}
+
+/// Confirm that STRING titles are decoded according to the configured
+/// encoding.
+#[test]
+fn check_decode_string() {
+    let utf8 = "caf\u{e9}".as_bytes();
+    let latin1 = b"caf\xe9\0";
+    assert_eq!(decode_string(utf8, TitleEncoding::Auto), "caf\u{e9}");
+    assert_eq!(decode_string(latin1, TitleEncoding::Auto), "caf\u{e9}");
+    assert_eq!(decode_string(utf8, TitleEncoding::Utf8), "caf\u{e9}");
+    assert_eq!(decode_string(latin1, TitleEncoding::Utf8), "caf\u{fffd}");
+    assert_eq!(
+        decode_string(utf8, TitleEncoding::Latin1),
+        "caf\u{c3}\u{a9}"
+    );
+    assert_eq!(decode_string(latin1, TitleEncoding::Latin1), "caf\u{e9}");
+    // "Japanese" in Shift_JIS and EUC-JP, and "Moscow" in KOI8-R.
+    let shift_jis = TitleEncoding::Named(encoding_rs::SHIFT_JIS);
+    let euc_jp = TitleEncoding::Named(encoding_rs::EUC_JP);
+    let koi8_r = TitleEncoding::Named(encoding_rs::KOI8_R);
+    assert_eq!(
+        decode_string(b"\x93\xfa\x96\x7b\x8c\xea\0", shift_jis),
+        "\u{65e5}\u{672c}\u{8a9e}"
+    );
+    assert_eq!(
+        decode_string(b"\xc6\xfc\xcb\xdc\xb8\xec", euc_jp),
+        "\u{65e5}\u{672c}\u{8a9e}"
+    );
+    assert_eq!(
+        decode_string(b"\xed\xcf\xd3\xcb\xd7\xc1", koi8_r),
+        "\u{41c}\u{43e}\u{441}\u{43a}\u{432}\u{430}"
+    );
+    // Invalid sequences are replaced, rather than falling back.
+    assert_eq!(decode_string(b"caf\x82", shift_jis), "caf\u{fffd}");
+    for &encoding in &[
+        TitleEncoding::Auto,
+        TitleEncoding::Utf8,
+        TitleEncoding::Latin1,
+        shift_jis,
+        koi8_r,
+    ] {
+        assert_eq!(decode_string(b"xterm\0", encoding), "xterm");
+        assert_eq!(decode_string(b"", encoding), "");
+    }
+}
+
+/// Confirm that _NET_WM_STATE actions are decoded and applied correctly.
+#[test]
+fn check_net_wm_state_action() {
+    let remove = NetWmStateAction::try_from(0).unwrap();
+    let add = NetWmStateAction::try_from(1).unwrap();
+    let toggle = NetWmStateAction::try_from(2).unwrap();
+    assert!(NetWmStateAction::try_from(3).is_err());
+    assert!(!remove.apply(true));
+    assert!(!remove.apply(false));
+    assert!(add.apply(true));
+    assert!(add.apply(false));
+    assert!(toggle.apply(false));
+    assert!(!toggle.apply(true));
+}
+
+/// Confirm that WM_COMMAND values are split into arguments correctly.
+#[test]
+fn check_parse_wm_command() {
+    assert_eq!(
+        parse_wm_command(b"xterm\0-e\0vim\0"),
+        vec!["xterm", "-e", "vim"]
+    );
+    // The final NUL is sometimes left off.
+    assert_eq!(
+        parse_wm_command(b"xterm\0-e\0vim"),
+        vec!["xterm", "-e", "vim"]
+    );
+    // Empty arguments in the middle are preserved.
+    assert_eq!(parse_wm_command(b"echo\0\0x\0"), vec!["echo", "", "x"]);
+    assert!(parse_wm_command(b"").is_empty());
+    assert!(parse_wm_command(b"\0").is_empty());
+}
+
+/// Confirm that windows start iconified only if WM_HINTS asks for it.
+#[test]
+fn check_initial_wm_state() {
+    let mut hints = WmHints::new();
+    assert_eq!(WmStateState::initial(&hints), WmStateState::Normal);
+    hints.initial_state = Some(WmHintsState::Normal);
+    assert_eq!(WmStateState::initial(&hints), WmStateState::Normal);
+    hints.initial_state = Some(WmHintsState::Iconic);
+    assert_eq!(WmStateState::initial(&hints), WmStateState::Iconic);
+}
+
+/// Confirm that the frame extents cover the border on every side.
+#[test]
+fn check_frame_extents() {
+    assert_eq!(frame_extents(0), [0, 0, 0, 0]);
+    assert_eq!(frame_extents(2), [2, 2, 2, 2]);
+}
+
+/// Confirm that locked windows aren't advertised as movable or resizable, but
+/// can still be closed and so on.
+#[test]
+fn check_allowed_actions() {
+    use AllowedAction::*;
+    assert_eq!(
+        allowed_actions(false),
+        vec![Move, Resize, Fullscreen, Minimize, Close]
+    );
+    assert_eq!(allowed_actions(true), vec![Fullscreen, Minimize, Close]);
+}
+
+/// Confirm that opacities map onto the full range of a CARDINAL.
+#[test]
+fn check_window_opacity() {
+    assert_eq!(window_opacity(0.0), 0);
+    assert_eq!(window_opacity(1.0), u32::MAX);
+    assert_eq!(window_opacity(0.5), 0x8000_0000);
+    assert_eq!(window_opacity(-1.0), 0);
+    assert_eq!(window_opacity(2.0), u32::MAX);
+}
+
+/// Confirm that concatenated icons are split up, that a truncated one is
+/// dropped, and that the closest size is picked.
+#[test]
+fn check_parse_net_wm_icon() {
+    let mut data = vec![2, 2, 1, 2, 3, 4, 4, 1, 5, 6, 7, 8];
+    let icons = parse_net_wm_icon(&data);
+    assert_eq!(icons.len(), 2);
+    assert_eq!((icons[0].width, icons[0].height), (2, 2));
+    assert_eq!(icons[0].pixels, vec![1, 2, 3, 4]);
+    assert_eq!((icons[1].width, icons[1].height), (4, 1));
+    assert_eq!(icons[1].pixels, vec![5, 6, 7, 8]);
+
+    data.extend(&[16, 16, 0, 0]);
+    assert_eq!(parse_net_wm_icon(&data), icons);
+    assert!(parse_net_wm_icon(&[]).is_empty());
+
+    assert_eq!(closest_icon(&icons, 2).unwrap().width, 2);
+    assert_eq!(closest_icon(&icons, 48).unwrap().width, 4);
+    assert_eq!(closest_icon(&icons, 3).unwrap().width, 4);
+    assert_eq!(closest_icon(&[], 16), None);
+}
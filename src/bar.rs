@@ -0,0 +1,112 @@
+//! A minimal status line drawn directly on the root window.
+
+use x11rb::connection::Connection;
+use x11rb::protocol::xproto;
+use x11rb::protocol::xproto::ConnectionExt as _;
+
+use crate::Result;
+
+/// Horizontal padding, in pixels, to the left of the status text.
+const PADDING: i16 = 4;
+
+/// The longest string that fits in a single `PolyText8` text item.
+const MAX_TEXT_LEN: usize = 254;
+
+/// Resources needed to draw the status line.
+#[derive(Clone, Debug)]
+pub(crate) struct Bar {
+    /// The graphics context that we draw with.
+    gc: xproto::Gcontext,
+    /// The ascent of the font, used to find the text baseline.
+    ascent: i16,
+    /// The height of the bar.
+    height: u16,
+}
+
+impl Bar {
+    /// Set up to draw on the given root window with the named core font. If
+    /// the font can't be loaded, we log a warning and return `None`, so the
+    /// window manager can carry on without a bar.
+    pub(crate) fn new<Conn>(
+        conn: &Conn,
+        screen: &xproto::Screen,
+        font_name: &str,
+    ) -> Result<Option<Bar>>
+    where
+        Conn: Connection,
+    {
+        let font = conn.generate_id()?;
+        if let Err(err) = conn.open_font(font, font_name.as_bytes())?.check() {
+            log::warn!(
+                "Unable to open font `{}'; disabling bar: {:?}",
+                font_name,
+                err
+            );
+            return Ok(None);
+        }
+        let info = conn.query_font(font)?.reply()?;
+        let gc = conn.generate_id()?;
+        conn.create_gc(
+            gc,
+            screen.root,
+            &xproto::CreateGCAux::new()
+                .foreground(screen.white_pixel)
+                .background(screen.black_pixel)
+                .font(font),
+        )?
+        .check()?;
+        conn.close_font(font)?.check()?;
+        Ok(Some(Bar {
+            gc,
+            ascent: info.font_ascent,
+            height: (info.font_ascent + info.font_descent) as u16,
+        }))
+    }
+
+    /// Redraw the status line with the given text. This happens on every
+    /// focus change, so we don't wait to hear whether the requests succeeded.
+    pub(crate) fn draw<Conn>(&self, conn: &Conn, screen: &xproto::Screen, text: &[u8]) -> Result<()>
+    where
+        Conn: Connection,
+    {
+        conn.clear_area(
+            false,
+            screen.root,
+            0,
+            0,
+            screen.width_in_pixels,
+            self.height,
+        )?;
+        if text.is_empty() {
+            return Ok(());
+        }
+        // A single text item: length, horizontal delta, then the string.
+        let mut item = vec![text.len() as u8, 0];
+        item.extend_from_slice(text);
+        conn.poly_text8(screen.root, self.gc, PADDING, self.ascent, &item)?;
+        Ok(())
+    }
+}
+
+/// Format the status line for the focused window's title. The result is
+/// encoded in Latin-1, since that's what core X fonts expect; characters that
+/// can't be represented are replaced with `?`.
+pub(crate) fn status_text(title: Option<&str>) -> Vec<u8> {
+    title
+        .unwrap_or("")
+        .chars()
+        .map(|c| if (c as u32) < 256 { c as u8 } else { b'?' })
+        .take(MAX_TEXT_LEN)
+        .collect()
+}
+
+/// Confirm that the status line is encoded and truncated properly.
+#[test]
+fn check_status_text() {
+    assert_eq!(status_text(None), b"");
+    assert_eq!(status_text(Some("xterm")), b"xterm");
+    assert_eq!(status_text(Some("caf\u{e9}")), b"caf\xe9");
+    assert_eq!(status_text(Some("\u{65e5}\u{672c}")), b"??");
+    let long = "x".repeat(1000);
+    assert_eq!(status_text(Some(&long)).len(), MAX_TEXT_LEN);
+}
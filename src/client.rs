@@ -1,11 +1,14 @@
 //! Local data about the state of the X server.
 
+use std::collections::HashMap;
+
 use x11rb::connection::Connection;
 use x11rb::properties::WmSizeHints;
 use x11rb::protocol::xproto;
 use x11rb::protocol::xproto::ConnectionExt as _;
 
 use crate::atom::*;
+use crate::config::TitleEncoding;
 use crate::Result;
 
 /// Local data about a top-level window.
@@ -16,12 +19,116 @@ pub(crate) struct Client {
     /// The client's state. We don't keep track of this for windows with
     /// override-redirect set.
     pub(crate) state: Option<ClientState>,
+    /// The geometry of a window with override-redirect set, if we're tracking
+    /// it. (The geometry of a managed window lives in its state.)
+    pub(crate) override_redirect_geometry: Option<Geometry>,
+    /// Whether a window with override-redirect set is mapped. (Whether a
+    /// managed window is viewable lives in its state.)
+    pub(crate) override_redirect_viewable: bool,
 }
 
 impl Client {
     /// Indicates whether a window has its override-redirect flag set.
     pub(crate) fn override_redirect(&self) -> bool {
-        self.state.is_some()
+        self.state.is_none()
+    }
+
+    /// Indicates whether the window may get the input focus. We don't stop
+    /// override-redirect windows from getting it.
+    pub(crate) fn focusable(&self) -> bool {
+        match self.state {
+            Some(ref st) => st.focusable,
+            None => true,
+        }
+    }
+
+    /// Indicates whether the window is a desktop.
+    pub(crate) fn desktop(&self) -> bool {
+        match self.state {
+            Some(ref st) => st.desktop,
+            None => false,
+        }
+    }
+
+    /// Indicates whether the window is currently mapped.
+    pub(crate) fn is_viewable(&self) -> bool {
+        match self.state {
+            Some(ref st) => st.is_viewable,
+            None => self.override_redirect_viewable,
+        }
+    }
+
+    /// Record whether the window is currently mapped.
+    pub(crate) fn set_viewable(&mut self, is_viewable: bool) {
+        match self.state {
+            Some(ref mut st) => st.is_viewable = is_viewable,
+            None => self.override_redirect_viewable = is_viewable,
+        }
+    }
+
+    /// The window's geometry, if we know it. We always know it for managed
+    /// windows; for override-redirect windows, only if we're tracking it.
+    pub(crate) fn geometry(&self) -> Option<Geometry> {
+        match self.state {
+            Some(ref st) => Some(Geometry {
+                x: st.x,
+                y: st.y,
+                width: st.width,
+                height: st.height,
+                border_width: st.border_width,
+            }),
+            None => self.override_redirect_geometry,
+        }
+    }
+
+    /// Record a change to the window's geometry. For override-redirect
+    /// windows, this is only recorded if `track_override_redirect` is set.
+    pub(crate) fn set_geometry(&mut self, geometry: Geometry, track_override_redirect: bool) {
+        match self.state {
+            Some(ref mut st) => {
+                st.x = geometry.x;
+                st.y = geometry.y;
+                st.width = geometry.width;
+                st.height = geometry.height;
+                st.border_width = geometry.border_width;
+            }
+            None if track_override_redirect => self.override_redirect_geometry = Some(geometry),
+            None => (),
+        }
+    }
+}
+
+/// The position and size of a window.
+#[derive(PartialEq, Eq, Clone, Copy, Hash, Debug)]
+pub(crate) struct Geometry {
+    /// Horizontal position.
+    pub(crate) x: i16,
+    /// Vertical position.
+    pub(crate) y: i16,
+    /// Horizontal extent.
+    pub(crate) width: u16,
+    /// Vertical extent.
+    pub(crate) height: u16,
+    /// Width of the window's border.
+    pub(crate) border_width: u16,
+}
+
+impl Geometry {
+    /// Indicates whether two windows overlap, borders included.
+    pub(crate) fn overlaps(&self, other: &Geometry) -> bool {
+        let extent = |g: &Geometry| {
+            let border = 2 * i32::from(g.border_width);
+            let (x, y) = (i32::from(g.x), i32::from(g.y));
+            (
+                x,
+                y,
+                x + i32::from(g.width) + border,
+                y + i32::from(g.height) + border,
+            )
+        };
+        let (left, top, right, bottom) = extent(self);
+        let (other_left, other_top, other_right, other_bottom) = extent(other);
+        left < other_right && other_left < right && top < other_bottom && other_top < bottom
     }
 }
 
@@ -36,6 +143,27 @@ pub(crate) struct ClientState {
     pub(crate) width: u16,
     /// Vertical extent.
     pub(crate) height: u16,
+    /// Width of the window's border.
+    pub(crate) border_width: u16,
+    /// The border width to restore, if the border is currently hidden.
+    pub(crate) saved_border_width: Option<u16>,
+    /// The index of the size preset that `cycle_size` last applied.
+    pub(crate) size_preset: Option<usize>,
+    /// Whether the window is fullscreen.
+    pub(crate) fullscreen: bool,
+    /// The geometry to restore when the window leaves fullscreen.
+    pub(crate) saved_geometry: Option<(i16, i16, u16, u16)>,
+    /// The client's _GTK_FRAME_EXTENTS (left, right, top, bottom).
+    pub(crate) gtk_frame_extents: [u32; 4],
+    /// Whether the window is locked in place, so that it can't be moved or
+    /// resized.
+    pub(crate) locked: bool,
+    /// The window that this one is transient for, according to its
+    /// WM_TRANSIENT_FOR.
+    pub(crate) transient_for: Option<xproto::Window>,
+    /// Whether we've unmapped the window to iconify it, and are waiting for
+    /// the UnmapNotify.
+    pub(crate) iconifying: bool,
     /// Whether the window is viewable.
     pub(crate) is_viewable: bool,
     /// The client's WM_PROTOCOLS.
@@ -44,6 +172,122 @@ pub(crate) struct ClientState {
     pub(crate) wm_state: Option<WmState>,
     /// The client's WM_NORMAL_HINTS.
     pub(crate) wm_normal_hints: WmSizeHints,
+    /// The client's WM_CLASS.
+    pub(crate) wm_class: Option<WmClass>,
+    /// The client's WM_WINDOW_ROLE.
+    pub(crate) wm_window_role: Option<String>,
+    /// Whether the client is demanding the user's attention.
+    pub(crate) urgent: bool,
+    /// Whether the client is a modal dialog.
+    pub(crate) modal: bool,
+    /// Whether the window should ever get the input focus. Docks and desktops
+    /// shouldn't.
+    pub(crate) focusable: bool,
+    /// Whether the window is a desktop, which stays underneath every other
+    /// window.
+    pub(crate) desktop: bool,
+    /// The client's title.
+    pub(crate) title: Option<String>,
+    /// The client's WM_COMMAND, for session management.
+    #[allow(dead_code)]
+    pub(crate) wm_command: Option<Vec<String>>,
+    /// The client's SM_CLIENT_ID, for session management.
+    #[allow(dead_code)]
+    pub(crate) sm_client_id: Option<String>,
+    /// The images in the client's _NET_WM_ICON.
+    pub(crate) icons: Vec<NetWmIcon>,
+    /// How much of the window is covered by other windows, as of the last
+    /// VisibilityNotify.
+    #[allow(dead_code)]
+    pub(crate) visibility: Visibility,
+}
+
+impl ClientState {
+    /// The client's icon that's closest to `size` pixels across, for a bar
+    /// or window switcher to show, once there's an interface to ask for it.
+    #[allow(dead_code)]
+    pub(crate) fn icon(&self, size: u32) -> Option<&NetWmIcon> {
+        closest_icon(&self.icons, size)
+    }
+
+    /// Hide the window's border, remembering its width so that it can be
+    /// restored. Returns the new border width.
+    pub(crate) fn hide_border(&mut self) -> u16 {
+        if self.saved_border_width.is_none() {
+            self.saved_border_width = Some(self.border_width);
+        }
+        0
+    }
+
+    /// Forget the saved border width, returning it if the border was hidden.
+    pub(crate) fn restore_border(&mut self) -> Option<u16> {
+        self.saved_border_width.take()
+    }
+
+    /// Change the width that the window's border should have. If the border
+    /// is hidden, the new width is saved for when it's restored, and `None`
+    /// is returned; otherwise, the new width is returned.
+    pub(crate) fn restyle_border(&mut self, width: u16) -> Option<u16> {
+        match self.saved_border_width {
+            Some(ref mut saved) => {
+                *saved = width;
+                None
+            }
+            None => Some(width),
+        }
+    }
+
+    /// Mark the window as fullscreen, saving its geometry and hiding its
+    /// border. Returns false if it was already fullscreen.
+    pub(crate) fn enter_fullscreen(&mut self) -> bool {
+        if self.fullscreen {
+            return false;
+        }
+        self.fullscreen = true;
+        self.saved_geometry = Some((self.x, self.y, self.width, self.height));
+        self.hide_border();
+        true
+    }
+
+    /// Mark the window as no longer fullscreen, returning the geometry to
+    /// restore. Returns `None` if it wasn't fullscreen. The border is left for
+    /// the caller to restore, since it may be hidden for other reasons too.
+    pub(crate) fn leave_fullscreen(&mut self) -> Option<(i16, i16, u16, u16)> {
+        if !self.fullscreen {
+            return None;
+        }
+        self.fullscreen = false;
+        self.saved_geometry.take()
+    }
+}
+
+/// How much of a window is covered by other windows.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Visibility {
+    Unobscured,
+    PartiallyObscured,
+    FullyObscured,
+}
+
+impl From<xproto::Visibility> for Visibility {
+    fn from(visibility: xproto::Visibility) -> Self {
+        match visibility {
+            xproto::Visibility::UNOBSCURED => Visibility::Unobscured,
+            xproto::Visibility::PARTIALLY_OBSCURED => Visibility::PartiallyObscured,
+            _ => Visibility::FullyObscured,
+        }
+    }
+}
+
+/// What to do with the focus when a window is unmapped or destroyed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Refocus {
+    /// The window didn't have the focus, so leave it alone.
+    Unaffected,
+    /// Focus this window instead.
+    Window(xproto::Window),
+    /// There's nothing left to focus.
+    Nothing,
 }
 
 /// Local data about the state of all top-level windows. This includes windows
@@ -72,6 +316,9 @@ pub(crate) struct Clients {
     stack: Vec<Client>,
     /// The currently-focused window, if any (the root window doesn't count).
     focus: Option<xproto::Window>,
+    /// Managed windows that haven't been withdrawn, in the order in which
+    /// they were first mapped.
+    mapped: Vec<xproto::Window>,
 }
 
 impl Clients {
@@ -82,7 +329,6 @@ impl Clients {
     }
 
     /// Get the currently-focused client.
-    #[allow(dead_code)]
     pub(crate) fn get_focus_mut(&mut self) -> Option<&mut Client> {
         let window = self.focus?;
         Some(self.get_mut(window))
@@ -125,6 +371,217 @@ impl Clients {
         self.stack.iter_mut()
     }
 
+    /// Find the nearest managed, viewable client above the given one in the
+    /// stack (or below it, if `above` is false), wrapping around.
+    pub(crate) fn neighbor(&self, window: xproto::Window, above: bool) -> Option<xproto::Window> {
+        self.find_from(window, above, |_| true)
+    }
+
+    /// Find the nearest managed, viewable client above the given one in the
+    /// stack that has the same WM_CLASS class, wrapping around. Raising each
+    /// result in turn cycles through all of them.
+    pub(crate) fn next_same_class(&self, window: xproto::Window) -> Option<xproto::Window> {
+        let class = match self.get(window).state {
+            Some(ref st) => st.wm_class.as_ref()?.class.clone(),
+            None => return None,
+        };
+        self.find_from(window, true, |st| match st.wm_class {
+            Some(ref wm_class) => wm_class.class == class,
+            None => false,
+        })
+    }
+
+    /// Indicates whether the top-most viewable window has override-redirect
+    /// set and covers `window`. That's usually a menu or other popup, which
+    /// has probably grabbed the pointer. If we don't know where either window
+    /// is, the popup is assumed to cover it.
+    pub(crate) fn popup_over(&self, window: xproto::Window) -> bool {
+        let popup = match self.stack.iter().rev().find(|client| client.is_viewable()) {
+            Some(client) if client.override_redirect() => client,
+            _ => return false,
+        };
+        let under = self
+            .stack
+            .iter()
+            .find(|client| client.window == window)
+            .and_then(Client::geometry);
+        match (popup.geometry(), under) {
+            (Some(popup), Some(under)) => popup.overlaps(&under),
+            _ => true,
+        }
+    }
+
+    /// Find the nearest managed, viewable, focusable client satisfying `pred`,
+    /// starting from the given one and moving up (or down) the stack, wrapping
+    /// around.
+    fn find_from<P>(&self, window: xproto::Window, above: bool, pred: P) -> Option<xproto::Window>
+    where
+        P: Fn(&ClientState) -> bool,
+    {
+        let (i, _) = self.get_with_index(window);
+        let n = self.stack.len();
+        (1..n)
+            .map(|k| if above { (i + k) % n } else { (i + n - k) % n })
+            .map(|j| &self.stack[j])
+            .find(|client| match client.state {
+                Some(ref st) => st.is_viewable && st.focusable && pred(st),
+                None => false,
+            })
+            .map(|client| client.window)
+    }
+
+    /// Find the top-most managed, viewable, focusable client other than
+    /// `except`, to hand the focus to when `except` goes away.
+    pub(crate) fn focus_fallback(&self, except: xproto::Window) -> Option<xproto::Window> {
+        self.stack
+            .iter()
+            .rev()
+            .filter(|client| client.window != except)
+            .find(|client| match client.state {
+                Some(ref st) => st.is_viewable && st.focusable,
+                None => false,
+            })
+            .map(|client| client.window)
+    }
+
+    /// Decide what to focus now that `window` is going away. It counts as
+    /// having had the focus if it still has it, or if nothing does and it was
+    /// the last window to get it: the FocusOut for an unmapped window usually
+    /// arrives before we hear about the unmap.
+    pub(crate) fn refocus_after(&self, window: xproto::Window, history: &FocusHistory) -> Refocus {
+        let had_focus = match self.focus {
+            Some(focus) => focus == window,
+            None => history.latest() == Some(window),
+        };
+        if !had_focus {
+            return Refocus::Unaffected;
+        }
+        match self.focus_fallback(window) {
+            Some(next) => Refocus::Window(next),
+            None => Refocus::Nothing,
+        }
+    }
+
+    /// Find the window to restack to rotate the managed, viewable windows by
+    /// one place: the bottom one, to be raised, if `forward` is set, and
+    /// otherwise the top one, to be lowered. Returns `None` if there's nothing
+    /// to rotate.
+    pub(crate) fn rotation_target(&self, forward: bool) -> Option<xproto::Window> {
+        let mut viewable = self.stack.iter().filter(|client| match client.state {
+            Some(ref st) => st.is_viewable,
+            None => false,
+        });
+        let first = viewable.next()?;
+        let last = viewable.next_back()?;
+        Some(if forward { first.window } else { last.window })
+    }
+
+    /// Find the managed, viewable clients to hide to leave `window` on its own
+    /// (e.g., while it's zoomed): everything but `window` itself, its
+    /// transients, and windows that never take the focus, like docks.
+    pub(crate) fn others_to_hide(&self, window: xproto::Window) -> Vec<xproto::Window> {
+        self.stack
+            .iter()
+            .filter(|client| client.window != window)
+            .filter(|client| match client.state {
+                Some(ref st) => st.is_viewable && st.focusable && st.transient_for != Some(window),
+                None => false,
+            })
+            .map(|client| client.window)
+            .collect()
+    }
+
+    /// Find the top-most desktop window other than `window`, which windows
+    /// being lowered should go just above, rather than underneath it.
+    pub(crate) fn top_desktop(&self, window: xproto::Window) -> Option<xproto::Window> {
+        self.stack
+            .iter()
+            .rev()
+            .filter(|client| client.window != window)
+            .find(|client| client.desktop())
+            .map(|client| client.window)
+    }
+
+    /// Like `rotation_target`, but only rotating the managed, viewable windows
+    /// that cover the point `(x, y)`, like X's CirculateWindow restricted to a
+    /// pile of overlapping windows. Desktops are left out.
+    pub(crate) fn circulation_target(
+        &self,
+        forward: bool,
+        x: i16,
+        y: i16,
+    ) -> Option<xproto::Window> {
+        let (x, y) = (i32::from(x), i32::from(y));
+        let mut pile = self.stack.iter().filter(|client| match client.state {
+            Some(ref st) if st.is_viewable && !st.desktop => {
+                let border = 2 * i32::from(st.border_width);
+                let (left, top) = (i32::from(st.x), i32::from(st.y));
+                let right = left + i32::from(st.width) + border;
+                let bottom = top + i32::from(st.height) + border;
+                (left..right).contains(&x) && (top..bottom).contains(&y)
+            }
+            _ => false,
+        });
+        let first = pile.next()?;
+        let last = pile.next_back()?;
+        Some(if forward { first.window } else { last.window })
+    }
+
+    /// Find the managed clients that are transient for the given window.
+    pub(crate) fn transients_for(&self, parent: xproto::Window) -> Vec<xproto::Window> {
+        self.stack
+            .iter()
+            .filter(|client| match client.state {
+                Some(ref st) => st.transient_for == Some(parent),
+                None => false,
+            })
+            .map(|client| client.window)
+            .collect()
+    }
+
+    /// Find the managed, viewable, focusable client that the given one is
+    /// transient for.
+    pub(crate) fn transient_parent(&self, window: xproto::Window) -> Option<xproto::Window> {
+        let parent = self.get(window).state.as_ref()?.transient_for?;
+        if !self.has_client(parent) {
+            return None;
+        }
+        match self.get(parent).state {
+            Some(ref st) if st.is_viewable && st.focusable => Some(parent),
+            _ => None,
+        }
+    }
+
+    /// Find the topmost viewable, focusable client that is transient for the
+    /// given one.
+    pub(crate) fn transient_child(&self, window: xproto::Window) -> Option<xproto::Window> {
+        self.stack
+            .iter()
+            .rev()
+            .find(|client| match client.state {
+                Some(ref st) => st.transient_for == Some(window) && st.is_viewable && st.focusable,
+                None => false,
+            })
+            .map(|client| client.window)
+    }
+
+    /// Exchange the positions and sizes of two managed clients.
+    pub(crate) fn swap(&mut self, a: xproto::Window, b: xproto::Window) {
+        let geometry = |client: &Client| {
+            let st = client.state.as_ref().unwrap();
+            (st.x, st.y, st.width, st.height)
+        };
+        let a_geometry = geometry(self.get(a));
+        let b_geometry = geometry(self.get(b));
+        for &(window, (x, y, width, height)) in &[(a, b_geometry), (b, a_geometry)] {
+            let st = self.get_mut(window).state.as_mut().unwrap();
+            st.x = x;
+            st.y = y;
+            st.width = width;
+            st.height = height;
+        }
+    }
+
     /// Move a client to just above another one.
     pub(crate) fn move_to_above(&mut self, window: xproto::Window, sibling: xproto::Window) {
         let (i, _) = self.get_with_index(window);
@@ -157,8 +614,17 @@ impl Clients {
         self.stack.push(client)
     }
 
-    /// Initialize a new client stack by issuing queries to the server.
-    pub(crate) fn new<Conn>(conn: &Conn, screen: usize, atoms: &Atoms) -> Result<Self>
+    /// Initialize a new client stack by issuing queries to the server. The
+    /// geometry of override-redirect windows is only recorded if
+    /// `track_override_redirect` is set, and titles are decoded according to
+    /// `title_encoding`.
+    pub(crate) fn new<Conn>(
+        conn: &Conn,
+        screen: usize,
+        atoms: &Atoms,
+        track_override_redirect: bool,
+        title_encoding: TitleEncoding,
+    ) -> Result<Self>
     where
         Conn: Connection,
     {
@@ -170,7 +636,20 @@ impl Clients {
         for window in children {
             let attrs = conn.get_window_attributes(window)?.reply()?;
             let override_redirect = attrs.override_redirect;
+            let override_redirect_viewable =
+                override_redirect && attrs.map_state == xproto::MapState::VIEWABLE;
+            let mut override_redirect_geometry = None;
             let state = if override_redirect {
+                if track_override_redirect {
+                    let geom = conn.get_geometry(window)?.reply()?;
+                    override_redirect_geometry = Some(Geometry {
+                        x: geom.x,
+                        y: geom.y,
+                        width: geom.width,
+                        height: geom.height,
+                        border_width: geom.border_width,
+                    });
+                }
                 None
             } else {
                 let geom = conn.get_geometry(window)?.reply()?;
@@ -178,18 +657,56 @@ impl Clients {
                 let wm_protocols = atoms.get_wm_protocols(conn, window)?;
                 let wm_state = atoms.get_wm_state(conn, window)?;
                 let wm_normal_hints = atoms.get_wm_normal_hints(conn, window)?;
+                let wm_class = atoms.get_wm_class(conn, window)?;
+                let wm_window_role = atoms.get_wm_window_role(conn, window)?;
+                let net_wm_state = atoms.get_net_wm_state(conn, window)?;
+                let urgent = net_wm_state.contains(&atoms.net_wm_state_demands_attention);
+                let modal = net_wm_state.contains(&atoms.net_wm_state_modal);
+                let focusable = atoms.get_focusable(conn, window)?;
+                let desktop = atoms.get_desktop(conn, window)?;
+                let gtk_frame_extents = atoms.get_gtk_frame_extents(conn, window)?;
+                let transient_for = atoms.get_wm_transient_for(conn, window)?;
+                let title = atoms.get_title(conn, window, title_encoding)?;
+                let wm_command = atoms.get_wm_command(conn, window)?;
+                let sm_client_id = atoms.get_sm_client_id(conn, window)?;
+                let icons = atoms.get_net_wm_icon(conn, window)?;
                 Some(ClientState {
                     x: geom.x,
                     y: geom.y,
                     width: geom.width,
                     height: geom.height,
+                    border_width: geom.border_width,
+                    saved_border_width: None,
+                    size_preset: None,
+                    fullscreen: false,
+                    saved_geometry: None,
+                    gtk_frame_extents,
+                    locked: false,
+                    transient_for,
+                    iconifying: false,
                     is_viewable,
                     wm_protocols,
                     wm_state,
                     wm_normal_hints,
+                    wm_class,
+                    wm_window_role,
+                    urgent,
+                    modal,
+                    focusable,
+                    desktop,
+                    title,
+                    wm_command,
+                    sm_client_id,
+                    icons,
+                    visibility: Visibility::Unobscured,
                 })
             };
-            stack.push(Client { window, state })
+            stack.push(Client {
+                window,
+                state,
+                override_redirect_geometry,
+                override_redirect_viewable,
+            })
         }
         let focus = conn.get_input_focus()?.reply()?.focus;
         let focus = if stack.iter().find(|client| client.window == focus).is_none() {
@@ -197,7 +714,24 @@ impl Clients {
         } else {
             Some(focus)
         };
-        Ok(Clients { stack, focus })
+        // We can't know what order the extant windows were mapped in, so
+        // stacking order will have to do.
+        let mapped = stack
+            .iter()
+            .filter(|client| match client.state {
+                Some(ref st) => {
+                    st.is_viewable
+                        || st.wm_state.map(|wm_state| wm_state.state) == Some(WmStateState::Iconic)
+                }
+                None => false,
+            })
+            .map(|client| client.window)
+            .collect();
+        Ok(Clients {
+            stack,
+            focus,
+            mapped,
+        })
     }
 
     /// Push a client on top of the stack.
@@ -209,11 +743,46 @@ impl Clients {
     /// Remove a client from the stack.
     pub(crate) fn remove(&mut self, window: xproto::Window) {
         self.stack.remove(self.get_with_index(window).0);
+        self.withdrawn(window);
         if self.focus == Some(window) {
             self.focus = None;
         }
     }
 
+    /// Note that a managed client has been mapped. Returns whether it's new
+    /// to the client list.
+    pub(crate) fn mapped(&mut self, window: xproto::Window) -> bool {
+        if self.get(window).override_redirect() || self.mapped.contains(&window) {
+            return false;
+        }
+        self.mapped.push(window);
+        true
+    }
+
+    /// Note that a client has been withdrawn. Returns whether it was on the
+    /// client list.
+    pub(crate) fn withdrawn(&mut self, window: xproto::Window) -> bool {
+        let len = self.mapped.len();
+        self.mapped.retain(|&w| w != window);
+        self.mapped.len() != len
+    }
+
+    /// The managed windows that haven't been withdrawn, in the order in which
+    /// they were first mapped, for _NET_CLIENT_LIST.
+    pub(crate) fn client_list(&self) -> &[xproto::Window] {
+        &self.mapped
+    }
+
+    /// The same windows as `client_list`, but from bottom to top of the
+    /// stack, for _NET_CLIENT_LIST_STACKING.
+    pub(crate) fn client_list_stacking(&self) -> Vec<xproto::Window> {
+        self.stack
+            .iter()
+            .map(|client| client.window)
+            .filter(|window| self.mapped.contains(window))
+            .collect()
+    }
+
     /// Get the client that is on the top of the stack.
     pub(crate) fn top(&self) -> &Client {
         self.stack.last().unwrap()
@@ -225,6 +794,16 @@ impl Clients {
         self.stack.last_mut().unwrap()
     }
 
+    /// Record a client's visibility from a VisibilityNotify event.
+    pub(crate) fn visibility_notify(&mut self, ev: &xproto::VisibilityNotifyEvent) {
+        if !self.has_client(ev.window) {
+            return;
+        }
+        if let Some(ref mut st) = self.get_mut(ev.window).state {
+            st.visibility = ev.state.into();
+        }
+    }
+
     // Private methods
 
     /// Get the `Client` that corresponds to a given window, along with its
@@ -246,74 +825,310 @@ impl Clients {
     }
 }
 
-/// Issue was encountered where `Clients` could retain the ID of a closed window in
-/// `Clients.focus`, despite having removed the corresponding window from the stack
-/// of managed windows. This caused an "unwrap None" error on removing the next window.
-/// Regression test to verify that `Clients.remove` properly updates `Clients.focus` when
-/// the focused window is removed.
-#[test]
-fn can_remove_focused_window() {
-    let mut clients = Clients {
-        stack: vec![],
-        focus: None,
-    };
+/// The windows that have most recently had the focus, used for switching back
+/// to them. While cycling through the history (e.g., with repeated presses of an
+/// alt-tab binding), the order is frozen until the selection is committed.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct FocusHistory {
+    /// Windows, from most to least recently focused.
+    windows: Vec<xproto::Window>,
+    /// While cycling, the index of the currently-selected window.
+    cursor: Option<usize>,
+}
 
-    //Setup dummy clients in the absence of an X11 server
-    clients.push(Client {
-        window: 100,
-        state: Some(ClientState {
-            x: 1,
-            y: 1,
-            width: 10,
-            height: 10,
-            is_viewable: true,
-            wm_protocols: WmProtocols::new(),
-            wm_state: None,
-            wm_normal_hints: WmSizeHints::new(),
-        }),
-    });
+impl FocusHistory {
+    /// Create an empty focus history.
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
 
-    clients.push(Client {
-        window: 200,
-        state: Some(ClientState {
-            x: 1,
-            y: 1,
-            width: 10,
-            height: 10,
-            is_viewable: true,
-            wm_protocols: WmProtocols::new(),
-            wm_state: None,
-            wm_normal_hints: WmSizeHints::new(),
-        }),
-    });
+    /// Record that a window has received the focus. This is ignored while
+    /// cycling, since cycling focuses windows without committing to them.
+    pub(crate) fn focused(&mut self, window: xproto::Window) {
+        if self.cursor.is_some() {
+            return;
+        }
+        self.windows.retain(|&w| w != window);
+        self.windows.insert(0, window);
+    }
 
-    clients.push(Client {
-        window: 250,
-        state: Some(ClientState {
-            x: 1,
-            y: 1,
-            width: 10,
-            height: 10,
-            is_viewable: false,
-            wm_protocols: WmProtocols::new(),
-            wm_state: None,
-            wm_normal_hints: WmSizeHints::new(),
-        }),
-    });
+    /// The most recently focused window, if any.
+    pub(crate) fn latest(&self) -> Option<xproto::Window> {
+        self.windows.first().copied()
+    }
+
+    /// Forget about a window; e.g., because it has been destroyed.
+    pub(crate) fn remove(&mut self, window: xproto::Window) {
+        let i = match self.windows.iter().position(|&w| w == window) {
+            Some(i) => i,
+            None => return,
+        };
+        self.windows.remove(i);
+        if let Some(cursor) = self.cursor {
+            if self.windows.is_empty() {
+                self.cursor = None;
+            } else if i <= cursor && cursor > 0 {
+                self.cursor = Some(cursor - 1);
+            }
+        }
+    }
+
+    /// Step back to the next-least-recently focused window, wrapping around to
+    /// the most recent one. Returns the newly-selected window, or `None` if
+    /// there's nothing to switch to.
+    pub(crate) fn cycle(&mut self) -> Option<xproto::Window> {
+        if self.windows.len() < 2 {
+            return None;
+        }
+        let next = self
+            .cursor
+            .map_or(1, |cursor| (cursor + 1) % self.windows.len());
+        self.cursor = Some(next);
+        Some(self.windows[next])
+    }
+
+    /// Stop cycling, making the selected window the most recent one.
+    pub(crate) fn commit(&mut self) {
+        if let Some(cursor) = self.cursor.take() {
+            let window = self.windows.remove(cursor);
+            self.windows.insert(0, window);
+        }
+    }
+
+    /// Stop cycling without committing, leaving the order as it was.
+    pub(crate) fn cancel(&mut self) {
+        self.cursor = None;
+    }
 
-    clients.push(Client {
-        window: 300,
+    /// Indicates whether we're in the middle of cycling.
+    pub(crate) fn is_cycling(&self) -> bool {
+        self.cursor.is_some()
+    }
+}
+
+/// What the next key press should be used for, after a `mark_window` or
+/// `goto_mark` binding.
+#[derive(PartialEq, Eq, Clone, Copy, Hash, Debug)]
+pub(crate) enum PendingMark {
+    /// Label the given window with the mark.
+    Set(xproto::Window),
+    /// Jump to the window with the mark.
+    Goto,
+}
+
+/// Windows labelled with single-character marks, like marks in vim.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Marks {
+    /// The marked windows. A window can have more than one mark.
+    marks: HashMap<char, xproto::Window>,
+    /// Whether we're waiting for a key press to finish setting or jumping to a
+    /// mark.
+    pending: Option<PendingMark>,
+}
+
+impl Marks {
+    /// Create an empty set of marks.
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start waiting for the key press that names a mark.
+    pub(crate) fn start(&mut self, pending: PendingMark) {
+        self.pending = Some(pending);
+    }
+
+    /// Stop waiting for a key press, returning what it would have been for.
+    pub(crate) fn take_pending(&mut self) -> Option<PendingMark> {
+        self.pending.take()
+    }
+
+    /// Indicates whether we're waiting for a key press that names a mark.
+    pub(crate) fn is_pending(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    /// Label a window with a mark, taking the mark from any other window.
+    pub(crate) fn set(&mut self, mark: char, window: xproto::Window) {
+        self.marks.insert(mark, window);
+    }
+
+    /// Find the window with a mark.
+    pub(crate) fn get(&self, mark: char) -> Option<xproto::Window> {
+        self.marks.get(&mark).copied()
+    }
+
+    /// Forget a window's marks; e.g., because it has been destroyed.
+    pub(crate) fn remove(&mut self, window: xproto::Window) {
+        self.marks.retain(|_, &mut w| w != window);
+        if self.pending == Some(PendingMark::Set(window)) {
+            self.pending = None;
+        }
+    }
+}
+
+/// An ad-hoc group of windows that `group_cycle` moves the focus around.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Group {
+    /// The group's members, in the order they were added.
+    members: Vec<xproto::Window>,
+}
+
+impl Group {
+    /// Create an empty group.
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a window to the group, unless it's already a member.
+    pub(crate) fn add(&mut self, window: xproto::Window) {
+        if !self.members.contains(&window) {
+            self.members.push(window);
+        }
+    }
+
+    /// Remove every window from the group.
+    pub(crate) fn clear(&mut self) {
+        self.members.clear();
+    }
+
+    /// Remove a window from the group; e.g., because it has been destroyed.
+    pub(crate) fn remove(&mut self, window: xproto::Window) {
+        self.members.retain(|&w| w != window);
+    }
+
+    /// Find the member after `current` for which `pred` holds, wrapping
+    /// around. If `current` isn't a member, start from the first member.
+    pub(crate) fn next<P>(&self, current: Option<xproto::Window>, pred: P) -> Option<xproto::Window>
+    where
+        P: Fn(xproto::Window) -> bool,
+    {
+        let start = current
+            .and_then(|current| self.members.iter().position(|&w| w == current))
+            .map_or(0, |i| i + 1);
+        let len = self.members.len();
+        (0..len)
+            .map(|i| self.members[(start + i) % len])
+            .find(|&w| Some(w) != current && pred(w))
+    }
+}
+
+/// A window designated with `set_scratch`, which `toggle_scratch_focus` flips
+/// the focus to and back from.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Scratch {
+    /// The scratch window.
+    window: Option<xproto::Window>,
+    /// The window that had the focus before we flipped to the scratch window.
+    previous: Option<xproto::Window>,
+}
+
+impl Scratch {
+    /// Create a scratch with no window designated.
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// Designate the scratch window.
+    pub(crate) fn set(&mut self, window: xproto::Window) {
+        self.window = Some(window);
+        self.previous = None;
+    }
+
+    /// Decide which window to flip to, given the focused window: the scratch
+    /// window, or if that's focused already, the window that was focused
+    /// before it.
+    pub(crate) fn toggle(&mut self, focus: Option<xproto::Window>) -> Option<xproto::Window> {
+        let window = self.window?;
+        if focus == Some(window) {
+            self.previous.take()
+        } else {
+            self.previous = focus;
+            Some(window)
+        }
+    }
+
+    /// Forget a window; e.g., because it has been destroyed.
+    pub(crate) fn remove(&mut self, window: xproto::Window) {
+        if self.window == Some(window) {
+            self.window = None;
+            self.previous = None;
+        }
+        if self.previous == Some(window) {
+            self.previous = None;
+        }
+    }
+}
+
+/// Create a dummy client for testing in the absence of an X11 server.
+#[cfg(test)]
+pub(crate) fn dummy_client(window: xproto::Window, is_viewable: bool) -> Client {
+    Client {
+        override_redirect_geometry: None,
+        override_redirect_viewable: false,
+        window,
         state: Some(ClientState {
             x: 1,
             y: 1,
             width: 10,
             height: 10,
-            is_viewable: true,
+            border_width: 0,
+            saved_border_width: None,
+            size_preset: None,
+            fullscreen: false,
+            saved_geometry: None,
+            gtk_frame_extents: [0; 4],
+            locked: false,
+            transient_for: None,
+            iconifying: false,
+            is_viewable,
             wm_protocols: WmProtocols::new(),
             wm_state: None,
             wm_normal_hints: WmSizeHints::new(),
+            wm_class: None,
+            wm_window_role: None,
+            urgent: false,
+            modal: false,
+            focusable: true,
+            desktop: false,
+            title: None,
+            wm_command: None,
+            sm_client_id: None,
+            icons: Vec::new(),
+            visibility: Visibility::Unobscured,
         }),
-    });
+    }
+}
+
+/// Create a dummy override-redirect window for testing in the absence of an X11
+/// server.
+#[cfg(test)]
+pub(crate) fn dummy_override_redirect(window: xproto::Window) -> Client {
+    Client {
+        window,
+        state: None,
+        override_redirect_geometry: None,
+        override_redirect_viewable: false,
+    }
+}
+
+/// Issue was encountered where `Clients` could retain the ID of a closed window in
+/// `Clients.focus`, despite having removed the corresponding window from the stack
+/// of managed windows. This caused an "unwrap None" error on removing the next window.
+/// Regression test to verify that `Clients.remove` properly updates `Clients.focus` when
+/// the focused window is removed.
+#[test]
+fn can_remove_focused_window() {
+    let mut clients = Clients {
+        stack: vec![],
+        focus: None,
+        mapped: vec![],
+    };
+
+    //Setup dummy clients in the absence of an X11 server
+    clients.push(dummy_client(100, true));
+    clients.push(dummy_client(200, true));
+    clients.push(dummy_client(250, false));
+    clients.push(dummy_client(300, true));
 
     clients.set_focus(300);
     assert_eq!(clients.get_focus().unwrap().window, 300);
@@ -343,78 +1158,15 @@ fn check_client_stacking() {
     let mut clients = Clients {
         stack: vec![],
         focus: None,
+        mapped: vec![],
     };
 
     //Setup dummy clients in the absence of an X11 server
-    clients.push(Client {
-        window: 100,
-        state: Some(ClientState {
-            x: 1,
-            y: 1,
-            width: 10,
-            height: 10,
-            is_viewable: true,
-            wm_protocols: WmProtocols::new(),
-            wm_state: None,
-            wm_normal_hints: WmSizeHints::new(),
-        }),
-    });
-
-    clients.push(Client {
-        window: 150,
-        state: Some(ClientState {
-            x: 1,
-            y: 1,
-            width: 10,
-            height: 10,
-            is_viewable: true,
-            wm_protocols: WmProtocols::new(),
-            wm_state: None,
-            wm_normal_hints: WmSizeHints::new(),
-        }),
-    });
-
-    clients.push(Client {
-        window: 200,
-        state: Some(ClientState {
-            x: 1,
-            y: 1,
-            width: 10,
-            height: 10,
-            is_viewable: true,
-            wm_protocols: WmProtocols::new(),
-            wm_state: None,
-            wm_normal_hints: WmSizeHints::new(),
-        }),
-    });
-
-    clients.push(Client {
-        window: 250,
-        state: Some(ClientState {
-            x: 1,
-            y: 1,
-            width: 10,
-            height: 10,
-            is_viewable: false,
-            wm_protocols: WmProtocols::new(),
-            wm_state: None,
-            wm_normal_hints: WmSizeHints::new(),
-        }),
-    });
-
-    clients.push(Client {
-        window: 300,
-        state: Some(ClientState {
-            x: 1,
-            y: 1,
-            width: 10,
-            height: 10,
-            is_viewable: true,
-            wm_protocols: WmProtocols::new(),
-            wm_state: None,
-            wm_normal_hints: WmSizeHints::new(),
-        }),
-    });
+    clients.push(dummy_client(100, true));
+    clients.push(dummy_client(150, true));
+    clients.push(dummy_client(200, true));
+    clients.push(dummy_client(250, false));
+    clients.push(dummy_client(300, true));
 
     //100,150,200,250,300
     assert_eq!(clients.top().window, 300);
@@ -493,36 +1245,12 @@ fn check_get() {
     let mut clients = Clients {
         stack: vec![],
         focus: None,
+        mapped: vec![],
     };
 
     //Setup dummy clients in the absence of an X11 server
-    clients.push(Client {
-        window: 100,
-        state: Some(ClientState {
-            x: 1,
-            y: 1,
-            width: 10,
-            height: 10,
-            is_viewable: true,
-            wm_protocols: WmProtocols::new(),
-            wm_state: None,
-            wm_normal_hints: WmSizeHints::new(),
-        }),
-    });
-
-    clients.push(Client {
-        window: 150,
-        state: Some(ClientState {
-            x: 1,
-            y: 1,
-            width: 10,
-            height: 10,
-            is_viewable: true,
-            wm_protocols: WmProtocols::new(),
-            wm_state: None,
-            wm_normal_hints: WmSizeHints::new(),
-        }),
-    });
+    clients.push(dummy_client(100, true));
+    clients.push(dummy_client(150, true));
 
     assert_eq!(clients.get(100).window, 100);
     assert_eq!(clients.get_mut(100).window, 100);
@@ -543,25 +1271,692 @@ fn check_get() {
         let mut clients_unsafe = Clients {
             stack: vec![],
             focus: None,
+            mapped: vec![],
         };
 
         //Setup dummy clients in the absence of an X11 server
-        clients_unsafe.push(Client {
-            window: 100,
-            state: Some(ClientState {
-                x: 1,
-                y: 1,
-                width: 10,
-                height: 10,
-                is_viewable: true,
-                wm_protocols: WmProtocols::new(),
-                wm_state: None,
-                wm_normal_hints: WmSizeHints::new(),
-            }),
-        });
+        clients_unsafe.push(dummy_client(100, true));
         let panic_result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
             assert_eq!(clients_unsafe.get_mut(750).window, 150);
         }));
         assert!(panic_result.is_err());
     }
 }
+
+/// Confirm that the focus history keeps windows in most-recently-focused order,
+/// and that pruning windows doesn't disturb it.
+#[test]
+fn check_focus_history_order() {
+    let mut history = FocusHistory::new();
+    history.focused(100);
+    history.focused(200);
+    history.focused(300);
+    history.focused(200);
+    assert_eq!(history.windows, vec![200, 300, 100]);
+
+    history.remove(300);
+    assert_eq!(history.windows, vec![200, 100]);
+    history.remove(675);
+    assert_eq!(history.windows, vec![200, 100]);
+}
+
+/// Confirm that cycling walks back through the history, and that committing
+/// moves the selected window to the front.
+#[test]
+fn check_focus_history_cycle() {
+    let mut history = FocusHistory::new();
+    assert_eq!(history.cycle(), None);
+    history.focused(100);
+    assert_eq!(history.cycle(), None);
+    assert!(!history.is_cycling());
+    history.focused(200);
+    history.focused(300);
+
+    // 300, 200, 100
+    assert_eq!(history.cycle(), Some(200));
+    // Focusing the selected window mustn't reorder anything while cycling.
+    history.focused(200);
+    assert_eq!(history.cycle(), Some(100));
+    assert_eq!(history.cycle(), Some(300));
+    assert_eq!(history.cycle(), Some(200));
+    history.commit();
+    assert!(!history.is_cycling());
+    assert_eq!(history.windows, vec![200, 300, 100]);
+
+    // Pruning the selected window while cycling keeps the cursor in bounds.
+    assert_eq!(history.cycle(), Some(300));
+    assert_eq!(history.cycle(), Some(100));
+    history.remove(100);
+    history.commit();
+    assert_eq!(history.windows, vec![300, 200]);
+}
+
+/// Confirm that cancelling a cycle leaves the history as it was.
+#[test]
+fn check_focus_history_cancel() {
+    let mut history = FocusHistory::new();
+    history.focused(100);
+    history.focused(200);
+    history.focused(300);
+    assert_eq!(history.cycle(), Some(200));
+    history.cancel();
+    assert!(!history.is_cycling());
+    history.commit();
+    assert_eq!(history.windows, vec![300, 200, 100]);
+    // A fresh cycle starts from the top again.
+    assert_eq!(history.cycle(), Some(200));
+}
+
+/// Confirm that hiding and restoring borders keeps each window's own width.
+#[test]
+fn check_hide_border() {
+    let mut client = dummy_client(100, true);
+    let st = client.state.as_mut().unwrap();
+    st.border_width = 3;
+    assert_eq!(st.restore_border(), None);
+    assert_eq!(st.hide_border(), 0);
+    st.border_width = 0;
+    // Hiding twice mustn't clobber the saved width.
+    assert_eq!(st.hide_border(), 0);
+    assert_eq!(st.restore_border(), Some(3));
+    assert_eq!(st.restore_border(), None);
+}
+
+/// Confirm that VisibilityNotify events update the client's visibility, and
+/// that events for unknown windows are ignored.
+#[test]
+fn check_visibility_notify() {
+    let mut clients = Clients {
+        stack: vec![],
+        focus: None,
+        mapped: vec![],
+    };
+    clients.push(dummy_client(100, true));
+    let mut ev = xproto::VisibilityNotifyEvent {
+        response_type: xproto::VISIBILITY_NOTIFY_EVENT,
+        sequence: 0,
+        window: 100,
+        state: xproto::Visibility::FULLY_OBSCURED,
+    };
+    let visibility = |clients: &Clients| clients.get(100).state.as_ref().unwrap().visibility;
+    assert_eq!(visibility(&clients), Visibility::Unobscured);
+    clients.visibility_notify(&ev);
+    assert_eq!(visibility(&clients), Visibility::FullyObscured);
+    ev.state = xproto::Visibility::PARTIALLY_OBSCURED;
+    clients.visibility_notify(&ev);
+    assert_eq!(visibility(&clients), Visibility::PartiallyObscured);
+    ev.state = xproto::Visibility::UNOBSCURED;
+    clients.visibility_notify(&ev);
+    assert_eq!(visibility(&clients), Visibility::Unobscured);
+    ev.window = 200;
+    clients.visibility_notify(&ev);
+}
+
+/// Confirm that a window's border and geometry are saved on entering
+/// fullscreen, and handed back on leaving it.
+#[test]
+fn check_fullscreen_border() {
+    let mut client = dummy_client(100, true);
+    let st = client.state.as_mut().unwrap();
+    st.border_width = 2;
+    assert_eq!(st.leave_fullscreen(), None);
+    assert!(st.enter_fullscreen());
+    assert!(st.fullscreen);
+    assert_eq!(st.saved_border_width, Some(2));
+    // Going fullscreen again mustn't clobber what we saved.
+    st.border_width = 0;
+    st.width = 1920;
+    assert!(!st.enter_fullscreen());
+    assert_eq!(st.saved_border_width, Some(2));
+    assert_eq!(st.leave_fullscreen(), Some((1, 1, 10, 10)));
+    assert!(!st.fullscreen);
+    assert_eq!(st.restore_border(), Some(2));
+}
+
+/// Confirm that only windows without local state count as override-redirect.
+#[test]
+fn check_override_redirect() {
+    assert!(!dummy_client(100, true).override_redirect());
+    let client = dummy_override_redirect(200);
+    assert!(client.override_redirect());
+}
+
+/// Confirm that neighbors are found in stacking order, skipping windows that
+/// aren't managed and viewable.
+#[test]
+fn check_neighbor() {
+    let mut clients = Clients {
+        stack: vec![
+            dummy_client(100, true),
+            dummy_override_redirect(200),
+            dummy_client(300, false),
+            dummy_client(400, true),
+            dummy_client(500, true),
+        ],
+        focus: None,
+        mapped: vec![],
+    };
+    assert_eq!(clients.neighbor(100, true), Some(400));
+    assert_eq!(clients.neighbor(400, true), Some(500));
+    assert_eq!(clients.neighbor(500, true), Some(100));
+    assert_eq!(clients.neighbor(100, false), Some(500));
+    assert_eq!(clients.neighbor(400, false), Some(100));
+    clients.get_mut(400).state.as_mut().unwrap().is_viewable = false;
+    clients.get_mut(500).state.as_mut().unwrap().is_viewable = false;
+    assert_eq!(clients.neighbor(100, true), None);
+}
+
+/// Confirm that swapping two clients exchanges their geometry.
+#[test]
+fn check_swap() {
+    let mut clients = Clients {
+        stack: vec![dummy_client(100, true), dummy_client(200, true)],
+        focus: None,
+        mapped: vec![],
+    };
+    {
+        let st = clients.get_mut(200).state.as_mut().unwrap();
+        st.x = 50;
+        st.y = 60;
+        st.width = 300;
+        st.height = 200;
+    }
+    clients.swap(100, 200);
+    let st = clients.get(100).state.as_ref().unwrap();
+    assert_eq!((st.x, st.y, st.width, st.height), (50, 60, 300, 200));
+    let st = clients.get(200).state.as_ref().unwrap();
+    assert_eq!((st.x, st.y, st.width, st.height), (1, 1, 10, 10));
+    // The stacking order is unchanged.
+    assert_eq!(
+        clients.iter().map(|c| c.window).collect::<Vec<_>>(),
+        vec![100, 200]
+    );
+}
+
+/// Confirm that the transients of a window are found.
+#[test]
+fn check_transients_for() {
+    let mut clients = Clients {
+        stack: vec![
+            dummy_client(100, true),
+            dummy_client(200, true),
+            dummy_client(300, false),
+            dummy_client(400, true),
+        ],
+        focus: None,
+        mapped: vec![],
+    };
+    clients.get_mut(200).state.as_mut().unwrap().transient_for = Some(100);
+    clients.get_mut(300).state.as_mut().unwrap().transient_for = Some(100);
+    clients.get_mut(400).state.as_mut().unwrap().transient_for = Some(200);
+    assert_eq!(clients.transients_for(100), vec![200, 300]);
+    assert_eq!(clients.transients_for(200), vec![400]);
+    assert!(clients.transients_for(400).is_empty());
+}
+
+/// Confirm that leaving a window on its own hides only the other viewable,
+/// focusable windows, keeping its transients.
+#[test]
+fn check_others_to_hide() {
+    let mut dock = dummy_client(300, true);
+    dock.state.as_mut().unwrap().focusable = false;
+    let mut dialog = dummy_client(600, true);
+    dialog.state.as_mut().unwrap().transient_for = Some(500);
+    let clients = Clients {
+        stack: vec![
+            dummy_client(100, true),
+            dummy_client(200, false),
+            dock,
+            dummy_override_redirect(400),
+            dummy_client(500, true),
+            dialog,
+            dummy_client(700, true),
+        ],
+        focus: Some(500),
+        mapped: vec![],
+    };
+    assert_eq!(clients.others_to_hide(500), vec![100, 700]);
+    assert_eq!(clients.others_to_hide(100), vec![500, 600, 700]);
+    assert_eq!(clients.others_to_hide(200), vec![100, 500, 600, 700]);
+}
+
+/// Confirm that cycling by class skips other classes and unviewable windows.
+#[test]
+fn check_next_same_class() {
+    let class = |window, class: &str, is_viewable| {
+        let mut client = dummy_client(window, is_viewable);
+        client.state.as_mut().unwrap().wm_class = Some(WmClass {
+            instance: class.to_lowercase(),
+            class: class.to_string(),
+        });
+        client
+    };
+    let clients = Clients {
+        stack: vec![
+            class(100, "XTerm", true),
+            class(200, "Firefox", true),
+            class(300, "XTerm", false),
+            dummy_override_redirect(400),
+            dummy_client(500, true),
+            class(600, "XTerm", true),
+            class(700, "XTerm", true),
+        ],
+        focus: None,
+        mapped: vec![],
+    };
+    assert_eq!(clients.next_same_class(100), Some(600));
+    assert_eq!(clients.next_same_class(600), Some(700));
+    assert_eq!(clients.next_same_class(700), Some(100));
+    assert_eq!(clients.next_same_class(200), None);
+    assert_eq!(clients.next_same_class(500), None);
+    assert_eq!(clients.next_same_class(400), None);
+}
+
+/// Confirm that override-redirect windows only have their geometry recorded
+/// when we've been asked to track it.
+#[test]
+fn check_set_geometry() {
+    let geometry = Geometry {
+        x: 5,
+        y: 6,
+        width: 70,
+        height: 80,
+        border_width: 2,
+    };
+
+    let mut client = dummy_client(100, true);
+    client.set_geometry(geometry, false);
+    assert_eq!(client.geometry(), Some(geometry));
+
+    let mut client = dummy_override_redirect(200);
+    client.set_geometry(geometry, false);
+    assert_eq!(client.geometry(), None);
+    client.set_geometry(geometry, true);
+    assert_eq!(client.geometry(), Some(geometry));
+}
+
+/// Confirm that _NET_CLIENT_LIST follows map order while
+/// _NET_CLIENT_LIST_STACKING follows the stack, and that neither includes
+/// override-redirect or withdrawn windows.
+#[test]
+fn check_client_lists() {
+    let mut clients = Clients {
+        stack: vec![
+            dummy_client(100, true),
+            dummy_client(200, true),
+            dummy_override_redirect(300),
+            dummy_client(400, true),
+        ],
+        focus: None,
+        mapped: vec![],
+    };
+    assert!(clients.mapped(400));
+    assert!(clients.mapped(100));
+    assert!(!clients.mapped(300));
+    assert!(clients.mapped(200));
+    assert!(!clients.mapped(100));
+    assert_eq!(clients.client_list(), &[400, 100, 200]);
+    assert_eq!(clients.client_list_stacking(), vec![100, 200, 400]);
+
+    clients.move_to_bottom(400);
+    assert_eq!(clients.client_list(), &[400, 100, 200]);
+    assert_eq!(clients.client_list_stacking(), vec![400, 100, 200]);
+
+    assert!(clients.withdrawn(100));
+    assert!(!clients.withdrawn(100));
+    clients.remove(200);
+    assert_eq!(clients.client_list(), &[400]);
+    assert_eq!(clients.client_list_stacking(), vec![400]);
+}
+
+/// Confirm that a popup only counts as being on top if no viewable window is
+/// stacked above it, and only covers windows that it overlaps if we know where
+/// it is.
+#[test]
+fn check_popup_over() {
+    let mut clients = Clients {
+        stack: vec![
+            dummy_client(100, true),
+            dummy_override_redirect(200),
+            dummy_client(300, false),
+        ],
+        focus: None,
+        mapped: vec![],
+    };
+    assert!(!clients.popup_over(100));
+    clients.get_mut(200).set_viewable(true);
+    assert!(clients.popup_over(100));
+    clients.get_mut(300).set_viewable(true);
+    assert!(!clients.popup_over(100));
+    clients.move_to_top(200);
+    assert!(clients.popup_over(100));
+
+    // Once the popup's geometry is tracked, it only covers windows that it
+    // overlaps. The dummy clients are 10x10 at (1, 1).
+    let corner = |x, y| Geometry {
+        x,
+        y,
+        width: 20,
+        height: 20,
+        border_width: 1,
+    };
+    clients.get_mut(200).set_geometry(corner(500, 500), true);
+    assert!(!clients.popup_over(100));
+    clients.get_mut(200).set_geometry(corner(5, 5), true);
+    assert!(clients.popup_over(100));
+    clients.get_mut(200).set_geometry(corner(-21, 0), true);
+    assert!(!clients.popup_over(100));
+    clients.get_mut(200).set_geometry(corner(-20, 0), true);
+    assert!(clients.popup_over(100));
+}
+
+/// Confirm that windows are lowered to just above the top-most desktop, and
+/// that the desktop stays at the bottom when other windows are raised.
+#[test]
+fn check_top_desktop() {
+    let mut desktop = dummy_client(100, true);
+    desktop.state.as_mut().unwrap().desktop = true;
+    desktop.state.as_mut().unwrap().focusable = false;
+    let mut clients = Clients {
+        stack: vec![
+            desktop,
+            dummy_client(200, true),
+            dummy_override_redirect(300),
+            dummy_client(400, true),
+        ],
+        focus: None,
+        mapped: vec![],
+    };
+    assert_eq!(clients.top_desktop(400), Some(100));
+    assert_eq!(clients.top_desktop(100), None);
+    // Cycling upwards from the top wraps around past the desktop.
+    assert_eq!(clients.neighbor(400, true), Some(200));
+    clients.move_to_top(200);
+    clients.move_to_above(400, clients.top_desktop(400).unwrap());
+    let stack: Vec<_> = clients.iter().map(|client| client.window).collect();
+    assert_eq!(stack, vec![100, 400, 300, 200]);
+    // A window that lowers itself to the bottom still finds the desktop to go
+    // back above.
+    clients.move_to_bottom(200);
+    assert_eq!(clients.top_desktop(200), Some(100));
+    clients.move_to_above(200, clients.top_desktop(200).unwrap());
+    let stack: Vec<_> = clients.iter().map(|client| client.window).collect();
+    assert_eq!(stack, vec![100, 200, 400, 300]);
+
+    clients.remove(100);
+    assert_eq!(clients.top_desktop(400), None);
+}
+
+/// Confirm that docks are never offered the focus, whether by cycling or by
+/// clicking.
+#[test]
+fn check_dock_not_focusable() {
+    let mut dock = dummy_client(200, true);
+    dock.state.as_mut().unwrap().focusable = false;
+    let clients = Clients {
+        stack: vec![dummy_client(100, true), dock, dummy_client(300, true)],
+        focus: None,
+        mapped: vec![],
+    };
+    assert_eq!(clients.neighbor(100, true), Some(300));
+    assert_eq!(clients.neighbor(300, false), Some(100));
+    assert!(!clients.get(200).focusable());
+    assert!(clients.get(100).focusable());
+    assert!(dummy_override_redirect(400).focusable());
+}
+
+/// Confirm that focus can follow a chain of transients up to their parents
+/// and down to their children, skipping windows that can't be focused.
+#[test]
+fn check_transient_parent_child() {
+    let mut clients = Clients {
+        stack: vec![],
+        focus: None,
+        mapped: vec![],
+    };
+    clients.push(dummy_client(100, true));
+    clients.push(dummy_client(200, true));
+    clients.push(dummy_client(300, true));
+    clients.push(dummy_client(400, false));
+    clients.get_mut(200).state.as_mut().unwrap().transient_for = Some(100);
+    clients.get_mut(300).state.as_mut().unwrap().transient_for = Some(200);
+    clients.get_mut(400).state.as_mut().unwrap().transient_for = Some(200);
+    assert_eq!(clients.transient_parent(300), Some(200));
+    assert_eq!(clients.transient_parent(200), Some(100));
+    assert_eq!(clients.transient_parent(100), None);
+    assert_eq!(clients.transient_child(100), Some(200));
+    // The topmost child is unmapped, so the one below it is chosen.
+    assert_eq!(clients.transient_child(200), Some(300));
+    assert_eq!(clients.transient_child(300), None);
+    // A parent that isn't managed is no use.
+    clients.get_mut(100).state.as_mut().unwrap().transient_for = Some(999);
+    assert_eq!(clients.transient_parent(100), None);
+}
+
+/// Confirm that the focus falls back on the top-most window that can take it,
+/// and on nothing once there are no such windows left.
+#[test]
+fn check_focus_fallback() {
+    let mut dock = dummy_client(300, true);
+    dock.state.as_mut().unwrap().focusable = false;
+    let clients = Clients {
+        stack: vec![
+            dummy_client(100, true),
+            dummy_client(200, false),
+            dock,
+            dummy_override_redirect(400),
+            dummy_client(500, true),
+        ],
+        focus: Some(500),
+        mapped: vec![],
+    };
+    assert_eq!(clients.focus_fallback(500), Some(100));
+    assert_eq!(clients.focus_fallback(100), Some(500));
+    let clients = Clients {
+        stack: vec![dummy_client(100, true), dummy_client(200, false)],
+        focus: Some(100),
+        mapped: vec![],
+    };
+    assert_eq!(clients.focus_fallback(100), None);
+}
+
+/// Confirm that the focus moves on when the focused window goes away, even if
+/// its FocusOut has already cleared the focus, and that other windows going
+/// away leave it alone.
+#[test]
+fn check_refocus_after() {
+    let mut history = FocusHistory::new();
+    history.focused(100);
+    history.focused(500);
+    let mut clients = Clients {
+        stack: vec![dummy_client(100, true), dummy_client(500, true)],
+        focus: Some(500),
+        mapped: vec![],
+    };
+    assert_eq!(clients.refocus_after(500, &history), Refocus::Window(100));
+    assert_eq!(clients.refocus_after(100, &history), Refocus::Unaffected);
+    // The FocusOut came first.
+    clients.focus = None;
+    assert_eq!(clients.refocus_after(500, &history), Refocus::Window(100));
+    assert_eq!(clients.refocus_after(100, &history), Refocus::Unaffected);
+    // The last window goes away.
+    let clients = Clients {
+        stack: vec![dummy_client(500, true)],
+        focus: None,
+        mapped: vec![],
+    };
+    assert_eq!(clients.refocus_after(500, &history), Refocus::Nothing);
+    assert_eq!(
+        clients.refocus_after(500, &FocusHistory::new()),
+        Refocus::Unaffected
+    );
+}
+
+/// Confirm that marks can be set, moved and looked up, and that they're
+/// pruned along with their windows.
+#[test]
+fn check_marks() {
+    let mut marks = Marks::new();
+    assert_eq!(marks.get('a'), None);
+    marks.set('a', 100);
+    marks.set('b', 200);
+    marks.set('c', 100);
+    assert_eq!(marks.get('a'), Some(100));
+    assert_eq!(marks.get('b'), Some(200));
+    marks.set('b', 300);
+    assert_eq!(marks.get('b'), Some(300));
+
+    marks.start(PendingMark::Set(100));
+    assert!(marks.is_pending());
+    marks.remove(100);
+    assert!(!marks.is_pending());
+    assert_eq!(marks.get('a'), None);
+    assert_eq!(marks.get('c'), None);
+    assert_eq!(marks.get('b'), Some(300));
+
+    marks.start(PendingMark::Goto);
+    assert_eq!(marks.take_pending(), Some(PendingMark::Goto));
+    assert_eq!(marks.take_pending(), None);
+}
+
+/// Confirm that groups ignore duplicates, forget removed windows, and cycle
+/// around their members, skipping ones that can't be focused.
+#[test]
+fn check_group() {
+    let mut group = Group::new();
+    assert_eq!(group.next(None, |_| true), None);
+    group.add(100);
+    group.add(200);
+    group.add(100);
+    group.add(300);
+    assert_eq!(group.next(None, |_| true), Some(100));
+    assert_eq!(group.next(Some(100), |_| true), Some(200));
+    assert_eq!(group.next(Some(300), |_| true), Some(100));
+    assert_eq!(group.next(Some(400), |_| true), Some(100));
+    assert_eq!(group.next(Some(100), |w| w != 200), Some(300));
+    assert_eq!(group.next(Some(100), |w| w == 100), None);
+
+    group.remove(200);
+    assert_eq!(group.next(Some(100), |_| true), Some(300));
+    group.clear();
+    assert_eq!(group.next(Some(100), |_| true), None);
+}
+
+/// Confirm that toggling flips between the scratch window and the previous
+/// focus, and that destroyed windows are forgotten.
+#[test]
+fn check_scratch() {
+    let mut scratch = Scratch::new();
+    assert_eq!(scratch.toggle(Some(100)), None);
+    scratch.set(200);
+    assert_eq!(scratch.toggle(Some(100)), Some(200));
+    assert_eq!(scratch.toggle(Some(200)), Some(100));
+    assert_eq!(scratch.toggle(Some(300)), Some(200));
+    assert_eq!(scratch.toggle(Some(200)), Some(300));
+    assert_eq!(scratch.toggle(None), Some(200));
+    assert_eq!(scratch.toggle(Some(200)), None);
+
+    assert_eq!(scratch.toggle(Some(100)), Some(200));
+    scratch.remove(100);
+    assert_eq!(scratch.toggle(Some(200)), None);
+    scratch.remove(200);
+    assert_eq!(scratch.toggle(Some(100)), None);
+}
+
+/// Confirm that circulating only touches the windows under the point, and
+/// does nothing with fewer than two of them.
+#[test]
+fn check_circulation_target() {
+    let at = |window, x, y, is_viewable| {
+        let mut client = dummy_client(window, is_viewable);
+        let st = client.state.as_mut().unwrap();
+        st.x = x;
+        st.y = y;
+        st.width = 100;
+        st.height = 100;
+        st.border_width = 1;
+        client
+    };
+    let mut desktop = at(50, 0, 0, true);
+    desktop.state.as_mut().unwrap().desktop = true;
+    let mut clients = Clients {
+        stack: vec![
+            desktop,
+            at(100, 0, 0, true),
+            at(200, 500, 500, true),
+            at(300, 50, 50, true),
+            at(400, 60, 60, false),
+            dummy_override_redirect(500),
+            at(600, 90, 90, true),
+        ],
+        focus: None,
+        mapped: vec![],
+    };
+    let order = |clients: &Clients| clients.iter().map(|c| c.window).collect::<Vec<_>>();
+
+    // 100, 300 and 600 all cover (95, 95).
+    let window = clients.circulation_target(true, 95, 95).unwrap();
+    assert_eq!(window, 100);
+    clients.move_to_top(window);
+    assert_eq!(clients.circulation_target(true, 95, 95), Some(300));
+    assert_eq!(clients.circulation_target(false, 95, 95), Some(100));
+    let window = clients.circulation_target(false, 95, 95).unwrap();
+    clients.move_to_above(window, 50);
+    assert_eq!(order(&clients), vec![50, 100, 200, 300, 400, 500, 600]);
+
+    // The border counts as part of the window.
+    assert_eq!(clients.circulation_target(true, 101, 101), Some(100));
+    // Only one window here, and none at all here.
+    assert_eq!(clients.circulation_target(true, 10, 10), None);
+    assert_eq!(clients.circulation_target(true, 1000, 1000), None);
+}
+
+/// Confirm that rotating the stack moves every window by one place, keeping
+/// the same windows and the same focus.
+#[test]
+fn check_rotation_target() {
+    let mut clients = Clients {
+        stack: vec![
+            dummy_client(100, true),
+            dummy_client(200, false),
+            dummy_client(300, true),
+            dummy_override_redirect(400),
+            dummy_client(500, true),
+        ],
+        focus: Some(300),
+        mapped: vec![],
+    };
+    let order = |clients: &Clients| clients.iter().map(|c| c.window).collect::<Vec<_>>();
+
+    let window = clients.rotation_target(true).unwrap();
+    assert_eq!(window, 100);
+    clients.move_to_top(window);
+    assert_eq!(order(&clients), vec![200, 300, 400, 500, 100]);
+
+    let window = clients.rotation_target(false).unwrap();
+    assert_eq!(window, 100);
+    clients.move_to_bottom(window);
+    assert_eq!(order(&clients), vec![100, 200, 300, 400, 500]);
+    assert_eq!(clients.get_focus().unwrap().window, 300);
+
+    let clients = Clients {
+        stack: vec![dummy_client(100, true), dummy_client(200, false)],
+        focus: None,
+        mapped: vec![],
+    };
+    assert_eq!(clients.rotation_target(true), None);
+    assert_eq!(clients.rotation_target(false), None);
+}
+
+/// Confirm that restyling a border changes its width right away only if it
+/// isn't hidden, and that a hidden border comes back with the new width.
+#[test]
+fn check_restyle_border() {
+    let mut st = dummy_client(100, true).state.unwrap();
+    st.border_width = 1;
+    assert_eq!(st.restyle_border(3), Some(3));
+    st.border_width = 3;
+    st.hide_border();
+    assert_eq!(st.restyle_border(5), None);
+    assert_eq!(st.restore_border(), Some(5));
+}
@@ -1,10 +1,12 @@
 //! Load config files.
 
+use crate::client::ClientState;
 use crate::util::*;
 use crate::OxWM;
 use crate::Result;
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
 use std::fs;
 use std::path::Path;
 
@@ -22,6 +24,16 @@ use x11rb::protocol::xproto;
 /// is the currently-focused window.
 type Action<Conn> = fn(&mut OxWM<Conn>, xproto::Window) -> crate::Result<()>;
 
+/// What a config is being loaded for. Running OxWM needs the keybinds bound to
+/// Keycodes, which takes an X server; printing the config doesn't.
+#[derive(PartialEq, Eq, Clone, Copy, Hash, Debug)]
+pub(crate) enum Purpose {
+    /// Running the window manager.
+    Run,
+    /// Printing the config, e.g. with `--print-config`.
+    Print,
+}
+
 /// Bespoke `ModMask` type so that we can have a `Deserialize` instance.
 #[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Hash, Debug, Deserialize, Serialize)]
 #[serde(rename_all = "lowercase")]
@@ -72,6 +84,57 @@ impl ModMask {
     }
 }
 
+/// Every action that can be bound to a key, by the name that the config file
+/// uses for it.
+pub(crate) fn actions<Conn>() -> Vec<(&'static str, Action<Conn>)>
+where
+    Conn: Connection,
+{
+    vec![
+        ("quit", OxWM::poison),
+        ("kill", OxWM::kill_focused_client),
+        ("restart", OxWM::restart),
+        ("focus_last", OxWM::focus_last),
+        ("focus_urgent", OxWM::focus_urgent),
+        ("toggle_all_borders", OxWM::toggle_all_borders),
+        ("pin_top_left", OxWM::pin_top_left),
+        ("pin_top_right", OxWM::pin_top_right),
+        ("pin_bottom_left", OxWM::pin_bottom_left),
+        ("pin_bottom_right", OxWM::pin_bottom_right),
+        ("cycle_size", OxWM::cycle_size),
+        ("toggle_locked", OxWM::toggle_locked),
+        ("swap_with_next", OxWM::swap_with_next),
+        ("swap_with_prev", OxWM::swap_with_prev),
+        ("focus_next_same_class", OxWM::focus_next_same_class),
+        ("focus_parent", OxWM::focus_parent),
+        ("focus_child", OxWM::focus_child),
+        ("mark_window", OxWM::mark_window),
+        ("goto_mark", OxWM::goto_mark),
+        ("rotate_stack_forward", OxWM::rotate_stack_forward),
+        ("rotate_stack_backward", OxWM::rotate_stack_backward),
+        ("focus_left", OxWM::focus_left),
+        ("focus_right", OxWM::focus_right),
+        ("focus_up", OxWM::focus_up),
+        ("focus_down", OxWM::focus_down),
+        ("refresh_appearance", OxWM::refresh_appearance),
+        ("grow_proportional", OxWM::grow_proportional),
+        ("shrink_proportional", OxWM::shrink_proportional),
+        ("toggle_zoom_focus", OxWM::toggle_zoom_focus),
+        ("group_add", OxWM::group_add),
+        ("group_clear", OxWM::group_clear),
+        ("group_cycle", OxWM::group_cycle),
+        ("set_scratch", OxWM::set_scratch),
+        ("toggle_scratch_focus", OxWM::toggle_scratch_focus),
+        ("minimize_others", OxWM::minimize_others),
+        ("restore_others", OxWM::restore_others),
+        ("prefix", OxWM::prefix),
+        ("toggle_freeze", OxWM::toggle_freeze),
+        ("circulate_up", OxWM::circulate_up),
+        ("circulate_down", OxWM::circulate_down),
+        ("toggle_focus_model", OxWM::toggle_focus_model),
+    ]
+}
+
 /// Focus model.
 #[derive(PartialEq, Eq, Clone, Copy, Hash, Debug, Deserialize, Serialize)]
 #[serde(rename_all = "lowercase")]
@@ -82,6 +145,218 @@ pub enum FocusModel {
     Autofocus,
 }
 
+impl FocusModel {
+    /// The other focus model, for `toggle_focus_model`.
+    pub(crate) fn toggled(self) -> Self {
+        match self {
+            FocusModel::Click => FocusModel::Autofocus,
+            FocusModel::Autofocus => FocusModel::Click,
+        }
+    }
+
+    /// The events that we select on managed windows. We only need to hear
+    /// about the pointer entering them for focus-follows-mouse.
+    pub(crate) fn client_event_mask(self) -> xproto::EventMask {
+        let mask = xproto::EventMask::FOCUS_CHANGE
+            | xproto::EventMask::PROPERTY_CHANGE
+            | xproto::EventMask::VISIBILITY_CHANGE;
+        match self {
+            FocusModel::Click => mask,
+            FocusModel::Autofocus => mask | xproto::EventMask::ENTER_WINDOW,
+        }
+    }
+}
+
+/// Where keybinds are grabbed.
+#[derive(PartialEq, Eq, Clone, Copy, Hash, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum KeybindScope {
+    /// Grab keybinds on the root window, so that they work whatever has the
+    /// focus.
+    Global,
+    /// Grab keybinds on each managed window, so that they only work while a
+    /// managed window has the focus, and a client with an active keyboard
+    /// grab (e.g. a fullscreen game) gets every key.
+    Managed,
+}
+
+impl KeybindScope {
+    /// Decide which window to grab keybinds on. `window` is the managed
+    /// window being set up, or `None` during global setup.
+    pub(crate) fn grab_window(
+        self,
+        root: xproto::Window,
+        window: Option<xproto::Window>,
+    ) -> Option<xproto::Window> {
+        match (self, window) {
+            (KeybindScope::Global, None) => Some(root),
+            (KeybindScope::Managed, Some(window)) => Some(window),
+            _ => None,
+        }
+    }
+}
+
+/// How to decode window titles that are given as plain STRINGs. Written as
+/// "auto", "utf8", "latin1", or the label of any other encoding in the WHATWG
+/// Encoding Standard (e.g. "shift_jis" or "koi8-r").
+#[derive(PartialEq, Eq, Clone, Copy, Hash, Debug, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub(crate) enum TitleEncoding {
+    /// UTF-8 if the title is valid UTF-8, and Latin-1 otherwise.
+    Auto,
+    /// Always UTF-8, replacing invalid sequences.
+    Utf8,
+    /// Always Latin-1, as the ICCCM specifies.
+    Latin1,
+    /// Always the named encoding, replacing invalid sequences.
+    Named(&'static encoding_rs::Encoding),
+}
+
+impl TryFrom<String> for TitleEncoding {
+    type Error = ConfigError;
+
+    fn try_from(value: String) -> std::result::Result<Self, Self::Error> {
+        match value.as_str() {
+            "auto" => Ok(TitleEncoding::Auto),
+            "utf8" => Ok(TitleEncoding::Utf8),
+            "latin1" => Ok(TitleEncoding::Latin1),
+            label => match encoding_rs::Encoding::for_label(label.as_bytes()) {
+                Some(encoding) => Ok(TitleEncoding::Named(encoding)),
+                None => Err(InvalidEncoding(value)),
+            },
+        }
+    }
+}
+
+impl From<TitleEncoding> for String {
+    fn from(value: TitleEncoding) -> String {
+        match value {
+            TitleEncoding::Auto => "auto".to_string(),
+            TitleEncoding::Utf8 => "utf8".to_string(),
+            TitleEncoding::Latin1 => "latin1".to_string(),
+            TitleEncoding::Named(encoding) => encoding.name().to_lowercase(),
+        }
+    }
+}
+
+/// Policy for giving the focus to newly-mapped windows.
+#[derive(PartialEq, Eq, Clone, Copy, Hash, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FocusNew {
+    /// Always focus new windows.
+    Always,
+    /// Never focus new windows (although they may take the focus themselves).
+    Never,
+    /// Focus new windows unless the user has done something since the window
+    /// was last interacted with, according to its _NET_WM_USER_TIME.
+    Smart,
+}
+
+impl FocusNew {
+    /// Decide whether to focus a window when it is mapped. Modal dialogs are
+    /// always focused, since a modal dialog without the focus blocks its
+    /// application while the user can't type into it.
+    ///
+    /// `user_time` is the window's _NET_WM_USER_TIME, if it has one, and
+    /// `last_user_time` is the time of the last user input that we saw.
+    pub(crate) fn should_focus(
+        self,
+        modal: bool,
+        user_time: Option<xproto::Timestamp>,
+        last_user_time: xproto::Timestamp,
+    ) -> bool {
+        if modal {
+            return true;
+        }
+        match self {
+            FocusNew::Always => true,
+            FocusNew::Never => false,
+            FocusNew::Smart => match user_time {
+                // Without a user time, we can't tell, so let it have the focus.
+                None => true,
+                // EWMH: a user time of zero means that the window mustn't be
+                // focused when it's mapped.
+                Some(0) => false,
+                // Timestamps wrap around, so compare them as in the X protocol.
+                Some(time) => time.wrapping_sub(last_user_time) as i32 >= 0,
+            },
+        }
+    }
+}
+
+/// An RGB color, written as "#rrggbb" in the config file.
+#[derive(PartialEq, Eq, Clone, Copy, Hash, Debug, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub(crate) struct Color {
+    /// Red component.
+    pub(crate) red: u8,
+    /// Green component.
+    pub(crate) green: u8,
+    /// Blue component.
+    pub(crate) blue: u8,
+}
+
+impl TryFrom<String> for Color {
+    type Error = ConfigError;
+
+    fn try_from(value: String) -> std::result::Result<Self, Self::Error> {
+        let hex = match value.strip_prefix('#') {
+            Some(hex) if hex.len() == 6 && hex.chars().all(|c| c.is_ascii_hexdigit()) => hex,
+            _ => return Err(InvalidColor(value)),
+        };
+        let component = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).unwrap();
+        Ok(Color {
+            red: component(0),
+            green: component(2),
+            blue: component(4),
+        })
+    }
+}
+
+impl From<Color> for String {
+    fn from(value: Color) -> String {
+        format!("#{:02x}{:02x}{:02x}", value.red, value.green, value.blue)
+    }
+}
+
+/// A window rule. A rule matches a window if every criterion that it specifies
+/// matches; a rule with no criteria matches every window.
+#[derive(PartialEq, Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub(crate) struct Rule {
+    /// Match on the class part of WM_CLASS.
+    pub(crate) class: Option<String>,
+    /// Match on the instance part of WM_CLASS.
+    pub(crate) instance: Option<String>,
+    /// Match on WM_WINDOW_ROLE.
+    pub(crate) role: Option<String>,
+    /// Center matching windows on the screen.
+    pub(crate) center: bool,
+    /// Set the opacity of matching windows, from 0 (transparent) to 1
+    /// (opaque). This only has an effect with a compositor running.
+    pub(crate) opacity: Option<f64>,
+}
+
+impl Rule {
+    /// Check whether this rule matches a client.
+    pub(crate) fn matches(&self, st: &ClientState) -> bool {
+        let class = st.wm_class.as_ref();
+        let role = st.wm_window_role.as_deref();
+        Self::criterion(&self.class, class.map(|c| c.class.as_str()))
+            && Self::criterion(&self.instance, class.map(|c| c.instance.as_str()))
+            && Self::criterion(&self.role, role)
+    }
+
+    /// Check a single criterion against a property value. Unspecified
+    /// criteria always match; specified criteria never match a missing value.
+    fn criterion(criterion: &Option<String>, value: Option<&str>) -> bool {
+        match criterion {
+            None => true,
+            Some(criterion) => value == Some(criterion.as_str()),
+        }
+    }
+}
+
 /// Type of OxWM configs. Has to be parameterized by the connection type,
 /// because Rust doesn't have higher-rank types yet.
 #[derive(Clone, Deserialize, Serialize)]
@@ -89,18 +364,110 @@ pub enum FocusModel {
 pub(crate) struct Config<Conn> {
     /// Startup programs.
     pub(crate) startup: Vec<String>,
+    /// Programs to run instead of `startup` when OxWM has been restarted.
+    pub(crate) restart_startup: Vec<String>,
     /// Global modifier key mask.
     #[serde(deserialize_with = "deserialize_xproto_modmask")]
     #[serde(serialize_with = "serialize_xproto_modmask")]
     pub(crate) mod_mask: xproto::ModMask,
     /// Focus model.
     pub(crate) focus_model: FocusModel,
+    /// Whether to focus newly-mapped windows.
+    pub(crate) focus_new_windows: FocusNew,
+    /// How long, in milliseconds, a window has to keep the pointer focus
+    /// before it's automatically raised. Zero disables auto-raising.
+    pub(crate) auto_raise_ms: u64,
+    /// Whether to draw a status line at the top of the root window.
+    pub(crate) builtin_bar: bool,
+    /// Whether to grab the server while setting up, so that setup is atomic.
+    pub(crate) grab_server_on_init: bool,
+    /// Gap, in pixels, to leave between a pinned window and the screen edges.
+    pub(crate) pin_margin: u16,
+    /// Whether to hide the border when only one window is visible.
+    pub(crate) smart_borders: bool,
+    /// Window sizes for `cycle_size`, as "WIDTHxHEIGHT" strings.
+    #[serde(rename = "size_presets")]
+    pub(crate) size_preset_names: Vec<String>,
+    /// Window sizes for `cycle_size`, parsed from `size_preset_names`.
+    #[serde(skip)]
+    pub(crate) size_presets: Vec<(u32, u32)>,
+    /// Width of the border that we give managed windows.
+    pub(crate) border_width: u16,
+    /// Width of the border in millimeters, which overrides `border_width` so
+    /// that borders are equally thick on screens of any resolution.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) border_width_mm: Option<f64>,
+    /// Border color for managed windows.
+    pub(crate) border_color: Color,
+    /// Border color for windows that are locked in place.
+    pub(crate) locked_border_color: Color,
+    /// Border color for windows that are demanding attention.
+    pub(crate) urgent_border_color: Color,
+    /// A solid color to fill the root window with at startup, until a
+    /// wallpaper tool sets a background of its own.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) background_color: Option<Color>,
+    /// The core X font used by the built-in status line.
+    pub(crate) font: String,
+    /// The top-level keys that the config file actually set, so that we know
+    /// which settings the resource database mustn't override.
+    #[serde(skip)]
+    pub(crate) explicit_keys: HashSet<String>,
+    /// Where to grab keybinds: on the root window, or on each managed window.
+    pub(crate) keybind_scope: KeybindScope,
+    /// Whether to close transient windows (e.g. dialogs) when the window that
+    /// they're transient for is destroyed.
+    pub(crate) close_orphan_transients: bool,
+    /// How long to wait, in milliseconds, after running startup programs
+    /// before checking whether any of them failed (0 to skip the check).
+    pub(crate) startup_check_ms: u64,
+    /// Whether to keep track of the geometry of override-redirect windows too,
+    /// so that `ignore_enter_under_popup` can tell which windows they cover.
+    pub(crate) track_override_redirect: bool,
+    /// With autofocus, whether to ignore the pointer entering windows while an
+    /// override-redirect window (usually a menu) is on top. If its geometry is
+    /// tracked, only windows that it overlaps are affected.
+    pub(crate) ignore_enter_under_popup: bool,
+    /// Whether the quit binding has to be pressed twice in quick succession to quit.
+    pub(crate) quit_confirm: bool,
+    /// The name of the cursor (from the X cursor font) to show over the root window.
+    pub(crate) root_cursor: String,
+    /// An action to run when the focused window goes away and there's nothing
+    /// else to focus, or an empty string for none.
+    pub(crate) no_window_action: String,
+    /// Whether to clamp the sizes that managed windows ask for to their size
+    /// hints (or our defaults). If this is off, they get exactly what they ask
+    /// for.
+    pub(crate) clamp_configure_requests: bool,
+    /// Whether the click that focuses a window is passed on to it, as well.
+    pub(crate) click_to_focus_passes_click: bool,
+    /// Whether `focus_left` and friends wrap around to the far side of the
+    /// screen when there's no window in that direction.
+    pub(crate) focus_direction_wraps: bool,
+    /// Whether to reconnect to the X server and start over if the connection to it
+    /// is lost, rather than exiting.
+    pub(crate) reconnect: bool,
+    /// How to decode window titles given as plain STRINGs, which legacy clients
+    /// may have written in their locale's encoding rather than Latin-1.
+    pub(crate) title_encoding: TitleEncoding,
     /// Active keybinds for running window manager.
     #[serde(skip)]
     pub(crate) keybinds: HashMap<xproto::Keycode, Action<Conn>>,
     /// Keybinds as represented in Config.toml.
     #[serde(rename = "keybinds")]
     pub(crate) keybind_names: HashMap<String, String>,
+    /// Active keybinds for prefix mode, entered with the `prefix` action. These
+    /// keys are pressed on their own, without `mod_mask`.
+    #[serde(skip)]
+    pub(crate) prefix_keybinds: HashMap<xproto::Keycode, Action<Conn>>,
+    /// Prefix mode keybinds as represented in Config.toml.
+    #[serde(rename = "prefix_keybinds", skip_serializing_if = "HashMap::is_empty")]
+    pub(crate) prefix_keybind_names: HashMap<String, String>,
+    /// Window rules. These are serialized as an array of tables, so they have
+    /// to come last; and an empty array would be a value after a table, so
+    /// it's skipped.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub(crate) rules: Vec<Rule>,
 }
 
 /// Deserialize an xproto::ModMask value by first deserializing into a
@@ -128,6 +495,51 @@ where
     modm.serialize(serializer)
 }
 
+/// Parse a window size written as "WIDTHxHEIGHT". Both dimensions must be
+/// positive.
+fn parse_size(s: &str) -> Option<(u32, u32)> {
+    let mut parts = s.splitn(2, 'x');
+    let width: u32 = parts.next()?.trim().parse().ok()?;
+    let height: u32 = parts.next()?.trim().parse().ok()?;
+    if width == 0 || height == 0 {
+        return None;
+    }
+    Some((width, height))
+}
+
+/// The width in pixels of a border `mm` millimeters wide, on a screen
+/// `width_in_pixels` wide and `width_in_millimeters` wide physically. Returns
+/// `None` if the physical width is unknown. A border that would round down to
+/// nothing is kept one pixel wide.
+fn border_width_from_mm(mm: f64, width_in_pixels: u16, width_in_millimeters: u16) -> Option<u16> {
+    if width_in_millimeters == 0 {
+        return None;
+    }
+    if mm <= 0.0 {
+        return Some(0);
+    }
+    let pixels = mm * f64::from(width_in_pixels) / f64::from(width_in_millimeters);
+    Some(pixels.round().clamp(1.0, f64::from(u16::MAX)) as u16)
+}
+
+/// Parse the contents of the X resource database (the RESOURCE_MANAGER
+/// property), picking out the resources for OxWM, e.g. `OxWM.borderColor`.
+/// Returns a map from resource names, without the `OxWM.` prefix, to values.
+pub(crate) fn parse_resources(db: &str) -> HashMap<String, String> {
+    db.lines()
+        .filter(|line| !line.starts_with('!'))
+        .filter_map(|line| {
+            let mut parts = line.splitn(2, ':');
+            let name = parts.next()?.trim();
+            let value = parts.next()?.trim();
+            let name = name
+                .strip_prefix("OxWM.")
+                .or_else(|| name.strip_prefix("oxwm."))?;
+            Some((name.to_string(), value.to_string()))
+        })
+        .collect()
+}
+
 /// An error indicating that we can't find the user's config directory.
 #[derive(
     PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug, Hash, Error, Deserialize, Serialize,
@@ -149,7 +561,7 @@ pub(crate) struct ConfigDirAccessError;
 impl<Conn> Config<Conn> {
     /// Load the config file, or return a default config object if there is no
     /// config file.
-    pub(crate) fn load() -> Result<Self>
+    pub(crate) fn load(purpose: Purpose) -> Result<Self>
     where
         Conn: Connection,
     {
@@ -158,65 +570,215 @@ impl<Conn> Config<Conn> {
         let mut path = dirs::config_dir().ok_or(UnsupportedPlatformError)?;
         path.push("oxwm");
         path.push("config.toml");
-        Self::from_path(&path)
+        Self::from_path(&path, purpose)
     }
 
     /// Load a specified config file.
-    fn from_path(path: &Path) -> Result<Self>
+    fn from_path(path: &Path, purpose: Purpose) -> Result<Self>
     where
         Conn: Connection,
     {
         let s = fs::read_to_string(path)?;
-        Self::from_str(&s)
+        Self::from_str(&s, purpose)
     }
 
     /// Parse a string directly.
-    fn from_str(s: &str) -> Result<Self>
+    fn from_str(s: &str, purpose: Purpose) -> Result<Self>
     where
         Conn: Connection,
     {
         let mut ret: Self = toml::from_str(s)?;
-        ret.translate_keybinds()?;
+        let table: toml::value::Table = toml::from_str(s)?;
+        ret.explicit_keys = table.keys().cloned().collect();
+        ret.translate_keybinds(purpose)?;
+        ret.translate_size_presets()?;
+        ret.check_no_window_action()?;
         Ok(ret)
     }
 
-    /// Populate `self.keybinds` with Keycodes and `Action<Conn>` fn pointers
-    /// that match the Keysyms and action names found in `self.keybind_names`.
-    fn translate_keybinds(&mut self) -> Result<()>
+    /// Make sure that `no_window_action` names an action, if it's set.
+    fn check_no_window_action(&self) -> std::result::Result<(), ConfigError>
     where
         Conn: Connection,
     {
-        for (key_name, action_name) in &self.keybind_names {
-            let keycode = match keysym_from_name(&key_name) {
-                None => Err(KeysymError(key_name.clone())),
-                Some(key_sym) => match keycode_from_keysym(key_sym) {
-                    None => Err(KeycodeError(key_name.clone(), key_sym)),
-                    Some(key_code) => Ok(key_code),
-                },
-            }?;
-            let action: std::result::Result<Action<Conn>, ConfigError> = match action_name.as_str()
-            {
-                "quit" => Ok(OxWM::poison),
-                "kill" => Ok(OxWM::kill_focused_client),
-                _ => Err(InvalidAction(action_name.clone())),
-            };
+        if !self.no_window_action.is_empty() && self.action(&self.no_window_action).is_none() {
+            return Err(InvalidAction(self.no_window_action.clone()));
+        }
+        Ok(())
+    }
 
-            self.keybinds.insert(keycode, action?);
+    /// Look up an action by name.
+    pub(crate) fn action(&self, name: &str) -> Option<Action<Conn>>
+    where
+        Conn: Connection,
+    {
+        actions::<Conn>()
+            .into_iter()
+            .find(|&(action_name, _)| action_name == name)
+            .map(|(_, action)| action)
+    }
+
+    /// Populate `self.keybinds` and `self.prefix_keybinds` with Keycodes and
+    /// `Action<Conn>` fn pointers that match the Keysyms and action names found
+    /// in `self.keybind_names` and `self.prefix_keybind_names`. Unless we're
+    /// going to run, the names are only checked, and nothing is bound.
+    fn translate_keybinds(&mut self, purpose: Purpose) -> Result<()>
+    where
+        Conn: Connection,
+    {
+        self.keybinds = self.translate_keybind_names(&self.keybind_names, purpose)?;
+        self.prefix_keybinds = self.translate_keybind_names(&self.prefix_keybind_names, purpose)?;
+        Ok(())
+    }
+
+    /// Map keybinds from Keysym and action names to Keycodes and
+    /// `Action<Conn>` fn pointers.
+    fn translate_keybind_names(
+        &self,
+        keybind_names: &HashMap<String, String>,
+        purpose: Purpose,
+    ) -> Result<HashMap<xproto::Keycode, Action<Conn>>>
+    where
+        Conn: Connection,
+    {
+        let mut keybinds = HashMap::new();
+        for (key_name, action_name) in keybind_names {
+            let key_sym =
+                keysym_from_name(&key_name).ok_or_else(|| KeysymError(key_name.clone()))?;
+            let action = self
+                .action(action_name)
+                .ok_or_else(|| InvalidAction(action_name.clone()))?;
+            if purpose != Purpose::Run {
+                continue;
+            }
+            let keycode = keycode_from_keysym(key_sym)
+                .ok_or_else(|| KeycodeError(key_name.clone(), key_sym))?;
+
+            keybinds.insert(keycode, action);
         }
+        Ok(keybinds)
+    }
+
+    /// Parse the size presets from `size_preset_names`.
+    fn translate_size_presets(&mut self) -> std::result::Result<(), ConfigError> {
+        self.size_presets = self
+            .size_preset_names
+            .iter()
+            .map(|name| parse_size(name).ok_or_else(|| InvalidSize(name.clone())))
+            .collect::<std::result::Result<_, _>>()?;
         Ok(())
     }
 
+    /// Fill in settings that the config file didn't set from the X resource
+    /// database, as parsed by `parse_resources`. Invalid values are logged and
+    /// ignored.
+    pub(crate) fn apply_resources(&mut self, resources: &HashMap<String, String>) {
+        for (resource, value) in resources {
+            let key = match resource.as_str() {
+                "borderColor" => "border_color",
+                "lockedBorderColor" => "locked_border_color",
+                "urgentBorderColor" => "urgent_border_color",
+                "borderWidth" => "border_width",
+                "font" => "font",
+                _ => continue,
+            };
+            if self.explicit_keys.contains(key) {
+                continue;
+            }
+            let result = match key {
+                "border_color" => Color::try_from(value.clone())
+                    .map(|color| self.border_color = color)
+                    .map_err(|_| ()),
+                "locked_border_color" => Color::try_from(value.clone())
+                    .map(|color| self.locked_border_color = color)
+                    .map_err(|_| ()),
+                "urgent_border_color" => Color::try_from(value.clone())
+                    .map(|color| self.urgent_border_color = color)
+                    .map_err(|_| ()),
+                "border_width" => value
+                    .parse()
+                    .map(|width| self.border_width = width)
+                    .map_err(|_| ()),
+                _ => {
+                    self.font = value.clone();
+                    Ok(())
+                }
+            };
+            if result.is_err() {
+                log::warn!("Ignoring invalid resource OxWM.{}: {}", resource, value);
+            }
+        }
+    }
+
+    /// The last-resort config, for when the user's can't be used. This is the
+    /// default config, unless even its keybinds can't be bound, in which case
+    /// it has no keybinds at all.
+    pub(crate) fn built_in(purpose: Purpose) -> Self
+    where
+        Conn: Connection,
+    {
+        Self::new(purpose).unwrap_or_else(|err| {
+            log::error!("{}", err);
+            let mut config = Self::new_core();
+            config.keybind_names.clear();
+            config.prefix_keybind_names.clear();
+            config
+        })
+    }
+
+    /// Take the settings that only affect how windows look (border widths and
+    /// colors) from another config, leaving everything else alone.
+    pub(crate) fn take_appearance(&mut self, other: &Config<Conn>) {
+        self.border_width = other.border_width;
+        self.border_width_mm = other.border_width_mm;
+        self.border_color = other.border_color;
+        self.locked_border_color = other.locked_border_color;
+        self.urgent_border_color = other.urgent_border_color;
+    }
+
+    /// Work out the border width for a screen of the given width from
+    /// `border_width_mm`, if it's set. If it isn't, or the screen's physical
+    /// size is unknown, this is just `border_width`.
+    pub(crate) fn scaled_border_width(
+        &self,
+        width_in_pixels: u16,
+        width_in_millimeters: u16,
+    ) -> u16 {
+        let mm = match self.border_width_mm {
+            Some(mm) => mm,
+            None => return self.border_width,
+        };
+        border_width_from_mm(mm, width_in_pixels, width_in_millimeters).unwrap_or_else(|| {
+            log::warn!(
+                "The screen's physical size is unknown; using border_width instead of \
+                 border_width_mm."
+            );
+            self.border_width
+        })
+    }
+
     /// Instantiate a default config which opens an xterm at startup, changes
     /// focus on mouse click, kills windows with Mod4 + w, and exits with Mod4 + Q.
-    pub fn new() -> Result<Self>
+    pub fn new(purpose: Purpose) -> Result<Self>
     where
         Conn: Connection,
     {
         let mut ret = Config::new_core();
-        ret.translate_keybinds()?;
+        ret.translate_keybinds(purpose)?;
+        ret.translate_size_presets()?;
         Ok(ret)
     }
 
+    /// Get the programs that should be run at startup, depending on whether
+    /// this is a fresh start or a restart.
+    pub(crate) fn startup_programs(&self, restarted: bool) -> &[String] {
+        if restarted {
+            &self.restart_startup
+        } else {
+            &self.startup
+        }
+    }
+
     /// Instantiates a Config with default settings, but does NOT attempt to bind
     /// Keycodes and `Action<Conn>` fn pointers into the `keybinds` field.
     /// Used by `Config::new`. Also used by derive[(Serialize)] on Config to fill in
@@ -229,8 +791,54 @@ impl<Conn> Config<Conn> {
     /// function of the returned Config to populate the keybind field.
     fn new_core() -> Self {
         let startup: Vec<String> = vec!["xterm".to_string()];
+        let restart_startup: Vec<String> = Vec::new();
         let mod_mask = ModMask::Mod4.into();
         let focus_model = FocusModel::Click;
+        let focus_new_windows = FocusNew::Never;
+        let auto_raise_ms = 0;
+        let builtin_bar = false;
+        let grab_server_on_init = true;
+        let pin_margin = 0;
+        let smart_borders = false;
+        let size_preset_names = vec![
+            "640x480".to_string(),
+            "1280x720".to_string(),
+            "1920x1080".to_string(),
+        ];
+        // Populated by translate_size_presets.
+        let size_presets = Vec::new();
+        let border_width = 1;
+        let border_color = Color {
+            red: 0x44,
+            green: 0x44,
+            blue: 0x44,
+        };
+        let locked_border_color = Color {
+            red: 0xcc,
+            green: 0x33,
+            blue: 0x33,
+        };
+        let urgent_border_color = Color {
+            red: 0xdd,
+            green: 0x99,
+            blue: 0x00,
+        };
+        let font = "fixed".to_string();
+        let explicit_keys = HashSet::new();
+        let keybind_scope = KeybindScope::Global;
+        let close_orphan_transients = false;
+        let startup_check_ms = 0;
+        let track_override_redirect = false;
+        let ignore_enter_under_popup = false;
+        let quit_confirm = false;
+        let root_cursor = "left_ptr".to_string();
+        let no_window_action = String::new();
+        let clamp_configure_requests = true;
+        let click_to_focus_passes_click = true;
+        let focus_direction_wraps = false;
+        let reconnect = false;
+        let title_encoding = TitleEncoding::Auto;
+        let rules = Vec::new();
 
         // Deliberately left unpopulated, callers are expected to call the new
         // Config object's translate_keybinds method to populate keybinds before use.
@@ -240,13 +848,51 @@ impl<Conn> Config<Conn> {
         keybind_names.insert("q".to_string(), "kill".to_string());
         Self {
             startup,
+            restart_startup,
             mod_mask,
             focus_model,
+            focus_new_windows,
+            auto_raise_ms,
+            builtin_bar,
+            grab_server_on_init,
+            pin_margin,
+            smart_borders,
+            size_preset_names,
+            size_presets,
+            border_width,
+            border_width_mm: None,
+            background_color: None,
+            border_color,
+            locked_border_color,
+            urgent_border_color,
+            font,
+            explicit_keys,
+            keybind_scope,
+            close_orphan_transients,
+            startup_check_ms,
+            track_override_redirect,
+            ignore_enter_under_popup,
+            quit_confirm,
+            root_cursor,
+            no_window_action,
+            clamp_configure_requests,
+            click_to_focus_passes_click,
+            focus_direction_wraps,
+            reconnect,
+            title_encoding,
             keybinds,
             keybind_names,
+            prefix_keybinds: HashMap::new(),
+            prefix_keybind_names: HashMap::new(),
+            rules,
         }
     }
 
+    /// Serialize the config as it would appear in config.toml.
+    pub(crate) fn to_toml(&self) -> Result<String> {
+        Ok(toml::to_string(self)?)
+    }
+
     /// Write the config in .toml format to the default location:
     /// `<config directory>/oxwm/config.toml`
     /// where `config_directory` is the location returned by `dirs::config_dir()`.
@@ -278,7 +924,7 @@ impl<Conn> Config<Conn> {
 
         //Create or overwrite existing config.toml
         path.push("config.toml");
-        fs::write(&path, toml::to_string(&self)?)?;
+        fs::write(&path, self.to_toml()?)?;
         log::info!("Saved configuration file to {}.", path.display());
 
         Ok(())
@@ -294,6 +940,12 @@ pub(crate) enum ConfigError {
     KeycodeError(String, xproto::Keysym),
     #[error("Invalid action \"{0}\" found in your Config.toml")]
     InvalidAction(String),
+    #[error("Invalid color \"{0}\" found in your Config.toml (expected e.g. \"#cc3333\")")]
+    InvalidColor(String),
+    #[error("Invalid size preset \"{0}\" found in your Config.toml (expected e.g. \"1280x720\")")]
+    InvalidSize(String),
+    #[error("Unknown title encoding \"{0}\" found in your Config.toml (expected e.g. \"auto\" or \"shift_jis\")")]
+    InvalidEncoding(String),
 }
 use ConfigError::*;
 
@@ -362,9 +1014,9 @@ fn check_deserialize_defaults() {
 #[test]
 fn check_serialize() {
     let good_toml =
-        "startup = [\"xterm\", \"xclock\"]\nmod_mask = \"mod4\"\nfocus_model = \"click\"\n\n[keybinds]\nw = \"kill\"\nq = \"quit\"\n";
+        "startup = [\"xterm\", \"xclock\"]\nrestart_startup = []\nmod_mask = \"mod4\"\nfocus_model = \"click\"\nfocus_new_windows = \"never\"\nauto_raise_ms = 0\nbuiltin_bar = false\ngrab_server_on_init = true\npin_margin = 0\nsmart_borders = false\nsize_presets = [\"640x480\", \"1280x720\", \"1920x1080\"]\nborder_width = 1\nborder_color = \"#444444\"\nlocked_border_color = \"#cc3333\"\nurgent_border_color = \"#dd9900\"\nfont = \"fixed\"\nkeybind_scope = \"global\"\nclose_orphan_transients = false\nstartup_check_ms = 0\ntrack_override_redirect = false\nignore_enter_under_popup = false\nquit_confirm = false\nroot_cursor = \"left_ptr\"\nno_window_action = \"\"\nclamp_configure_requests = true\nclick_to_focus_passes_click = true\nfocus_direction_wraps = false\nreconnect = false\ntitle_encoding = \"auto\"\n\n[keybinds]\nw = \"kill\"\nq = \"quit\"\n";
     let alternate_toml =
-        "startup = [\"xterm\", \"xclock\"]\nmod_mask = \"mod4\"\nfocus_model = \"click\"\n\n[keybinds]\nq = \"quit\"\nw = \"kill\"\n";
+        "startup = [\"xterm\", \"xclock\"]\nrestart_startup = []\nmod_mask = \"mod4\"\nfocus_model = \"click\"\nfocus_new_windows = \"never\"\nauto_raise_ms = 0\nbuiltin_bar = false\ngrab_server_on_init = true\npin_margin = 0\nsmart_borders = false\nsize_presets = [\"640x480\", \"1280x720\", \"1920x1080\"]\nborder_width = 1\nborder_color = \"#444444\"\nlocked_border_color = \"#cc3333\"\nurgent_border_color = \"#dd9900\"\nfont = \"fixed\"\nkeybind_scope = \"global\"\nclose_orphan_transients = false\nstartup_check_ms = 0\ntrack_override_redirect = false\nignore_enter_under_popup = false\nquit_confirm = false\nroot_cursor = \"left_ptr\"\nno_window_action = \"\"\nclamp_configure_requests = true\nclick_to_focus_passes_click = true\nfocus_direction_wraps = false\nreconnect = false\ntitle_encoding = \"auto\"\n\n[keybinds]\nq = \"quit\"\nw = \"kill\"\n";
     let response_1: std::result::Result<
         Config<x11rb::rust_connection::RustConnection>,
         toml::de::Error,
@@ -412,3 +1064,391 @@ fn check_deserialize_errors() {
     > = toml::from_str(any_mask_toml);
     assert!(response_3.is_err());
 }
+
+/// Confirm that a restart runs `restart_startup` rather than `startup`.
+#[test]
+fn check_startup_programs() {
+    let toml = "startup = [\"xterm\", \"xclock\"]\nrestart_startup = [\"xclock\"]\n";
+    let a_config: Config<x11rb::rust_connection::RustConnection> = toml::from_str(toml).unwrap();
+    assert_eq!(a_config.startup_programs(false), ["xterm", "xclock"]);
+    assert_eq!(a_config.startup_programs(true), ["xclock"]);
+
+    // By default, nothing is run on restart.
+    let a_config: Config<x11rb::rust_connection::RustConnection> = toml::from_str("").unwrap();
+    assert_eq!(a_config.startup_programs(false), ["xterm"]);
+    assert!(a_config.startup_programs(true).is_empty());
+}
+
+/// Confirm that rules can be deserialized, and that they match on class,
+/// instance, and role.
+#[test]
+fn check_rule_matching() {
+    use crate::atom::WmClass;
+
+    let toml = "[[rules]]\nclass = \"Firefox\"\nrole = \"Preferences\"\ncenter = true\n\n[[rules]]\ninstance = \"xterm\"\nopacity = 0.9\n";
+    let a_config: Config<x11rb::rust_connection::RustConnection> = toml::from_str(toml).unwrap();
+    assert_eq!(a_config.rules.len(), 2);
+    let prefs = &a_config.rules[0];
+    assert_eq!(prefs.class.as_deref(), Some("Firefox"));
+    assert_eq!(prefs.instance, None);
+    assert_eq!(prefs.role.as_deref(), Some("Preferences"));
+    assert!(prefs.center);
+    assert_eq!(prefs.opacity, None);
+    let xterm = &a_config.rules[1];
+    assert!(!xterm.center);
+    assert_eq!(xterm.opacity, Some(0.9));
+
+    let mut st = crate::client::dummy_client(100, true).state.unwrap();
+    st.wm_class = Some(WmClass {
+        instance: "navigator".to_string(),
+        class: "Firefox".to_string(),
+    });
+    st.wm_window_role = Some("browser".to_string());
+    assert!(!prefs.matches(&st));
+    assert!(!xterm.matches(&st));
+    st.wm_window_role = Some("Preferences".to_string());
+    assert!(prefs.matches(&st));
+    st.wm_window_role = None;
+    assert!(!prefs.matches(&st));
+    assert!(Rule::default().matches(&st));
+}
+
+/// Confirm that the server grab during setup is on unless disabled.
+#[test]
+fn check_grab_server_on_init() {
+    let a_config: Config<x11rb::rust_connection::RustConnection> = toml::from_str("").unwrap();
+    assert!(a_config.grab_server_on_init);
+    let a_config: Config<x11rb::rust_connection::RustConnection> =
+        toml::from_str("grab_server_on_init = false\n").unwrap();
+    assert!(!a_config.grab_server_on_init);
+}
+
+/// Confirm that modal windows are focused on map regardless of the policy.
+#[test]
+fn check_focus_new_windows() {
+    let a_config: Config<x11rb::rust_connection::RustConnection> = toml::from_str("").unwrap();
+    assert_eq!(a_config.focus_new_windows, FocusNew::Never);
+    let a_config: Config<x11rb::rust_connection::RustConnection> =
+        toml::from_str("focus_new_windows = \"always\"\n").unwrap();
+    assert_eq!(a_config.focus_new_windows, FocusNew::Always);
+
+    assert!(FocusNew::Always.should_focus(false, None, 0));
+    assert!(FocusNew::Always.should_focus(true, None, 0));
+    assert!(!FocusNew::Never.should_focus(false, None, 0));
+    assert!(FocusNew::Never.should_focus(true, None, 0));
+    assert!(FocusNew::Smart.should_focus(true, Some(0), 1000));
+}
+
+/// Confirm that the smart focus-new policy compares user times correctly.
+#[test]
+fn check_focus_new_smart() {
+    let smart =
+        |user_time, last_user_time| FocusNew::Smart.should_focus(false, user_time, last_user_time);
+    assert!(smart(None, 1000));
+    assert!(!smart(Some(0), 1000));
+    assert!(smart(Some(1500), 1000));
+    assert!(smart(Some(1000), 1000));
+    assert!(!smart(Some(500), 1000));
+    // Across the wraparound of the server's clock.
+    assert!(smart(Some(10), u32::MAX - 10));
+    assert!(!smart(Some(u32::MAX - 10), 10));
+}
+
+/// Confirm that size presets are parsed, and that malformed ones are rejected.
+#[test]
+fn check_size_presets() {
+    assert_eq!(parse_size("640x480"), Some((640, 480)));
+    assert_eq!(parse_size("1920x1080"), Some((1920, 1080)));
+    assert_eq!(parse_size("640"), None);
+    assert_eq!(parse_size("640x"), None);
+    assert_eq!(parse_size("x480"), None);
+    assert_eq!(parse_size("0x480"), None);
+    assert_eq!(parse_size("640x480x2"), None);
+    assert_eq!(parse_size("wide x tall"), None);
+
+    let mut a_config: Config<x11rb::rust_connection::RustConnection> =
+        toml::from_str("size_presets = [\"800x600\", \"1024x768\"]\n").unwrap();
+    a_config.translate_size_presets().unwrap();
+    assert_eq!(a_config.size_presets, vec![(800, 600), (1024, 768)]);
+
+    let mut a_config: Config<x11rb::rust_connection::RustConnection> =
+        toml::from_str("size_presets = [\"800x600\", \"big\"]\n").unwrap();
+    assert_eq!(
+        a_config.translate_size_presets(),
+        Err(InvalidSize("big".to_string()))
+    );
+}
+
+/// Confirm that colors are parsed from and written as "#rrggbb".
+#[test]
+fn check_color() {
+    let color = Color::try_from("#cc3399".to_string()).unwrap();
+    assert_eq!(
+        color,
+        Color {
+            red: 0xcc,
+            green: 0x33,
+            blue: 0x99,
+        }
+    );
+    assert_eq!(String::from(color), "#cc3399");
+    assert_eq!(Color::try_from("#CC3399".to_string()), Ok(color));
+    for bad in &["cc3399", "#cc339", "#cc33999", "#cc33zz", "red", ""] {
+        assert_eq!(
+            Color::try_from(bad.to_string()),
+            Err(InvalidColor(bad.to_string()))
+        );
+    }
+    let response: std::result::Result<
+        Config<x11rb::rust_connection::RustConnection>,
+        toml::de::Error,
+    > = toml::from_str("border_color = \"blue\"\n");
+    assert!(response.is_err());
+}
+
+/// Confirm that OxWM's resources are picked out of the resource database.
+#[test]
+fn check_parse_resources() {
+    let db = "*background:\t#000000\n\
+              ! OxWM.font: commented out\n\
+              OxWM.borderColor:\t#ff0000\n\
+              oxwm.borderWidth: 3\n\
+              OxWM.font:  -misc-fixed-medium-r-*-*-13-*-*-*-*-*-*-*  \n\
+              XTerm*faceName:\tMonospace\n\
+              garbage\n";
+    let resources = parse_resources(db);
+    assert_eq!(resources.len(), 3);
+    assert_eq!(resources["borderColor"], "#ff0000");
+    assert_eq!(resources["borderWidth"], "3");
+    assert_eq!(
+        resources["font"],
+        "-misc-fixed-medium-r-*-*-13-*-*-*-*-*-*-*"
+    );
+}
+
+/// Confirm that settings from the config file take precedence over resources.
+#[test]
+fn check_apply_resources() {
+    let resources = parse_resources("OxWM.borderColor: #ff0000\nOxWM.borderWidth: 3\nOxWM.font: bogus-but-fine\nOxWM.lockedBorderColor: nope\n");
+    let mut a_config: Config<x11rb::rust_connection::RustConnection> = Config::new_core();
+    a_config.apply_resources(&resources);
+    assert_eq!(String::from(a_config.border_color), "#ff0000");
+    assert_eq!(a_config.border_width, 3);
+    assert_eq!(a_config.font, "bogus-but-fine");
+    assert_eq!(String::from(a_config.locked_border_color), "#cc3333");
+
+    let mut a_config: Config<x11rb::rust_connection::RustConnection> =
+        toml::from_str("border_width = 5\n").unwrap();
+    a_config.explicit_keys.insert("border_width".to_string());
+    a_config.apply_resources(&resources);
+    assert_eq!(a_config.border_width, 5);
+    assert_eq!(String::from(a_config.border_color), "#ff0000");
+}
+
+/// Confirm that the title encoding defaults to auto, that named encodings are
+/// looked up by label, and that unknown ones are rejected.
+#[test]
+fn check_title_encoding() {
+    type Conn = x11rb::rust_connection::RustConnection;
+    let a_config: Config<Conn> = Config::new_core();
+    assert_eq!(a_config.title_encoding, TitleEncoding::Auto);
+    let a_config: Config<Conn> = toml::from_str("title_encoding = \"latin1\"\n").unwrap();
+    assert_eq!(a_config.title_encoding, TitleEncoding::Latin1);
+    let a_config: Config<Conn> = toml::from_str("title_encoding = \"utf8\"\n").unwrap();
+    assert_eq!(a_config.title_encoding, TitleEncoding::Utf8);
+    let a_config: Config<Conn> = toml::from_str("title_encoding = \"koi8-r\"\n").unwrap();
+    assert_eq!(
+        a_config.title_encoding,
+        TitleEncoding::Named(encoding_rs::KOI8_R)
+    );
+    let a_config: Config<Conn> = toml::from_str("title_encoding = \"SJIS\"\n").unwrap();
+    assert_eq!(
+        a_config.title_encoding,
+        TitleEncoding::Named(encoding_rs::SHIFT_JIS)
+    );
+    assert!(a_config
+        .to_toml()
+        .unwrap()
+        .contains("title_encoding = \"shift_jis\"\n"));
+    assert!(toml::from_str::<Config<Conn>>("title_encoding = \"klingon\"\n").is_err());
+}
+
+/// Confirm that prefix keybinds are read from their own table, and left out
+/// of the config file when there aren't any.
+#[test]
+fn check_prefix_keybinds() {
+    type Conn = x11rb::rust_connection::RustConnection;
+    let toml = "[keybinds]\nspace = \"prefix\"\n\n[prefix_keybinds]\nz = \"toggle_zoom_focus\"\n";
+    let a_config: Config<Conn> = toml::from_str(toml).unwrap();
+    assert_eq!(a_config.keybind_names["space"], "prefix");
+    assert_eq!(a_config.prefix_keybind_names["z"], "toggle_zoom_focus");
+    assert_eq!(a_config.prefix_keybind_names.len(), 1);
+    assert!(a_config.action("prefix").is_some());
+
+    let a_config: Config<Conn> = Config::new_core();
+    assert!(a_config.prefix_keybind_names.is_empty());
+    assert!(!a_config.to_toml().unwrap().contains("prefix_keybinds"));
+}
+
+/// Confirm that border widths in millimeters are converted to pixels by the
+/// screen's resolution, and that an unknown physical size is detected.
+#[test]
+fn check_border_width_from_mm() {
+    // 1920 pixels across 508 millimeters is 96 DPI.
+    assert_eq!(border_width_from_mm(0.5, 1920, 508), Some(2));
+    // 3840 pixels across the same width is 192 DPI.
+    assert_eq!(border_width_from_mm(0.5, 3840, 508), Some(4));
+    // Borders never disappear because they're thin.
+    assert_eq!(border_width_from_mm(0.01, 1920, 508), Some(1));
+    assert_eq!(border_width_from_mm(0.0, 1920, 508), Some(0));
+    assert_eq!(border_width_from_mm(0.5, 1920, 0), None);
+
+    type Conn = x11rb::rust_connection::RustConnection;
+    let a_config: Config<Conn> = toml::from_str("border_width_mm = 0.5\n").unwrap();
+    assert_eq!(a_config.scaled_border_width(3840, 508), 4);
+    let a_config: Config<Conn> = toml::from_str("border_width = 3\n").unwrap();
+    assert_eq!(a_config.scaled_border_width(3840, 508), 3);
+}
+
+/// Confirm that toggling the focus model flips it, and that only
+/// focus-follows-mouse selects EnterNotify events on managed windows.
+#[test]
+fn check_focus_model_toggle() {
+    assert_eq!(FocusModel::Click.toggled(), FocusModel::Autofocus);
+    assert_eq!(FocusModel::Autofocus.toggled(), FocusModel::Click);
+    let enter = u32::from(xproto::EventMask::ENTER_WINDOW);
+    let focus = u32::from(xproto::EventMask::FOCUS_CHANGE);
+    let click = u32::from(FocusModel::Click.client_event_mask());
+    let autofocus = u32::from(FocusModel::Autofocus.client_event_mask());
+    assert_eq!(click & enter, 0);
+    assert_eq!(autofocus & enter, enter);
+    assert_eq!(click & focus, focus);
+    assert_eq!(autofocus & !enter, click);
+}
+
+/// Confirm that there's no background color by default, and that one can be
+/// set.
+#[test]
+fn check_background_color() {
+    type Conn = x11rb::rust_connection::RustConnection;
+    let a_config: Config<Conn> = Config::new_core();
+    assert_eq!(a_config.background_color, None);
+    assert!(!a_config.to_toml().unwrap().contains("background_color"));
+    let a_config: Config<Conn> = toml::from_str("background_color = \"#102030\"\n").unwrap();
+    let color = a_config.background_color.unwrap();
+    assert_eq!((color.red, color.green, color.blue), (0x10, 0x20, 0x30));
+    assert!(a_config
+        .to_toml()
+        .unwrap()
+        .contains("background_color = \"#102030\""));
+    assert!(toml::from_str::<Config<Conn>>("background_color = \"teal\"\n").is_err());
+}
+
+/// Confirm that keybinds are grabbed on the root or on managed windows,
+/// depending on the scope.
+#[test]
+fn check_keybind_scope() {
+    let a_config: Config<x11rb::rust_connection::RustConnection> = toml::from_str("").unwrap();
+    assert_eq!(a_config.keybind_scope, KeybindScope::Global);
+    let a_config: Config<x11rb::rust_connection::RustConnection> =
+        toml::from_str("keybind_scope = \"managed\"\n").unwrap();
+    assert_eq!(a_config.keybind_scope, KeybindScope::Managed);
+
+    assert_eq!(KeybindScope::Global.grab_window(1, None), Some(1));
+    assert_eq!(KeybindScope::Global.grab_window(1, Some(100)), None);
+    assert_eq!(KeybindScope::Managed.grab_window(1, None), None);
+    assert_eq!(KeybindScope::Managed.grab_window(1, Some(100)), Some(100));
+}
+
+/// Confirm that the serialized form of a config (as printed by
+/// `--print-config`) parses back into the same config.
+#[test]
+fn check_to_toml_round_trip() {
+    let mut config: Config<x11rb::rust_connection::RustConnection> = Config::new_core();
+    config.border_width = 3;
+    let toml = config.to_toml().unwrap();
+    let parsed: Config<x11rb::rust_connection::RustConnection> = toml::from_str(&toml).unwrap();
+    assert_eq!(parsed.border_width, 3);
+    assert_eq!(parsed.keybind_names, config.keybind_names);
+}
+
+/// Confirm that every action in the table has a distinct name, and that the
+/// default keybinds only use actions that exist.
+#[test]
+fn check_actions() {
+    type Conn = x11rb::rust_connection::RustConnection;
+    let names: Vec<&str> = actions::<Conn>()
+        .into_iter()
+        .map(|(name, _)| name)
+        .collect();
+    let unique: HashSet<&str> = names.iter().cloned().collect();
+    assert_eq!(unique.len(), names.len());
+    assert!(names.contains(&"quit"));
+    assert!(names.contains(&"focus_next_same_class"));
+    assert!(names.contains(&"focus_parent"));
+    assert!(names.contains(&"focus_child"));
+    let config: Config<Conn> = Config::new_core();
+    for action_name in config.keybind_names.values() {
+        assert!(names.contains(&action_name.as_str()), "{}", action_name);
+    }
+}
+
+/// Confirm that `no_window_action` has to name a real action, if it's set.
+#[test]
+fn check_no_window_action() {
+    let mut a_config: Config<x11rb::rust_connection::RustConnection> = Config::new_core();
+    assert!(a_config.check_no_window_action().is_ok());
+    a_config.no_window_action = "restart".to_string();
+    assert!(a_config.check_no_window_action().is_ok());
+    a_config.no_window_action = "launch_rockets".to_string();
+    assert_eq!(
+        a_config.check_no_window_action(),
+        Err(InvalidAction("launch_rockets".to_string()))
+    );
+}
+
+/// Confirm that taking the appearance from another config only changes the
+/// border settings.
+#[test]
+fn check_take_appearance() {
+    type Conn = x11rb::rust_connection::RustConnection;
+    let mut a_config: Config<Conn> = Config::new_core();
+    let mut b_config: Config<Conn> = Config::new_core();
+    b_config.border_width = 5;
+    b_config.border_color = Color::try_from("#ff0000".to_string()).unwrap();
+    b_config.locked_border_color = Color::try_from("#00ff00".to_string()).unwrap();
+    b_config.urgent_border_color = Color::try_from("#0000ff".to_string()).unwrap();
+    b_config.focus_model = FocusModel::Autofocus;
+    b_config.startup = Vec::new();
+    a_config.take_appearance(&b_config);
+    assert_eq!(a_config.border_width, 5);
+    assert_eq!(a_config.border_color, b_config.border_color);
+    assert_eq!(a_config.locked_border_color, b_config.locked_border_color);
+    assert_eq!(a_config.urgent_border_color, b_config.urgent_border_color);
+    assert_eq!(a_config.focus_model, FocusModel::Click);
+    assert_eq!(a_config.startup, vec!["xterm".to_string()]);
+}
+
+/// Confirm that a config loaded for printing has its keybinds checked but not
+/// bound, so that no X server is needed.
+#[test]
+fn check_from_str_for_printing() {
+    type Conn = x11rb::rust_connection::RustConnection;
+    let a_config = Config::<Conn>::from_str("[keybinds]\nw = \"kill\"\n", Purpose::Print).unwrap();
+    assert_eq!(a_config.keybind_names.len(), 1);
+    assert!(a_config.keybinds.is_empty());
+    let err = Config::<Conn>::from_str("[keybinds]\nw = \"launch_rockets\"\n", Purpose::Print)
+        .err()
+        .unwrap();
+    assert_eq!(
+        err.downcast_ref::<ConfigError>(),
+        Some(&InvalidAction("launch_rockets".to_string()))
+    );
+    let err = Config::<Conn>::from_str("[keybinds]\nnot_a_key = \"kill\"\n", Purpose::Print)
+        .err()
+        .unwrap();
+    assert_eq!(
+        err.downcast_ref::<ConfigError>(),
+        Some(&KeysymError("not_a_key".to_string()))
+    );
+}
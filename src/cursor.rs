@@ -0,0 +1,160 @@
+//! Setting the root window's cursor from the core cursor font.
+
+use x11rb::connection::Connection;
+use x11rb::protocol::xproto;
+use x11rb::protocol::xproto::ConnectionExt as _;
+
+use crate::Result;
+
+/// The cursor to fall back on if the configured one doesn't exist.
+const DEFAULT_CURSOR: &str = "left_ptr";
+
+/// The glyphs in the cursor font, as listed in `X11/cursorfont.h`. Each
+/// cursor's mask is the glyph after it.
+const CURSOR_GLYPHS: [(&str, u16); 77] = [
+    ("X_cursor", 0),
+    ("arrow", 2),
+    ("based_arrow_down", 4),
+    ("based_arrow_up", 6),
+    ("boat", 8),
+    ("bogosity", 10),
+    ("bottom_left_corner", 12),
+    ("bottom_right_corner", 14),
+    ("bottom_side", 16),
+    ("bottom_tee", 18),
+    ("box_spiral", 20),
+    ("center_ptr", 22),
+    ("circle", 24),
+    ("clock", 26),
+    ("coffee_mug", 28),
+    ("cross", 30),
+    ("cross_reverse", 32),
+    ("crosshair", 34),
+    ("diamond_cross", 36),
+    ("dot", 38),
+    ("dotbox", 40),
+    ("double_arrow", 42),
+    ("draft_large", 44),
+    ("draft_small", 46),
+    ("draped_box", 48),
+    ("exchange", 50),
+    ("fleur", 52),
+    ("gobbler", 54),
+    ("gumby", 56),
+    ("hand1", 58),
+    ("hand2", 60),
+    ("heart", 62),
+    ("icon", 64),
+    ("iron_cross", 66),
+    ("left_ptr", 68),
+    ("left_side", 70),
+    ("left_tee", 72),
+    ("leftbutton", 74),
+    ("ll_angle", 76),
+    ("lr_angle", 78),
+    ("man", 80),
+    ("middlebutton", 82),
+    ("mouse", 84),
+    ("pencil", 86),
+    ("pirate", 88),
+    ("plus", 90),
+    ("question_arrow", 92),
+    ("right_ptr", 94),
+    ("right_side", 96),
+    ("right_tee", 98),
+    ("rightbutton", 100),
+    ("rtl_logo", 102),
+    ("sailboat", 104),
+    ("sb_down_arrow", 106),
+    ("sb_h_double_arrow", 108),
+    ("sb_left_arrow", 110),
+    ("sb_right_arrow", 112),
+    ("sb_up_arrow", 114),
+    ("sb_v_double_arrow", 116),
+    ("shuttle", 118),
+    ("sizing", 120),
+    ("spider", 122),
+    ("spraycan", 124),
+    ("star", 126),
+    ("target", 128),
+    ("tcross", 130),
+    ("top_left_arrow", 132),
+    ("top_left_corner", 134),
+    ("top_right_corner", 136),
+    ("top_side", 138),
+    ("top_tee", 140),
+    ("trek", 142),
+    ("ul_angle", 144),
+    ("umbrella", 146),
+    ("ur_angle", 148),
+    ("watch", 150),
+    ("xterm", 152),
+];
+
+/// Look up a cursor's glyph in the cursor font by name.
+pub(crate) fn cursor_glyph(name: &str) -> Option<u16> {
+    CURSOR_GLYPHS
+        .iter()
+        .find(|&&(glyph_name, _)| glyph_name == name)
+        .map(|&(_, glyph)| glyph)
+}
+
+/// Set the cursor that's shown over the root window to the named one from the
+/// cursor font, falling back on `left_ptr` if there's no such cursor.
+pub(crate) fn set_root_cursor<Conn>(conn: &Conn, root: xproto::Window, name: &str) -> Result<()>
+where
+    Conn: Connection,
+{
+    let glyph = match cursor_glyph(name) {
+        Some(glyph) => glyph,
+        None => {
+            log::warn!(
+                "Unknown cursor `{}'; using `{}' instead.",
+                name,
+                DEFAULT_CURSOR
+            );
+            cursor_glyph(DEFAULT_CURSOR).unwrap()
+        }
+    };
+    let font = conn.generate_id()?;
+    conn.open_font(font, b"cursor")?.check()?;
+    let cursor = conn.generate_id()?;
+    // Black on white, like xsetroot.
+    conn.create_glyph_cursor(
+        cursor,
+        font,
+        font,
+        glyph,
+        glyph + 1,
+        0,
+        0,
+        0,
+        0xffff,
+        0xffff,
+        0xffff,
+    )?
+    .check()?;
+    conn.change_window_attributes(
+        root,
+        &xproto::ChangeWindowAttributesAux::new().cursor(cursor),
+    )?
+    .check()?;
+    // The root window keeps its own reference to the cursor.
+    conn.free_cursor(cursor)?.check()?;
+    conn.close_font(font)?.check()?;
+    Ok(())
+}
+
+/// Confirm that cursor names map to the right glyphs, and that unknown ones
+/// aren't found.
+#[test]
+fn check_cursor_glyph() {
+    assert_eq!(cursor_glyph("X_cursor"), Some(0));
+    assert_eq!(cursor_glyph("left_ptr"), Some(68));
+    assert_eq!(cursor_glyph("xterm"), Some(152));
+    assert_eq!(cursor_glyph("no_such_cursor"), None);
+    assert!(cursor_glyph(DEFAULT_CURSOR).is_some());
+    for (i, &(_, glyph)) in CURSOR_GLYPHS.iter().enumerate() {
+        assert_eq!(usize::from(glyph), 2 * i);
+    }
+}
@@ -1,22 +1,40 @@
 //! The top-level window manager object.
 
 mod atom;
+mod bar;
 mod client;
 mod config;
+mod cursor;
+mod signal;
 mod util;
 
+use std::collections::HashMap;
+use std::convert::TryFrom;
 use std::error::Error;
+use std::os::unix::io::AsRawFd as _;
+use std::os::unix::io::RawFd;
+use std::os::unix::process::CommandExt as _;
 use std::process::Command;
+use std::time::Duration;
+use std::time::Instant;
 
 use x11rb::connection::Connection;
+use x11rb::errors::ConnectionError;
+use x11rb::errors::ReplyError;
+use x11rb::errors::ReplyOrIdError;
+use x11rb::properties::WmSizeHints;
 use x11rb::protocol::xproto;
 use x11rb::protocol::xproto::ConfigureWindowAux;
 use x11rb::protocol::xproto::ConnectionExt as _;
+use x11rb::protocol::Event;
 use x11rb::protocol::Event::*;
 
 use atom::*;
+use bar::*;
 use client::*;
 use config::*;
+use cursor::*;
+use signal::*;
 use util::*;
 
 /// General-purpose result type. Not very precise, but we're not actually doing
@@ -32,28 +50,107 @@ const MAX_WIDTH: u16 = 16384;
 const MIN_HEIGHT: u16 = 128;
 /// Default maximum client width.
 const MAX_HEIGHT: u16 = 16384;
+/// The smallest that a client may ever be in either dimension, even if its
+/// WM_NORMAL_HINTS ask for a smaller minimum.
+const MIN_SIZE_FLOOR: i32 = 1;
+
+/// How much `grow_proportional` and `shrink_proportional` resize a window by,
+/// in percent of its current size.
+const PROPORTIONAL_STEP: i32 = 10;
+
+/// The exit status when the connection to the X server is lost, and we aren't
+/// reconnecting (or have given up on it).
+const CONNECTION_LOST_EXIT_CODE: i32 = 3;
+/// How many times to try to reconnect to the X server before giving up.
+const RECONNECT_ATTEMPTS: u32 = 10;
+/// How long to wait between attempts to reconnect to the X server.
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+/// Environment variable that we set before re-executing ourselves, so that the
+/// new process knows that it's a restart rather than a fresh start.
+const RESTART_ENV_VAR: &str = "OXWM_RESTARTED";
+
+/// With `quit_confirm`, how soon after the first press of the quit binding
+/// the second one has to come.
+const QUIT_CONFIRM_TIMEOUT: Duration = Duration::from_secs(2);
 
 pub(crate) struct OxWM<Conn> {
     /// The source of all our problems.
     conn: Conn,
-    /// The index of the screen we're connected on.
+    /// The file descriptor underlying the connection, so that we can wait on
+    /// it with a timeout.
+    conn_fd: RawFd,
+    /// A pipe that wakes up the event loop when we get a shutdown signal.
+    self_pipe: SelfPipe,
+    /// The index of the screen whose events we're handling. The fields below
+    /// from `clients` on hold that screen's state; see `ScreenState`.
     screen: usize,
+    /// The state of every other screen that we manage, keyed by screen index.
+    /// It's swapped in by `switch_screen` when an event for that screen comes
+    /// in.
+    other_screens: HashMap<usize, ScreenState>,
     /// Configuration data.
     config: Config<Conn>,
     /// Local client data.
     clients: Clients,
+    /// Recently-focused windows.
+    focus_history: FocusHistory,
+    /// Windows labelled by `mark_window`.
+    marks: Marks,
     /// "Keep going" flag. If this is set to `false` at the start of the event
     /// loop, the window manager will stop running.
     keep_going: bool,
+    /// Whether this process was started by restarting a previous instance.
+    restarted: bool,
+    /// Whether we should re-execute ourselves once the event loop exits.
+    restart: bool,
     /// If a window is being dragged, then that state is stored here.
     drag: Option<Drag>,
+    /// If a window is waiting to be automatically raised, then that state is
+    /// stored here.
+    auto_raise: Option<AutoRaise>,
+    /// Startup programs waiting to be checked on, if `startup_check_ms` is
+    /// set.
+    startup_check: Option<StartupCheck>,
+    /// When the quit binding was first pressed, if we're waiting for it to be
+    /// pressed again to confirm.
+    quit_pending: Option<Instant>,
+    /// Whether we're in prefix mode, waiting for a key from
+    /// `prefix_keybinds`.
+    prefix: Prefix,
+    /// Whether `toggle_freeze` has frozen the screen.
+    freeze: Freeze,
+    /// The windows that `group_cycle` moves the focus around.
+    group: Group,
+    /// The window that `toggle_scratch_focus` flips to.
+    scratch: Scratch,
+    /// The windows that `minimize_others` iconified, for `restore_others`.
+    minimized_others: Vec<xproto::Window>,
+    /// If a window is zoomed by `toggle_zoom_focus`, then that state is stored
+    /// here.
+    zoom: Option<Zoom>,
+    /// The time of the last user input that we saw.
+    last_user_time: xproto::Timestamp,
+    /// Whether every window's border is hidden by `toggle_all_borders`.
+    borders_hidden: bool,
+    /// `config.border_width`, scaled for this screen if `border_width_mm` is
+    /// set.
+    border_width: u16,
     /// Manager for atoms that we need to intern.
     atoms: Atoms,
+    /// The built-in status line, if enabled.
+    bar: Option<Bar>,
+    /// The pixel value for `config.border_color`.
+    border_pixel: u32,
+    /// The pixel value for `config.locked_border_color`.
+    locked_border_pixel: u32,
+    /// The pixel value for `config.urgent_border_color`.
+    urgent_border_pixel: u32,
 }
 
 impl<Conn> OxWM<Conn> {
     /// Initialize the window manager.
-    fn new(conn: Conn, screen: usize) -> Result<OxWM<Conn>>
+    fn new(conn: Conn, conn_fd: RawFd, screen: usize, restarted: bool) -> Result<OxWM<Conn>>
     where
         Conn: Connection,
     {
@@ -65,59 +162,62 @@ impl<Conn> OxWM<Conn> {
         // likely to occur.
         //
         // (Well, that's probably not true right now, but IN THEORY...)
-        let config = Config::load().or_else(|err| -> Result<Config<Conn>> {
-            //File access errors
-            if let Some(io_error) = err.downcast_ref::<std::io::Error>() {
-                match io_error.kind() {
-                    std::io::ErrorKind::NotFound => log::info!("Configuration file not found."),
-                    std::io::ErrorKind::PermissionDenied => {
-                        log::error!(
-                            "Permission denied trying to read configuration file, aborting"
-                        );
-                        return Err(err);
-                    }
-                    _ => return Err(err),
-                }
-            }
-            // Deserialization format errors
-            if let Some(de_error) = err.downcast_ref::<toml::de::Error>() {
-                log::error!("Failed to parse config.toml: {}", de_error);
-                return Err(err);
-            }
-            // Config.toml content errors
-            if let Some(config_error) = err.downcast_ref::<ConfigError>() {
-                log::error!("{}", config_error);
-                return Err(err);
-            };
-            log::info!("Applying default configuration.");
-            let default_config = Config::new().unwrap();
-            default_config.save().map_err(|save_err| {
-                log::error!("{}", save_err);
-                save_err
-            })?;
-            Ok(default_config)
-        })?;
+        let config = config_or_fallback(Config::load(Purpose::Run), Purpose::Run)?;
         // Grab the server so that we can do setup atomically. We don't need to
         // worry about ungrabbing if we fail: this function consumes the
         // connection, so if we fail, the connection will just get dropped.
         //
         // TODO Not sure whether it's strictly necessary to grab the server, but
-        // it gives me some peace of mind. Should probably investigate.
-        conn.grab_server()?.check()?;
+        // it gives me some peace of mind. Should probably investigate. It can
+        // be turned off, though, since holding the grab while a slow startup
+        // program or debugger is waiting on the server can hang everything.
+        let grab_server = config.grab_server_on_init;
+        if grab_server {
+            conn.grab_server()?.check()?;
+        }
         log::debug!("Interning needed atoms.");
         let atoms = Atoms::new(&conn)?;
-        let clients = Clients::new(&conn, screen, &atoms)?;
+        let mut config = config;
+        apply_x_resources(&mut config, &conn, &atoms, conn.setup().roots[screen].root)?;
+        let home = ScreenState::new(&conn, screen, &atoms, &config)?;
+        let self_pipe = SelfPipe::new()?;
+        install_shutdown_handlers(&self_pipe)?;
         let mut ret = OxWM {
             conn,
+            conn_fd,
+            self_pipe,
             screen,
+            other_screens: HashMap::new(),
             config,
-            clients,
+            clients: home.clients,
+            focus_history: home.focus_history,
+            marks: home.marks,
+            prefix: Prefix::default(),
+            freeze: Freeze::default(),
+            group: home.group,
+            scratch: home.scratch,
+            minimized_others: home.minimized_others,
+            zoom: home.zoom,
             keep_going: true,
+            restarted,
+            restart: false,
             drag: None,
+            auto_raise: None,
+            startup_check: None,
+            quit_pending: None,
+            last_user_time: x11rb::CURRENT_TIME,
+            borders_hidden: home.borders_hidden,
+            border_width: home.border_width,
             atoms,
+            bar: home.bar,
+            border_pixel: home.border_pixel,
+            locked_border_pixel: home.locked_border_pixel,
+            urgent_border_pixel: home.urgent_border_pixel,
         };
         ret.init()?;
-        ret.conn.ungrab_server()?.check()?;
+        if grab_server {
+            ret.conn.ungrab_server()?.check()?;
+        }
         Ok(ret)
     }
 
@@ -128,26 +228,159 @@ impl<Conn> OxWM<Conn> {
     {
         // Try to become the window manager early, so that we can fail early
         // if necessary.
-        self.become_wm()?;
-        self.manage_extant_clients()?;
-        self.global_setup()?;
+        for screen in self.become_wm()? {
+            let state = ScreenState::new(&self.conn, screen, &self.atoms, &self.config)?;
+            self.other_screens.insert(screen, state);
+        }
+        self.for_each_screen(|wm| {
+            wm.ewmh_setup()?;
+            wm.manage_extant_clients()?;
+            wm.global_setup()?;
+            wm.bar_setup()
+        })?;
         self.run_startup_programs()?;
         Ok(())
     }
 
-    /// Try to become the window manager.
-    fn become_wm(&self) -> Result<()>
+    /// Try to become the window manager, on our own screen and then on every
+    /// other one. Failing on our own screen is fatal; failing on another one
+    /// (e.g. because some other window manager has it) just means that we
+    /// leave it alone. Returns the other screens that we got.
+    fn become_wm(&self) -> Result<Vec<usize>>
     where
         Conn: Connection,
     {
         log::debug!("Trying to become the window manager.");
+        let mut others = Vec::new();
+        for (screen, root) in roots_to_redirect(&self.conn.setup().roots, self.screen) {
+            let result = self
+                .conn
+                .change_window_attributes(
+                    root,
+                    &xproto::ChangeWindowAttributesAux::new()
+                        .event_mask(xproto::EventMask::SUBSTRUCTURE_REDIRECT),
+                )?
+                .check();
+            match result {
+                Ok(()) if screen != self.screen => others.push(screen),
+                Ok(()) => (),
+                Err(err) if screen == self.screen => return Err(err.into()),
+                Err(err) => log::warn!("Not managing screen {}: {:?}", screen, err),
+            }
+        }
+        Ok(others)
+    }
+
+    /// Make `screen` the one whose events we're handling, parking the state
+    /// of the current one. Does nothing if we don't manage that screen.
+    fn switch_screen(&mut self, screen: usize) {
+        use std::mem::swap;
+        let OxWM {
+            screen: current,
+            other_screens,
+            clients,
+            focus_history,
+            marks,
+            group,
+            scratch,
+            minimized_others,
+            zoom,
+            borders_hidden,
+            bar,
+            border_width,
+            border_pixel,
+            locked_border_pixel,
+            urgent_border_pixel,
+            ..
+        } = self;
+        swap_screen(other_screens, current, screen, |state| {
+            swap(clients, &mut state.clients);
+            swap(focus_history, &mut state.focus_history);
+            swap(marks, &mut state.marks);
+            swap(group, &mut state.group);
+            swap(scratch, &mut state.scratch);
+            swap(minimized_others, &mut state.minimized_others);
+            swap(zoom, &mut state.zoom);
+            swap(borders_hidden, &mut state.borders_hidden);
+            swap(bar, &mut state.bar);
+            swap(border_width, &mut state.border_width);
+            swap(border_pixel, &mut state.border_pixel);
+            swap(locked_border_pixel, &mut state.locked_border_pixel);
+            swap(urgent_border_pixel, &mut state.urgent_border_pixel);
+        });
+    }
+
+    /// Find the managed screen that a window belongs to, whether it's a root
+    /// window or a client.
+    fn screen_of(&self, window: xproto::Window) -> Option<usize>
+    where
+        Conn: Connection,
+    {
+        let roots: Vec<xproto::Window> = self.conn.setup().roots.iter().map(|s| s.root).collect();
+        find_screen(
+            &roots,
+            self.screen,
+            &self.other_screens,
+            window,
+            self.clients.has_client(window),
+            |state| state.clients.has_client(window),
+        )
+    }
+
+    /// Run `f` once for each screen that we manage, with that screen switched
+    /// in, and then switch back.
+    fn for_each_screen<F>(&mut self, mut f: F) -> Result<()>
+    where
+        F: FnMut(&mut Self) -> Result<()>,
+    {
+        let current = self.screen;
+        let mut result = Ok(());
+        for screen in screen_order(current, &self.other_screens) {
+            self.switch_screen(screen);
+            result = f(self);
+            if result.is_err() {
+                break;
+            }
+        }
+        self.switch_screen(current);
+        result
+    }
+
+    /// Advertise ourselves as an EWMH-compliant window manager.
+    fn ewmh_setup(&self) -> Result<()>
+    where
+        Conn: Connection,
+    {
+        log::debug!("Setting up EWMH hints.");
+        // The check window has to exist for as long as we're running, but we
+        // never need to touch it again: it goes away with our connection.
+        let check_window = self.conn.generate_id()?;
         self.conn
-            .change_window_attributes(
+            .create_window(
+                x11rb::COPY_DEPTH_FROM_PARENT,
+                check_window,
                 self.root(),
-                &xproto::ChangeWindowAttributesAux::new()
-                    .event_mask(xproto::EventMask::SUBSTRUCTURE_REDIRECT),
+                -1,
+                -1,
+                1,
+                1,
+                0,
+                xproto::WindowClass::INPUT_ONLY,
+                x11rb::COPY_FROM_PARENT,
+                &xproto::CreateWindowAux::new().override_redirect(1),
             )?
             .check()?;
+        self.atoms.set_supporting_wm_check(
+            &self.conn,
+            self.root(),
+            check_window,
+            env!("CARGO_PKG_VERSION"),
+        )?;
+        self.atoms.set_net_supported(&self.conn, self.root())?;
+        // Don't leave a previous window manager's value behind.
+        let focus = self.clients.get_focus().map(|client| client.window);
+        self.atoms
+            .set_net_active_window(&self.conn, self.root(), focus)?;
         Ok(())
     }
 
@@ -160,7 +393,7 @@ impl<Conn> OxWM<Conn> {
         for client in self.clients.iter() {
             self.manage(&client)?;
         }
-        Ok(())
+        self.update_client_list()
     }
 
     /// Perform global setup operations that involve the server.
@@ -169,23 +402,67 @@ impl<Conn> OxWM<Conn> {
         Conn: Connection,
     {
         log::debug!("Setting event mask on the root window.");
+        let mut event_mask =
+            xproto::EventMask::SUBSTRUCTURE_NOTIFY | xproto::EventMask::SUBSTRUCTURE_REDIRECT;
+        if self.config.builtin_bar {
+            // We need to know when to redraw the status line.
+            event_mask |= u32::from(xproto::EventMask::EXPOSURE);
+        }
         self.conn
             .change_window_attributes(
                 self.root(),
-                &xproto::ChangeWindowAttributesAux::new().event_mask(
-                    xproto::EventMask::SUBSTRUCTURE_NOTIFY
-                        | xproto::EventMask::SUBSTRUCTURE_REDIRECT,
-                ),
+                &xproto::ChangeWindowAttributesAux::new().event_mask(event_mask),
+            )?
+            .check()?;
+        if let Err(err) = set_root_cursor(&self.conn, self.root(), &self.config.root_cursor) {
+            log::warn!("Couldn't set the root cursor: {:?}", err);
+        }
+        // After a restart, the root window may well have a wallpaper that we
+        // mustn't paint over.
+        if let Some(color) = self.config.background_color.filter(|_| !self.restarted) {
+            if let Err(err) = self.set_root_background(color) {
+                log::warn!("Couldn't set the root background: {:?}", err);
+            }
+        }
+        self.grab_keys(None)
+    }
+
+    /// Fill the root window with a solid color.
+    fn set_root_background(&self, color: Color) -> Result<()>
+    where
+        Conn: Connection,
+    {
+        let pixel = alloc_color(&self.conn, self.screen, color)?;
+        self.conn
+            .change_window_attributes(
+                self.root(),
+                &xproto::ChangeWindowAttributesAux::new().background_pixel(pixel),
             )?
             .check()?;
-        log::debug!("Grabbing bound keycodes.");
+        self.conn
+            .clear_area(false, self.root(), 0, 0, 0, 0)?
+            .check()?;
+        Ok(())
+    }
+
+    /// Grab the bound keycodes, if the keybind scope says that they should be
+    /// grabbed on `window` (or, if that's `None`, the root window).
+    fn grab_keys(&self, window: Option<xproto::Window>) -> Result<()>
+    where
+        Conn: Connection,
+    {
+        let window = match self.config.keybind_scope.grab_window(self.root(), window) {
+            Some(window) => window,
+            None => return Ok(()),
+        };
+        log::debug!("Grabbing bound keycodes on window {}.", window);
         self.config
             .keybinds
             .keys()
             .map(|keycode| {
                 self.conn.grab_key(
                     false,
-                    self.root(),
+                    window,
                     self.config.mod_mask,
                     *keycode,
                     xproto::GrabMode::ASYNC,
@@ -198,75 +475,180 @@ impl<Conn> OxWM<Conn> {
         Ok(())
     }
 
+    /// Set up the built-in status line, if it's enabled.
+    fn bar_setup(&mut self) -> Result<()>
+    where
+        Conn: Connection,
+    {
+        if self.config.builtin_bar {
+            log::debug!("Setting up the status line.");
+            let screen = &self.conn.setup().roots[self.screen];
+            self.bar = Bar::new(&self.conn, screen, &self.config.font)?;
+            self.redraw_bar()?;
+        }
+        Ok(())
+    }
+
     /// Run configured startup programs.
-    fn run_startup_programs(&self) -> Result<()> {
+    fn run_startup_programs(&mut self) -> Result<()> {
         log::debug!("Running startup programs.");
-        for program in &self.config.startup {
-            if let Err(err) = Command::new(program).spawn() {
-                log::warn!("Unable to execute startup program `{}': {:?}", program, err);
+        let mut children = Vec::new();
+        for program in self.config.startup_programs(self.restarted) {
+            match Command::new(program).spawn() {
+                Ok(child) => children.push((program.clone(), child)),
+                Err(err) => {
+                    log::warn!("Unable to execute startup program `{}': {:?}", program, err)
+                }
             }
         }
+        // A program that starts but dies straight away (e.g. because of bad
+        // arguments) would otherwise fail silently. The check happens from the
+        // event loop, since the server may still be grabbed here.
+        self.startup_check =
+            StartupCheck::schedule(children, self.config.startup_check_ms, Instant::now());
         Ok(())
     }
 
     /// Run the WM. Note that this consumes the OxWM object: once
-    /// this procedure returns, the connection to the X server is gone.
-    fn run(mut self) -> Result<()>
+    /// this procedure returns, the connection to the X server is gone. Returns
+    /// whether a restart was requested.
+    fn run(mut self) -> Result<bool>
     where
         Conn: Connection,
     {
         while self.keep_going {
-            let ev = self.conn.wait_for_event()?;
+            let deadline = self
+                .auto_raise
+                .as_ref()
+                .map(|auto_raise| auto_raise.deadline)
+                .into_iter()
+                .chain(self.startup_check.as_ref().map(|check| check.deadline))
+                .min();
+            let timeout =
+                deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+            let ev = self.wait_for_event(timeout)?;
+            if shutdown_requested() {
+                log::info!("Received a shutdown signal; exiting.");
+                self.keep_going = false;
+                self.restart = false;
+                break;
+            }
+            self.run_timers()?;
+            let ev = match ev {
+                Some(ev) => ev,
+                None => continue,
+            };
             log::trace!("{:?}", ev);
+            if let Some(screen) = event_window(&ev).and_then(|window| self.screen_of(window)) {
+                self.switch_screen(screen);
+            }
             match ev {
                 ButtonPress(ev) => {
+                    self.last_user_time = ev.time;
                     let window = ev.event;
+                    let was_focused =
+                        self.clients.get_focus().map(|client| client.window) == Some(window);
                     self.click(window)?;
                     if ev.state & u16::from(self.config.mod_mask) == 0 {
+                        let allow =
+                            if replay_click(self.config.click_to_focus_passes_click, was_focused) {
+                                xproto::Allow::REPLAY_POINTER
+                            } else {
+                                xproto::Allow::ASYNC_POINTER
+                            };
                         self.conn
-                            .allow_events(xproto::Allow::REPLAY_POINTER, x11rb::CURRENT_TIME)?
+                            .allow_events(allow, x11rb::CURRENT_TIME)?
                             .check()?;
                     } else {
                         self.begin_drag(window, ev.detail, ev.event_x, ev.event_y);
                     }
                 }
                 ButtonRelease(_) => self.drag = None,
+                ClientMessage(ev) => {
+                    if let Err(err) = self.client_message(ev) {
+                        log::warn!("{:?}", err);
+                    }
+                }
                 ConfigureNotify(ev) => {
                     if ev.above_sibling == x11rb::NONE {
                         self.clients.move_to_bottom(ev.window);
+                        // A managed window that lowered itself to the very
+                        // bottom belongs back above the desktops.
+                        let managed = self.clients.get(ev.window).state.is_some();
+                        if managed
+                            && !self.is_desktop(ev.window)
+                            && self.clients.top_desktop(ev.window).is_some()
+                        {
+                            if let Err(err) = self.lower(ev.window) {
+                                log::warn!("{:?}", err);
+                            }
+                        }
                     } else {
                         self.clients.move_to_above(ev.window, ev.above_sibling);
+                        // Put desktops back underneath everything but each
+                        // other, however they got restacked.
+                        if self.is_desktop(ev.window) && !self.is_desktop(ev.above_sibling) {
+                            if let Err(err) = self.lower(ev.window) {
+                                log::warn!("{:?}", err);
+                            }
+                        }
+                    }
+                    if self.clients.client_list().contains(&ev.window) {
+                        if let Err(err) = self.update_client_list() {
+                            log::warn!("{:?}", err);
+                        }
+                    }
+                    let client = self.clients.get_mut(ev.window);
+                    let border_changed = match client.state {
+                        Some(ref st) => st.border_width != ev.border_width,
+                        None => false,
+                    };
+                    client.set_geometry(
+                        Geometry {
+                            x: ev.x,
+                            y: ev.y,
+                            width: ev.width,
+                            height: ev.height,
+                            border_width: ev.border_width,
+                        },
+                        self.config.track_override_redirect,
+                    );
+                    if border_changed {
+                        if let Err(err) =
+                            self.atoms
+                                .set_net_frame_extents(&self.conn, ev.window, ev.border_width)
+                        {
+                            log::warn!("{:?}", err);
+                        }
                     }
-                    if let Some(ref mut st) = self.clients.get_mut(ev.window).state {
-                        st.x = ev.x;
-                        st.y = ev.y;
-                        st.width = ev.width;
-                        st.height = ev.height;
+                }
+                ConfigureRequest(ev)
+                    if self.freeze.holds(Change::Configure {
+                        viewable: self.clients.get(ev.window).is_viewable(),
+                    }) =>
+                {
+                    // Hold managed windows still while frozen, telling them
+                    // where they are as if we'd declined. Windows that aren't
+                    // on screen yet can still set themselves up.
+                    if self.clients.get(ev.window).state.is_some() {
+                        self.send_configure_notify(ev.window)?;
                     }
                 }
                 ConfigureRequest(ev) => {
-                    let st = self.clients.get(ev.window).state.as_ref().unwrap();
-                    let (min_width, min_height) = st
-                        .wm_normal_hints
-                        .min_size
-                        .unwrap_or((MIN_WIDTH as i32, MIN_HEIGHT as i32));
-                    let (max_width, max_height) = st
-                        .wm_normal_hints
-                        .max_size
-                        .unwrap_or((MAX_WIDTH as i32, MAX_HEIGHT as i32));
-                    let mut value_list = xproto::ConfigureWindowAux::from_configure_request(&ev);
-                    // Windows that have override-redirect set can do whatever they want.
-                    if !self.clients.get(ev.window).override_redirect() {
-                        value_list.width = value_list
-                            .width
-                            .map(|w| w.max(min_width as u32).min(max_width as u32));
-                        value_list.height = value_list
-                            .height
-                            .map(|h| h.max(min_height as u32).min(max_height as u32));
-                    }
+                    let client = self.clients.get(ev.window);
+                    let value_list =
+                        configure_request_aux(&ev, client, self.config.clamp_configure_requests);
+                    let locked = match client.state {
+                        Some(ref st) => st.locked,
+                        None => false,
+                    };
                     if let Err(e) = self.conn.configure_window(ev.window, &value_list)?.check() {
                         // The window might have already been destroyed!
                         log::warn!("{:?}", e);
+                    } else if locked {
+                        // ICCCM 4.1.5: if we don't move or resize the window,
+                        // we have to tell it where it really is.
+                        self.send_configure_notify(ev.window)?;
                     }
                 }
                 CreateNotify(ev) => match self.create_notify(ev) {
@@ -275,23 +657,38 @@ impl<Conn> OxWM<Conn> {
                 },
                 DestroyNotify(ev) => {
                     let window = ev.window;
-                    if let Some(client) = self.clients.get_focus() {
-                        if client.window == window {
-                            // Focus the first managed client that we can find.
-                            for client in self.clients.iter().rev().skip(1) {
-                                if let Some(ref st) = client.state {
-                                    if st.is_viewable {
-                                        self.focus(client.window)?;
-                                        break;
-                                    }
-                                }
-                            }
+                    if let Err(err) = self.refocus_after(window) {
+                        log::warn!("{:?}", err);
+                    }
+                    if let Some(ref auto_raise) = self.auto_raise {
+                        if auto_raise.window == window {
+                            self.auto_raise = None;
+                        }
+                    }
+                    self.focus_history.remove(window);
+                    self.marks.remove(window);
+                    self.group.remove(window);
+                    self.scratch.remove(window);
+                    self.minimized_others.retain(|&w| w != window);
+                    if self.zoom.as_ref().map(|zoom| zoom.window) == Some(window) {
+                        let zoom = self.zoom.take().unwrap();
+                        if let Err(err) = self.unzoom(zoom) {
+                            log::warn!("{:?}", err);
                         }
                     }
+                    if self.config.close_orphan_transients {
+                        self.close_orphans(window);
+                    }
                     // Have to check here in case the window got destroyed
                     // before we could add it.
                     if self.clients.has_client(window) {
+                        let listed = self.clients.client_list().contains(&window);
                         self.clients.remove(window);
+                        if listed {
+                            if let Err(err) = self.update_client_list() {
+                                log::warn!("{:?}", err);
+                            }
+                        }
                     }
                     // If we were dragging the window, stop dragging it.
                     if let Some(ref drag) = self.drag {
@@ -300,49 +697,181 @@ impl<Conn> OxWM<Conn> {
                         }
                     }
                 }
+                Expose(ev) => {
+                    // Only redraw once the last of a batch of exposures comes in.
+                    if ev.window == self.root() && ev.count == 0 {
+                        if let Err(err) = self.redraw_bar() {
+                            log::warn!("{:?}", err);
+                        }
+                    }
+                }
                 EnterNotify(ev) => {
                     let window = ev.event;
-                    if let FocusModel::Autofocus = self.config.focus_model {
+                    // Don't let focus wander away from under an open menu.
+                    let popup_open =
+                        self.config.ignore_enter_under_popup && self.clients.popup_over(window);
+                    if self.config.focus_model == FocusModel::Autofocus && !popup_open {
                         if let Err(err) = self.focus(window) {
                             log::warn!("{:?}", err);
+                        } else {
+                            self.auto_raise = AutoRaise::schedule(
+                                window,
+                                self.config.auto_raise_ms,
+                                Instant::now(),
+                                self.drag.is_some(),
+                            );
                         }
                     }
                 }
                 FocusIn(ev) => {
                     self.clients.set_focus(ev.event);
+                    self.focus_history.focused(ev.event);
+                    if let Err(err) =
+                        self.atoms
+                            .set_net_active_window(&self.conn, self.root(), Some(ev.event))
+                    {
+                        log::warn!("{:?}", err);
+                    }
+                    if let Err(err) = self.set_urgent(ev.event, false) {
+                        log::warn!("{:?}", err);
+                    }
+                    if let Err(err) = self.redraw_bar() {
+                        log::warn!("{:?}", err);
+                    }
                 }
-                FocusOut(_) => {
+                FocusOut(ev) => {
                     self.clients.set_focus(None);
+                    if let Err(err) =
+                        self.atoms
+                            .set_net_active_window(&self.conn, self.root(), None)
+                    {
+                        log::warn!("{:?}", err);
+                    }
+                    if let Err(err) = self.redraw_bar() {
+                        log::warn!("{:?}", err);
+                    }
+                    if let Some(ref auto_raise) = self.auto_raise {
+                        if auto_raise.window == ev.event {
+                            self.auto_raise = None;
+                        }
+                    }
                 }
                 KeyPress(ev) => {
-                    let action = self.config.keybinds.get(&ev.detail).unwrap();
-                    action(&mut self, ev.child)?;
+                    self.last_user_time = ev.time;
+                    // While we have the keyboard grabbed, we see every key
+                    // press, not just our bindings.
+                    if self.marks.is_pending() {
+                        if let Err(err) = self.finish_mark(ev.detail) {
+                            log::warn!("{:?}", err);
+                        }
+                    } else if self.prefix.is_active() {
+                        let window = if ev.event == self.root() {
+                            ev.child
+                        } else {
+                            ev.event
+                        };
+                        self.prefix_key_press(ev.detail, window)?;
+                    } else if ev.state & u16::from(self.config.mod_mask) != 0 {
+                        if let Some(action) = self.config.keybinds.get(&ev.detail) {
+                            // Actions get the top-level window that the key
+                            // was pressed over; with a grab on a managed
+                            // window, that's the window itself.
+                            let window = if ev.event == self.root() {
+                                ev.child
+                            } else {
+                                ev.event
+                            };
+                            action(&mut self, window)?;
+                        }
+                    }
+                }
+                KeyRelease(ev) => {
+                    if self.focus_history.is_cycling() {
+                        let mapping = self.conn.get_modifier_mapping()?.reply()?;
+                        let keycodes = modifier_keycodes(
+                            mapping.keycodes_per_modifier(),
+                            &mapping.keycodes,
+                            u16::from(self.config.mod_mask),
+                        );
+                        if keycodes.contains(&ev.detail) {
+                            self.focus_history.commit();
+                            self.conn.ungrab_keyboard(x11rb::CURRENT_TIME)?.check()?;
+                        }
+                    }
                 }
                 MapNotify(ev) => {
                     let window = ev.window;
-                    if let Some(ref mut st) = self.clients.get_mut(window).state {
-                        st.is_viewable = true;
+                    self.clients.get_mut(window).set_viewable(true);
+                    // Override-redirect windows (menus, tooltips and so on)
+                    // map themselves without a MapRequest. They mustn't steal
+                    // the focus, and they aren't ours to give a WM_STATE.
+                    if !self.clients.get(window).override_redirect() {
+                        // Only Smart looks at the user time, and the window
+                        // may already be gone, so don't let that be fatal.
+                        let user_time = if self.config.focus_new_windows == FocusNew::Smart {
+                            self.atoms
+                                .get_net_wm_user_time(&self.conn, window)
+                                .unwrap_or_else(|err| {
+                                    log::warn!("{:?}", err);
+                                    None
+                                })
+                        } else {
+                            None
+                        };
+                        let focus = focus_on_map(
+                            self.clients.get(window),
+                            self.config.focus_new_windows,
+                            user_time,
+                            self.last_user_time,
+                        );
+                        if focus {
+                            if let Err(err) = self.focus(window) {
+                                log::warn!("{:?}", err);
+                            }
+                        }
+                        if self.clients.mapped(window) {
+                            if let Err(err) = self.update_client_list() {
+                                log::warn!("{:?}", err);
+                            }
+                        }
+                        if let Err(err) = self.update_smart_borders() {
+                            log::warn!("{:?}", err);
+                        }
+                        self.atoms.set_wm_state(
+                            &self.conn,
+                            window,
+                            WmState {
+                                state: WmStateState::Normal,
+                                icon: x11rb::NONE,
+                            },
+                        )?;
+                    }
+                }
+                MapRequest(ev) => {
+                    if self.freeze.defer_map(ev.window) {
+                        log::debug!("Frozen; deferring mapping window {}.", ev.window);
+                    } else {
+                        self.map_request(ev)?;
+                    }
+                }
+                MappingNotify(ev) => {
+                    if mapping_changes_buttons(ev.request) {
+                        log::debug!("Pointer mapping changed; regrabbing buttons.");
+                        self.for_each_screen(|wm| {
+                            for client in wm.clients.iter().filter(|c| !c.override_redirect()) {
+                                if let Err(err) = wm.grab_buttons(client.window) {
+                                    log::warn!("{:?}", err);
+                                }
+                            }
+                            Ok(())
+                        })?;
                     }
-                    self.atoms.set_wm_state(
-                        &self.conn,
-                        window,
-                        WmState {
-                            state: WmStateState::Normal,
-                            icon: x11rb::NONE,
-                        },
-                    )?;
                 }
-                MapRequest(ev) => self.conn.map_window(ev.window)?.check()?,
+                MotionNotify(_) if self.freeze.holds(Change::Drag) => (),
                 MotionNotify(ev) => {
                     let st = self.clients.get(ev.event).state.as_ref().unwrap();
-                    let (min_width, min_height) = st
-                        .wm_normal_hints
-                        .min_size
-                        .unwrap_or((MIN_WIDTH as i32, MIN_HEIGHT as i32));
-                    let (max_width, max_height) = st
-                        .wm_normal_hints
-                        .max_size
-                        .unwrap_or((MAX_WIDTH as i32, MAX_HEIGHT as i32));
+                    let ((min_width, min_height), (max_width, max_height)) =
+                        size_limits(&st.wm_normal_hints);
                     let drag = self.drag.as_ref().unwrap();
                     let mut config = match drag.type_ {
                         DragType::Move => {
@@ -455,18 +984,44 @@ impl<Conn> OxWM<Conn> {
                         log::warn!("{:?}", err);
                     }
                 }
+                VisibilityNotify(ev) => self.clients.visibility_notify(&ev),
                 UnmapNotify(ev) => {
                     let window = ev.window;
+                    if let Err(err) = self.refocus_after(window) {
+                        log::warn!("{:?}", err);
+                    }
                     if let Some(client) = self.clients.get_focus() {
                         if client.window == window {
                             self.clients.set_focus(None);
                         }
                     }
+                    // Unmapped windows can't be focused, so don't offer them.
+                    self.focus_history.remove(window);
+                    let mut state = WmStateState::Withdrawn;
+                    if self.clients.has_client(window) {
+                        self.clients.get_mut(window).set_viewable(false);
+                        if let Some(ref mut st) = self.clients.get_mut(window).state {
+                            // If we unmapped the window to iconify it, then it
+                            // hasn't been withdrawn.
+                            if st.iconifying {
+                                st.iconifying = false;
+                                state = WmStateState::Iconic;
+                            }
+                        }
+                    }
+                    if state == WmStateState::Withdrawn && self.clients.withdrawn(window) {
+                        if let Err(err) = self.update_client_list() {
+                            log::warn!("{:?}", err);
+                        }
+                    }
+                    if let Err(err) = self.update_smart_borders() {
+                        log::warn!("{:?}", err);
+                    }
                     if let Err(err) = self.atoms.set_wm_state(
                         &self.conn,
                         window,
                         WmState {
-                            state: WmStateState::Withdrawn,
+                            state,
                             icon: x11rb::NONE,
                         },
                     ) {
@@ -476,41 +1031,84 @@ impl<Conn> OxWM<Conn> {
                 _ => log::warn!("Unhandled event!"),
             }
         }
+        Ok(self.restart)
+    }
+
+    /// Wait for the next event, giving up after `timeout` if one is given.
+    fn wait_for_event(&self, timeout: Option<Duration>) -> Result<Option<Event>>
+    where
+        Conn: Connection,
+    {
+        if let Some(ev) = self.conn.poll_for_event()? {
+            return Ok(Some(ev));
+        }
+        // Make sure the server has seen all of our requests before we go to
+        // sleep, or we might end up waiting for a response to them forever.
+        self.conn.flush()?;
+        let mut fds = [
+            libc::pollfd {
+                fd: self.conn_fd,
+                events: libc::POLLIN,
+                revents: 0,
+            },
+            libc::pollfd {
+                fd: self.self_pipe.fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            },
+        ];
+        // Round up, so that we don't spin while waiting out the last
+        // fraction of a millisecond.
+        let timeout = timeout.map_or(-1, |timeout| {
+            (timeout + Duration::from_micros(999))
+                .as_millis()
+                .min(i32::MAX as u128) as i32
+        });
+        // Safety: `fds` is a valid array of `pollfd`s, and it outlives the
+        // call.
+        if unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, timeout) } < 0 {
+            let err = std::io::Error::last_os_error();
+            if err.kind() != std::io::ErrorKind::Interrupted {
+                return Err(Box::new(err));
+            }
+        }
+        self.self_pipe.drain();
+        Ok(self.conn.poll_for_event()?)
+    }
+
+    /// Perform any timed actions that are due.
+    fn run_timers(&mut self) -> Result<()>
+    where
+        Conn: Connection,
+    {
+        if let Some(auto_raise) = self.auto_raise.take() {
+            if !auto_raise.is_due(Instant::now()) {
+                self.auto_raise = Some(auto_raise);
+            } else if self.drag.is_none() && !self.freeze.holds(Change::AutoRaise) {
+                // The timer isn't tied to an event, so whichever screen the
+                // last event was for is switched in.
+                if let Some(screen) = self.screen_of(auto_raise.window) {
+                    self.switch_screen(screen);
+                    if let Err(err) = self.raise(auto_raise.window) {
+                        log::warn!("{:?}", err);
+                    }
+                }
+            }
+        }
+        if let Some(check) = self.startup_check.take() {
+            if check.is_due(Instant::now()) {
+                check.report();
+            } else {
+                self.startup_check = Some(check);
+            }
+        }
         Ok(())
     }
 
     /// Initiate a drag on the given window.
     fn begin_drag(&mut self, window: xproto::Window, button: xproto::Button, x: i16, y: i16) {
         let st = self.clients.get(window).state.as_ref().unwrap();
-        let (type_, corner) = match button {
-            1 => (DragType::Move, Corner::LeftTop),
-            3 => {
-                // We resize from whatever corner the pointer is
-                // closest to.
-                let mid_x = (st.width / 2) as i16;
-                let mid_y = (st.height / 2) as i16;
-                let corner = match (x >= mid_x, y >= mid_y) {
-                    (false, false) => Corner::LeftTop,
-                    (false, true) => Corner::LeftBottom,
-                    (true, false) => Corner::RightTop,
-                    (true, true) => Corner::RightBottom,
-                };
-                (DragType::Resize(corner), corner)
-            }
-            _ => {
-                log::error!("Invalid button.");
-                return;
-            }
-        };
-        let (cx, cy) = corner.relative(st);
-        let x = x - (cx as i16);
-        let y = y - (cy as i16);
-        self.drag = Some(Drag {
-            type_,
-            window,
-            x,
-            y,
-        });
+        self.drag = Drag::start(window, st, button, x, y);
     }
 
     /// A button has been clicked.
@@ -523,6 +1121,94 @@ impl<Conn> OxWM<Conn> {
         Ok(())
     }
 
+    /// Dispatch on a ClientMessage event.
+    fn client_message(&mut self, ev: xproto::ClientMessageEvent) -> Result<()>
+    where
+        Conn: Connection,
+    {
+        let window = ev.window;
+        if !self.clients.has_client(window) || self.clients.get(window).state.is_none() {
+            log::warn!("Ignoring client message for unmanaged window {}.", window);
+            return Ok(());
+        }
+        if ev.type_ == self.atoms.net_wm_state {
+            let data = ev.data.as_data32();
+            let action = match NetWmStateAction::try_from(data[0]) {
+                Ok(action) => action,
+                Err(_) => {
+                    log::warn!("Invalid _NET_WM_STATE action {}.", data[0]);
+                    return Ok(());
+                }
+            };
+            for &state in &data[1..3] {
+                let st = self.clients.get(window).state.as_ref().unwrap();
+                if state == self.atoms.net_wm_state_demands_attention {
+                    self.set_urgent(window, action.apply(st.urgent))?;
+                } else if state == self.atoms.net_wm_state_fullscreen {
+                    if self.freeze.holds(Change::Fullscreen) {
+                        log::debug!("Frozen; not changing fullscreen on window {}.", window);
+                    } else {
+                        self.set_fullscreen(window, action.apply(st.fullscreen))?;
+                    }
+                } else if state == self.atoms.net_wm_state_modal {
+                    // Our copy of the flag is updated when the property
+                    // changes.
+                    let modal = action.apply(st.modal);
+                    self.atoms
+                        .set_net_wm_state(&self.conn, window, state, modal)?;
+                } else if state != x11rb::NONE {
+                    log::warn!("Ignoring unsupported _NET_WM_STATE {}.", state);
+                }
+            }
+        } else if ev.type_ == self.atoms.net_active_window {
+            let data = ev.data.as_data32();
+            if !self.freeze.holds(Change::Activate)
+                && activation_allowed(
+                    data[0],
+                    data[1],
+                    self.config.focus_new_windows,
+                    self.last_user_time,
+                )
+            {
+                if !self.clients.get(window).is_viewable() {
+                    self.conn.map_window(window)?.check()?;
+                }
+                self.focus(window)?;
+                self.raise(window)?;
+            } else {
+                log::debug!("Refusing to activate window {}; marking it urgent.", window);
+                self.set_urgent(window, true)?;
+            }
+        } else if ev.type_ == self.atoms.wm_change_state {
+            let data = ev.data.as_data32();
+            if self.freeze.holds(Change::Iconify) {
+                log::debug!("Frozen; not iconifying window {}.", window);
+            } else if change_state_iconifies(data[0]) {
+                self.iconify(window)?;
+            } else {
+                log::warn!("Ignoring WM_CHANGE_STATE to state {}.", data[0]);
+            }
+        } else {
+            log::warn!("Ignoring.");
+        }
+        Ok(())
+    }
+
+    /// Iconify a window: unmap it, leaving it in the Iconic state. The client
+    /// can map it again to restore it.
+    fn iconify(&mut self, window: xproto::Window) -> Result<()>
+    where
+        Conn: Connection,
+    {
+        let st = self.clients.get_mut(window).state.as_mut().unwrap();
+        if !st.is_viewable {
+            return Ok(());
+        }
+        st.iconifying = true;
+        self.conn.unmap_window(window)?.check()?;
+        Ok(())
+    }
+
     /// Dispatch on a CreateNotify event.
     fn create_notify(&mut self, ev: xproto::CreateNotifyEvent) -> Result<()>
     where
@@ -530,16 +1216,44 @@ impl<Conn> OxWM<Conn> {
     {
         // TODO We should really factor all event handlers out into functions like this.
         let window = ev.window;
-        self.clients.push(Client {
-            window,
-            state: if ev.override_redirect {
-                None
-            } else {
-                Some(ClientState {
+        let net_wm_state = if ev.override_redirect {
+            Vec::new()
+        } else {
+            self.atoms.get_net_wm_state(&self.conn, window)?
+        };
+        let override_redirect_geometry =
+            if ev.override_redirect && self.config.track_override_redirect {
+                Some(Geometry {
+                    x: ev.x,
+                    y: ev.y,
+                    width: ev.width,
+                    height: ev.height,
+                    border_width: ev.border_width,
+                })
+            } else {
+                None
+            };
+        self.clients.push(Client {
+            window,
+            override_redirect_geometry,
+            override_redirect_viewable: false,
+            state: if ev.override_redirect {
+                None
+            } else {
+                Some(ClientState {
                     x: ev.x,
                     y: ev.y,
                     width: ev.width,
                     height: ev.height,
+                    border_width: ev.border_width,
+                    saved_border_width: None,
+                    size_preset: None,
+                    fullscreen: false,
+                    saved_geometry: None,
+                    gtk_frame_extents: self.atoms.get_gtk_frame_extents(&self.conn, window)?,
+                    locked: false,
+                    transient_for: self.atoms.get_wm_transient_for(&self.conn, window)?,
+                    iconifying: false,
                     is_viewable: false,
                     wm_protocols: self.atoms.get_wm_protocols(&self.conn, window)?,
                     wm_state: Some(WmState {
@@ -547,6 +1261,19 @@ impl<Conn> OxWM<Conn> {
                         icon: x11rb::NONE,
                     }),
                     wm_normal_hints: self.atoms.get_wm_normal_hints(&self.conn, window)?,
+                    wm_class: self.atoms.get_wm_class(&self.conn, window)?,
+                    wm_window_role: self.atoms.get_wm_window_role(&self.conn, window)?,
+                    urgent: net_wm_state.contains(&self.atoms.net_wm_state_demands_attention),
+                    modal: net_wm_state.contains(&self.atoms.net_wm_state_modal),
+                    focusable: self.atoms.get_focusable(&self.conn, window)?,
+                    desktop: self.atoms.get_desktop(&self.conn, window)?,
+                    title: self
+                        .atoms
+                        .get_title(&self.conn, window, self.config.title_encoding)?,
+                    wm_command: self.atoms.get_wm_command(&self.conn, window)?,
+                    sm_client_id: self.atoms.get_sm_client_id(&self.conn, window)?,
+                    icons: self.atoms.get_net_wm_icon(&self.conn, window)?,
+                    visibility: Visibility::Unobscured,
                 })
             },
         });
@@ -557,6 +1284,63 @@ impl<Conn> OxWM<Conn> {
         Ok(())
     }
 
+    /// Dispatch on a MapRequest event.
+    fn map_request(&mut self, ev: xproto::MapRequestEvent) -> Result<()>
+    where
+        Conn: Connection,
+    {
+        let window = ev.window;
+        // Rules only apply when a window is leaving the Withdrawn state, so
+        // that windows aren't repositioned every time they're remapped.
+        let withdrawn = match self.clients.get(window).state {
+            Some(ref st) => match st.wm_state {
+                Some(wm_state) => wm_state.state == WmStateState::Withdrawn,
+                None => true,
+            },
+            None => false,
+        };
+        if withdrawn {
+            // Clients usually set their window type and icon after creating
+            // the window, so what we read then may be out of date.
+            let focusable = self.atoms.get_focusable(&self.conn, window)?;
+            let desktop = self.atoms.get_desktop(&self.conn, window)?;
+            let icons = self.atoms.get_net_wm_icon(&self.conn, window)?;
+            let st = self.clients.get_mut(window).state.as_mut().unwrap();
+            st.focusable = focusable;
+            st.desktop = desktop;
+            st.icons = icons;
+            self.apply_rules(window)?;
+            // Windows can ask to start out fullscreen.
+            if self
+                .atoms
+                .get_net_wm_state(&self.conn, window)?
+                .contains(&self.atoms.net_wm_state_fullscreen)
+            {
+                self.set_fullscreen(window, true)?;
+            }
+            // ICCCM 4.1.4: a client may ask to start out iconified, in which
+            // case we manage it but leave it unmapped.
+            let hints = self.atoms.get_wm_hints(&self.conn, window)?;
+            if WmStateState::initial(&hints) == WmStateState::Iconic {
+                log::debug!("Window {} starts out iconified.", window);
+                self.atoms.set_wm_state(
+                    &self.conn,
+                    window,
+                    WmState {
+                        state: WmStateState::Iconic,
+                        icon: x11rb::NONE,
+                    },
+                )?;
+                return Ok(());
+            }
+        }
+        if self.is_desktop(window) {
+            self.lower(window)?;
+        }
+        self.conn.map_window(window)?.check()?;
+        Ok(())
+    }
+
     /// Dispatch on a PropertyNotify event.
     fn property_notify(&mut self, ev: xproto::PropertyNotifyEvent) -> Result<()>
     where
@@ -581,23 +1365,159 @@ impl<Conn> OxWM<Conn> {
                 .wm_state = self.atoms.get_wm_state(&self.conn, window)?;
         } else if ev.atom == xproto::AtomEnum::WM_NORMAL_HINTS.into() {
             log::debug!("Updating WM_NORMAL_HINTS.");
+            let st = self.clients.get_mut(window).state.as_mut().unwrap();
+            st.wm_normal_hints = self.atoms.get_wm_normal_hints(&self.conn, window)?;
+            // The window might not fit its new limits any more.
+            let (width, height) = clamp_size(&st.wm_normal_hints, st.width, st.height);
+            if (width, height) != (st.width, st.height) {
+                log::debug!("Resizing window {} to {}x{}.", window, width, height);
+                self.conn
+                    .configure_window(
+                        window,
+                        &ConfigureWindowAux::new()
+                            .width(u32::from(width))
+                            .height(u32::from(height)),
+                    )?
+                    .check()?;
+            }
+        } else if ev.atom == xproto::AtomEnum::WM_CLASS.into() {
+            log::debug!("Updating WM_CLASS.");
             self.clients
                 .get_mut(window)
                 .state
                 .as_mut()
                 .unwrap()
-                .wm_normal_hints = self.atoms.get_wm_normal_hints(&self.conn, window)?
+                .wm_class = self.atoms.get_wm_class(&self.conn, window)?
+        } else if ev.atom == self.atoms.wm_window_role {
+            log::debug!("Updating WM_WINDOW_ROLE.");
+            self.clients
+                .get_mut(window)
+                .state
+                .as_mut()
+                .unwrap()
+                .wm_window_role = self.atoms.get_wm_window_role(&self.conn, window)?
+        } else if ev.atom == self.atoms.net_wm_name || ev.atom == xproto::AtomEnum::WM_NAME.into() {
+            log::debug!("Updating title.");
+            self.clients.get_mut(window).state.as_mut().unwrap().title =
+                self.atoms
+                    .get_title(&self.conn, window, self.config.title_encoding)?;
+            if self.clients.get_focus().map(|client| client.window) == Some(window) {
+                self.redraw_bar()?;
+            }
+        } else if ev.atom == xproto::AtomEnum::WM_COMMAND.into() {
+            log::debug!("Updating WM_COMMAND.");
+            self.clients
+                .get_mut(window)
+                .state
+                .as_mut()
+                .unwrap()
+                .wm_command = self.atoms.get_wm_command(&self.conn, window)?;
+        } else if ev.atom == self.atoms.sm_client_id {
+            log::debug!("Updating SM_CLIENT_ID.");
+            self.clients
+                .get_mut(window)
+                .state
+                .as_mut()
+                .unwrap()
+                .sm_client_id = self.atoms.get_sm_client_id(&self.conn, window)?;
+        } else if ev.atom == self.atoms.net_wm_icon {
+            log::debug!("Updating _NET_WM_ICON.");
+            self.clients.get_mut(window).state.as_mut().unwrap().icons =
+                self.atoms.get_net_wm_icon(&self.conn, window)?;
+        } else if ev.atom == self.atoms.net_wm_state {
+            log::debug!("Updating _NET_WM_STATE.");
+            let net_wm_state = self.atoms.get_net_wm_state(&self.conn, window)?;
+            let st = self.clients.get_mut(window).state.as_mut().unwrap();
+            st.urgent = net_wm_state.contains(&self.atoms.net_wm_state_demands_attention);
+            st.modal = net_wm_state.contains(&self.atoms.net_wm_state_modal);
+            self.repaint_border(window)?;
+        } else if ev.atom == u32::from(xproto::AtomEnum::WM_TRANSIENT_FOR) {
+            log::debug!("Updating WM_TRANSIENT_FOR.");
+            self.clients
+                .get_mut(window)
+                .state
+                .as_mut()
+                .unwrap()
+                .transient_for = self.atoms.get_wm_transient_for(&self.conn, window)?;
+        } else if ev.atom == self.atoms.gtk_frame_extents {
+            log::debug!("Updating _GTK_FRAME_EXTENTS.");
+            self.clients
+                .get_mut(window)
+                .state
+                .as_mut()
+                .unwrap()
+                .gtk_frame_extents = self.atoms.get_gtk_frame_extents(&self.conn, window)?;
         } else {
             log::warn!("Ignoring.");
         }
         Ok(())
     }
 
-    /// Focus a window.
+    /// Mark a client as demanding attention, or not.
+    fn set_urgent(&mut self, window: xproto::Window, urgent: bool) -> Result<()>
+    where
+        Conn: Connection,
+    {
+        if !self.clients.has_client(window) {
+            return Ok(());
+        }
+        if let Some(ref mut st) = self.clients.get_mut(window).state {
+            if st.urgent == urgent {
+                return Ok(());
+            }
+            st.urgent = urgent;
+            self.atoms.set_net_wm_state(
+                &self.conn,
+                window,
+                self.atoms.net_wm_state_demands_attention,
+                urgent,
+            )?;
+        }
+        self.repaint_border(window)
+    }
+
+    /// Set a managed window's border to the color for its current state.
+    fn repaint_border(&self, window: xproto::Window) -> Result<()>
+    where
+        Conn: Connection,
+    {
+        if let Some(ref st) = self.clients.get(window).state {
+            self.conn
+                .change_window_attributes(
+                    window,
+                    &xproto::ChangeWindowAttributesAux::new()
+                        .border_pixel(self.border_pixel_for(st)),
+                )?
+                .check()?;
+        }
+        Ok(())
+    }
+
+    /// Redraw the built-in status line, if it's enabled.
+    fn redraw_bar(&self) -> Result<()>
+    where
+        Conn: Connection,
+    {
+        if let Some(ref bar) = self.bar {
+            let title = self
+                .clients
+                .get_focus()
+                .and_then(|client| client.state.as_ref())
+                .and_then(|st| st.title.as_deref());
+            let screen = &self.conn.setup().roots[self.screen];
+            bar.draw(&self.conn, screen, &status_text(title))?;
+        }
+        Ok(())
+    }
+
+    /// Focus a window, unless it should never be focused.
     fn focus(&self, window: xproto::Window) -> Result<()>
     where
         Conn: Connection,
     {
+        if self.clients.has_client(window) && !self.clients.get(window).focusable() {
+            return Ok(());
+        }
         self.conn
             .set_input_focus(
                 xproto::InputFocus::POINTER_ROOT,
@@ -608,6 +1528,38 @@ impl<Conn> OxWM<Conn> {
         Ok(())
     }
 
+    /// Move the focus on if `window`, which is being unmapped or destroyed, had
+    /// it.
+    fn refocus_after(&mut self, window: xproto::Window) -> Result<()>
+    where
+        Conn: Connection,
+    {
+        match self.clients.refocus_after(window, &self.focus_history) {
+            Refocus::Unaffected => Ok(()),
+            Refocus::Window(next) => self.focus(next),
+            Refocus::Nothing => self.focus_nothing(),
+        }
+    }
+
+    /// Give the focus to the root window, so that our keybinds still work when
+    /// there's no window to focus, and run `no_window_action` if it's set.
+    fn focus_nothing(&mut self) -> Result<()>
+    where
+        Conn: Connection,
+    {
+        let root = self.root();
+        self.focus(root)?;
+        if let Some(action) = self.config.action(&self.config.no_window_action) {
+            log::debug!(
+                "No window to focus; running {}.",
+                self.config.no_window_action
+            );
+            // There's no window under the pointer to speak of.
+            action(self, x11rb::NONE)?;
+        }
+        Ok(())
+    }
+
     /// Kill a window.
     fn kill(&self, window: xproto::Window) -> Result<()>
     where
@@ -630,6 +1582,24 @@ impl<Conn> OxWM<Conn> {
         Ok(())
     }
 
+    /// Close the windows that were transient for a window that's just been
+    /// destroyed. Only windows that support WM_DELETE_WINDOW are closed, since
+    /// killing the client would take the rest of the application with it.
+    fn close_orphans(&self, parent: xproto::Window)
+    where
+        Conn: Connection,
+    {
+        for window in self.clients.transients_for(parent) {
+            let st = self.clients.get(window).state.as_ref().unwrap();
+            if st.wm_protocols.delete_window {
+                log::debug!("Closing window {}, orphaned by {}.", window, parent);
+                if let Err(err) = self.atoms.delete_window(&self.conn, window) {
+                    log::warn!("{:?}", err);
+                }
+            }
+        }
+    }
+
     /// Begin managing a client.
     fn manage(&self, client: &Client) -> Result<()>
     where
@@ -637,23 +1607,11 @@ impl<Conn> OxWM<Conn> {
     {
         let st = client.state.as_ref().unwrap();
         // Enforce our size policies.
-        let (min_width, min_height) = st
-            .wm_normal_hints
-            .min_size
-            .unwrap_or((MIN_WIDTH as i32, MIN_HEIGHT as i32));
-        let (max_width, max_height) = st
-            .wm_normal_hints
-            .max_size
-            .unwrap_or((MAX_WIDTH as i32, MAX_HEIGHT as i32));
-        let mut value_list = xproto::ConfigureWindowAux::new()
-            .width(st.width as u32)
-            .height(st.height as u32);
-        value_list.width = value_list
-            .width
-            .map(|w| w.max(min_width as u32).min(max_width as u32));
-        value_list.height = value_list
-            .height
-            .map(|h| h.max(min_height as u32).min(max_height as u32));
+        let (width, height) = clamp_size(&st.wm_normal_hints, st.width, st.height);
+        let value_list = xproto::ConfigureWindowAux::new()
+            .width(u32::from(width))
+            .height(u32::from(height))
+            .border_width(u32::from(self.border_width));
         self.conn
             .configure_window(client.window, &value_list)?
             .check()?;
@@ -672,13 +1630,42 @@ impl<Conn> OxWM<Conn> {
                 icon: x11rb::NONE,
             },
         )?;
+        self.conn
+            .change_window_attributes(
+                client.window,
+                &xproto::ChangeWindowAttributesAux::new().border_pixel(self.border_pixel_for(st)),
+            )?
+            .check()?;
+        self.atoms
+            .set_net_frame_extents(&self.conn, client.window, self.border_width)?;
+        self.atoms
+            .set_net_wm_allowed_actions(&self.conn, client.window, st.locked)?;
+        self.grab_keys(Some(client.window))?;
+        self.grab_buttons(client.window)?;
+        // Set our desired event mask.
+        self.conn
+            .change_window_attributes(
+                client.window,
+                &xproto::ChangeWindowAttributesAux::new()
+                    .event_mask(self.config.focus_model.client_event_mask()),
+            )?
+            .check()?;
+        Ok(())
+    }
+
+    /// Grab the mouse buttons that we use for clicking to focus and for
+    /// dragging on a managed window.
+    fn grab_buttons(&self, window: xproto::Window) -> Result<()>
+    where
+        Conn: Connection,
+    {
         // Grab modifier + nothing.
         let nomod: u16 = 0;
         // TODO I don't fully understand sync/async grab modes.
         self.conn
             .grab_button(
                 true,
-                client.window,
+                window,
                 event_mask_to_u16(xproto::EventMask::BUTTON_PRESS),
                 xproto::GrabMode::SYNC,
                 xproto::GrabMode::SYNC,
@@ -692,7 +1679,7 @@ impl<Conn> OxWM<Conn> {
         self.conn
             .grab_button(
                 false,
-                client.window,
+                window,
                 event_mask_to_u16(
                     xproto::EventMask::BUTTON_PRESS
                         | xproto::EventMask::BUTTON_RELEASE
@@ -710,7 +1697,7 @@ impl<Conn> OxWM<Conn> {
         self.conn
             .grab_button(
                 false,
-                client.window,
+                window,
                 event_mask_to_u16(
                     xproto::EventMask::BUTTON_PRESS
                         | xproto::EventMask::BUTTON_RELEASE
@@ -724,25 +1711,223 @@ impl<Conn> OxWM<Conn> {
                 self.config.mod_mask,
             )?
             .check()?;
-        // Set our desired event mask.
+        Ok(())
+    }
+
+    /// Apply any configured rules that match a client.
+    fn apply_rules(&self, window: xproto::Window) -> Result<()>
+    where
+        Conn: Connection,
+    {
+        let st = self.clients.get(window).state.as_ref().unwrap();
+        for rule in self.config.rules.iter().filter(|rule| rule.matches(st)) {
+            log::debug!("Applying rule {:?} to window {}.", rule, window);
+            if rule.center {
+                let screen = &self.conn.setup().roots[self.screen];
+                let x = (screen.width_in_pixels as i32 - st.width as i32) / 2;
+                let y = (screen.height_in_pixels as i32 - st.height as i32) / 2;
+                self.conn
+                    .configure_window(window, &ConfigureWindowAux::new().x(x).y(y))?
+                    .check()?;
+            }
+            if let Some(opacity) = rule.opacity {
+                self.atoms
+                    .set_net_wm_window_opacity(&self.conn, window, opacity)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Set the border widths of some windows.
+    fn set_border_widths(&self, changes: Vec<(xproto::Window, u16)>) -> Result<()>
+    where
+        Conn: Connection,
+    {
+        for (window, width) in changes {
+            self.conn
+                .configure_window(
+                    window,
+                    &xproto::ConfigureWindowAux::new().border_width(u32::from(width)),
+                )?
+                .check()?;
+        }
+        Ok(())
+    }
+
+    /// With `smart_borders` on, hide the border of the only visible window,
+    /// and restore any borders hidden this way once that's no longer the case.
+    fn update_smart_borders(&mut self) -> Result<()>
+    where
+        Conn: Connection,
+    {
+        // Borders hidden by toggle_all_borders stay hidden.
+        if !self.config.smart_borders || self.borders_hidden {
+            return Ok(());
+        }
+        let viewable = self
+            .clients
+            .iter()
+            .filter(|client| match client.state {
+                Some(ref st) => st.is_viewable,
+                None => false,
+            })
+            .count();
+        let mut changes = Vec::new();
+        for client in self.clients.iter_mut() {
+            if let Some(ref mut st) = client.state {
+                if st.fullscreen {
+                    continue;
+                }
+                if smart_border_hidden(viewable, st.is_viewable) {
+                    if st.saved_border_width.is_none() {
+                        changes.push((client.window, st.hide_border()));
+                    }
+                } else if let Some(width) = st.restore_border() {
+                    changes.push((client.window, width));
+                }
+            }
+        }
+        self.set_border_widths(changes)
+    }
+
+    /// Make a window fullscreen, or restore it. Fullscreen windows cover the
+    /// whole screen and have no border, so that no strip of border shows at
+    /// the screen edges.
+    fn set_fullscreen(&mut self, window: xproto::Window, on: bool) -> Result<()>
+    where
+        Conn: Connection,
+    {
+        let borders_hidden = self.borders_hidden;
+        let st = self.clients.get_mut(window).state.as_mut().unwrap();
+        let value_list = if on {
+            if !st.enter_fullscreen() {
+                return Ok(());
+            }
+            let screen = &self.conn.setup().roots[self.screen];
+            ConfigureWindowAux::new()
+                .x(0)
+                .y(0)
+                .width(u32::from(screen.width_in_pixels))
+                .height(u32::from(screen.height_in_pixels))
+                .border_width(0)
+                .stack_mode(xproto::StackMode::ABOVE)
+        } else {
+            let (x, y, width, height) = match st.leave_fullscreen() {
+                Some(geometry) => geometry,
+                None => return Ok(()),
+            };
+            let mut value_list = ConfigureWindowAux::new()
+                .x(i32::from(x))
+                .y(i32::from(y))
+                .width(u32::from(width))
+                .height(u32::from(height));
+            // Borders hidden by toggle_all_borders stay hidden.
+            if !borders_hidden {
+                if let Some(width) = st.restore_border() {
+                    value_list = value_list.border_width(u32::from(width));
+                }
+            }
+            value_list
+        };
+        self.conn.configure_window(window, &value_list)?.check()?;
+        self.atoms
+            .set_net_wm_state(&self.conn, window, self.atoms.net_wm_state_fullscreen, on)?;
+        if !on {
+            self.update_smart_borders()?;
+        }
+        Ok(())
+    }
+
+    /// The border color that a client should have. Demanding attention takes
+    /// precedence over being locked.
+    fn border_pixel_for(&self, st: &ClientState) -> u32 {
+        if st.urgent {
+            self.urgent_border_pixel
+        } else if st.locked {
+            self.locked_border_pixel
+        } else {
+            self.border_pixel
+        }
+    }
+
+    /// Send a synthetic ConfigureNotify telling a window its current
+    /// geometry, for when we've declined to carry out a ConfigureRequest.
+    fn send_configure_notify(&self, window: xproto::Window) -> Result<()>
+    where
+        Conn: Connection,
+    {
+        let st = self.clients.get(window).state.as_ref().unwrap();
         self.conn
-            .change_window_attributes(
-                client.window,
-                &xproto::ChangeWindowAttributesAux::new().event_mask(
-                    xproto::EventMask::ENTER_WINDOW
-                        | xproto::EventMask::FOCUS_CHANGE
-                        | xproto::EventMask::PROPERTY_CHANGE,
-                ),
+            .send_event(
+                false,
+                window,
+                xproto::EventMask::STRUCTURE_NOTIFY,
+                xproto::ConfigureNotifyEvent {
+                    response_type: xproto::CONFIGURE_NOTIFY_EVENT,
+                    sequence: 0,
+                    event: window,
+                    window,
+                    above_sibling: x11rb::NONE,
+                    x: st.x,
+                    y: st.y,
+                    width: st.width,
+                    height: st.height,
+                    border_width: st.border_width,
+                    override_redirect: false,
+                },
             )?
             .check()?;
         Ok(())
     }
 
-    /// Raise a window to the front of the stack.
+    /// Move the focused window into a corner of the screen, without resizing it.
+    fn pin(&self, corner: Corner) -> Result<()>
+    where
+        Conn: Connection,
+    {
+        let client = match self.clients.get_focus() {
+            Some(client) => client,
+            None => return Ok(()),
+        };
+        let st = match client.state {
+            Some(ref st) if !st.locked => st,
+            _ => return Ok(()),
+        };
+        let screen = &self.conn.setup().roots[self.screen];
+        let border = 2 * u32::from(st.border_width);
+        // Line up the visible part of the window, not its shadow.
+        let (width, height, dx, dy) = visible_part(
+            u32::from(st.width) + border,
+            u32::from(st.height) + border,
+            st.gtk_frame_extents,
+        );
+        let (x, y) = corner.pinned_origin(
+            screen.width_in_pixels,
+            screen.height_in_pixels,
+            width,
+            height,
+            self.config.pin_margin,
+        );
+        let (x, y) = (x - dx, y - dy);
+        self.conn
+            .configure_window(client.window, &ConfigureWindowAux::new().x(x).y(y))?
+            .check()?;
+        Ok(())
+    }
+
+    /// Indicates whether a window is a known desktop window.
+    fn is_desktop(&self, window: xproto::Window) -> bool {
+        self.clients.has_client(window) && self.clients.get(window).desktop()
+    }
+
+    /// Raise a window to the front of the stack, unless it's a desktop.
     fn raise(&self, window: xproto::Window) -> Result<()>
     where
         Conn: Connection,
     {
+        if self.is_desktop(window) {
+            return Ok(());
+        }
         self.conn
             .configure_window(
                 window,
@@ -752,6 +1937,21 @@ impl<Conn> OxWM<Conn> {
         Ok(())
     }
 
+    /// Lower a window to the bottom of the stack, but above any desktop.
+    fn lower(&self, window: xproto::Window) -> Result<()>
+    where
+        Conn: Connection,
+    {
+        let value_list = match self.clients.top_desktop(window) {
+            Some(desktop) if !self.is_desktop(window) => xproto::ConfigureWindowAux::new()
+                .sibling(desktop)
+                .stack_mode(xproto::StackMode::ABOVE),
+            _ => xproto::ConfigureWindowAux::new().stack_mode(xproto::StackMode::BELOW),
+        };
+        self.conn.configure_window(window, &value_list)?.check()?;
+        Ok(())
+    }
+
     // Actions go here. Note that, due to the need to conform to the Action
     // type, these functions' type signatures may sometimes seem odd.
 
@@ -771,49 +1971,1063 @@ impl<Conn> OxWM<Conn> {
         }
     }
 
-    /// Poison the window manager, causing it to die promptly.
-    fn poison(&mut self, _: xproto::Window) -> Result<()> {
-        self.keep_going = false;
+    /// Switch to the previously-focused window. Pressing this repeatedly while
+    /// holding the modifier walks further back through the focus history;
+    /// releasing the modifier commits to the selected window.
+    fn focus_last(&mut self, _: xproto::Window) -> Result<()>
+    where
+        Conn: Connection,
+    {
+        let was_cycling = self.focus_history.is_cycling();
+        if let Some(window) = self.focus_history.cycle() {
+            // Grab the keyboard so that we find out when the modifier is
+            // released. Without the grab, we'd never commit, so don't cycle.
+            if !was_cycling && !self.grab_keyboard()? {
+                self.focus_history.cancel();
+                return Ok(());
+            }
+            self.focus(window)?;
+            self.raise(window)?;
+        }
         Ok(())
     }
 
-    // Simple utility stuff goes here.
-
-    /// Get the root window.
-    fn root(&self) -> xproto::Window
+    /// Grab the keyboard on the root window, so that we see every key event.
+    /// Returns whether the grab succeeded; if it didn't (e.g. because another
+    /// client has the keyboard grabbed), that's logged.
+    fn grab_keyboard(&self) -> Result<bool>
     where
         Conn: Connection,
     {
-        self.conn.setup().roots[self.screen].root
+        let reply = self
+            .conn
+            .grab_keyboard(
+                false,
+                self.root(),
+                x11rb::CURRENT_TIME,
+                xproto::GrabMode::ASYNC,
+                xproto::GrabMode::ASYNC,
+            )?
+            .reply()?;
+        if reply.status != xproto::GrabStatus::SUCCESS {
+            log::warn!("Couldn't grab the keyboard: {:?}", reply.status);
+            return Ok(false);
+        }
+        Ok(true)
     }
-}
-
-/// A corner.
-#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug)]
-enum Corner {
-    /// The top-left corner.
-    LeftTop,
-    /// The bottom-left corner.
-    LeftBottom,
-    /// The top-right corner.
-    RightTop,
-    /// The bottom-right corner.
-    RightBottom,
-}
 
-impl Corner {
-    /// Obtain the relative location of a corner for a given client window.
-    fn relative(&self, st: &ClientState) -> (i16, i16) {
-        match self {
-            Self::LeftTop => (0, 0),
-            Self::LeftBottom => (0, st.height as i16),
-            Self::RightTop => (st.width as i16, 0),
-            Self::RightBottom => (st.width as i16, st.height as i16),
+    /// Focus and raise the topmost client that is demanding attention.
+    fn focus_urgent(&mut self, _: xproto::Window) -> Result<()>
+    where
+        Conn: Connection,
+    {
+        let urgent = self.clients.iter().rev().find(|client| match client.state {
+            Some(ref st) => st.urgent && st.is_viewable && st.focusable,
+            None => false,
+        });
+        if let Some(client) = urgent {
+            let window = client.window;
+            self.focus(window)?;
+            self.raise(window)?;
         }
+        Ok(())
     }
-}
 
-/// A type of drag: either moving or resizing from a particular corner.
+    /// Hide the borders of every managed window, or restore them if they're
+    /// already hidden.
+    fn toggle_all_borders(&mut self, _: xproto::Window) -> Result<()>
+    where
+        Conn: Connection,
+    {
+        self.borders_hidden = !self.borders_hidden;
+        let hidden = self.borders_hidden;
+        let mut changes = Vec::new();
+        for client in self.clients.iter_mut() {
+            if let Some(ref mut st) = client.state {
+                // Fullscreen windows keep their borders hidden regardless.
+                if st.fullscreen {
+                    continue;
+                }
+                let width = if hidden {
+                    Some(st.hide_border())
+                } else {
+                    st.restore_border()
+                };
+                if let Some(width) = width {
+                    changes.push((client.window, width));
+                }
+            }
+        }
+        self.set_border_widths(changes)?;
+        self.update_smart_borders()
+    }
+
+    /// Move the focused window into the top-left corner of the screen.
+    fn pin_top_left(&mut self, _: xproto::Window) -> Result<()>
+    where
+        Conn: Connection,
+    {
+        self.pin(Corner::LeftTop)
+    }
+
+    /// Move the focused window into the top-right corner of the screen.
+    fn pin_top_right(&mut self, _: xproto::Window) -> Result<()>
+    where
+        Conn: Connection,
+    {
+        self.pin(Corner::RightTop)
+    }
+
+    /// Move the focused window into the bottom-left corner of the screen.
+    fn pin_bottom_left(&mut self, _: xproto::Window) -> Result<()>
+    where
+        Conn: Connection,
+    {
+        self.pin(Corner::LeftBottom)
+    }
+
+    /// Move the focused window into the bottom-right corner of the screen.
+    fn pin_bottom_right(&mut self, _: xproto::Window) -> Result<()>
+    where
+        Conn: Connection,
+    {
+        self.pin(Corner::RightBottom)
+    }
+
+    /// Resize the focused window to the next of the configured size presets,
+    /// and center it.
+    fn cycle_size(&mut self, _: xproto::Window) -> Result<()>
+    where
+        Conn: Connection,
+    {
+        let len = self.config.size_presets.len();
+        let client = match self.clients.get_focus_mut() {
+            Some(client) => client,
+            None => return Ok(()),
+        };
+        let window = client.window;
+        let st = match client.state {
+            Some(ref mut st) if !st.locked => st,
+            _ => return Ok(()),
+        };
+        let index = match next_size_preset(st.size_preset, len) {
+            Some(index) => index,
+            None => return Ok(()),
+        };
+        st.size_preset = Some(index);
+        let border = 2 * i32::from(st.border_width);
+        let (width, height) = self.config.size_presets[index];
+        let screen = &self.conn.setup().roots[self.screen];
+        let x = (i32::from(screen.width_in_pixels) - width as i32 - border) / 2;
+        let y = (i32::from(screen.height_in_pixels) - height as i32 - border) / 2;
+        self.conn
+            .configure_window(
+                window,
+                &ConfigureWindowAux::new()
+                    .x(x)
+                    .y(y)
+                    .width(width)
+                    .height(height),
+            )?
+            .check()?;
+        Ok(())
+    }
+
+    /// Scale the focused window about its center by `percent` percent of its
+    /// current size, within its size hints and the screen.
+    fn resize_proportional(&mut self, percent: i32) -> Result<()>
+    where
+        Conn: Connection,
+    {
+        let client = match self.clients.get_focus() {
+            Some(client) => client,
+            None => return Ok(()),
+        };
+        let st = match client.state {
+            Some(ref st) if !st.locked => st,
+            _ => return Ok(()),
+        };
+        let screen = &self.conn.setup().roots[self.screen];
+        let border = 2 * st.border_width;
+        let (x, y, width, height) = scale_geometry(
+            &st.wm_normal_hints,
+            (st.x, st.y, st.width, st.height),
+            (
+                screen.width_in_pixels.saturating_sub(border),
+                screen.height_in_pixels.saturating_sub(border),
+            ),
+            percent,
+        );
+        self.conn
+            .configure_window(
+                client.window,
+                &ConfigureWindowAux::new()
+                    .x(x)
+                    .y(y)
+                    .width(u32::from(width))
+                    .height(u32::from(height)),
+            )?
+            .check()?;
+        Ok(())
+    }
+
+    /// Grow the focused window by a tenth of its size, keeping it centered.
+    fn grow_proportional(&mut self, _: xproto::Window) -> Result<()>
+    where
+        Conn: Connection,
+    {
+        self.resize_proportional(PROPORTIONAL_STEP)
+    }
+
+    /// Shrink the focused window by a tenth of its size, keeping it centered.
+    fn shrink_proportional(&mut self, _: xproto::Window) -> Result<()>
+    where
+        Conn: Connection,
+    {
+        self.resize_proportional(-PROPORTIONAL_STEP)
+    }
+
+    /// Zoom the focused window: hide every other window and make it cover the
+    /// screen. Doing it again puts everything back.
+    fn toggle_zoom_focus(&mut self, _: xproto::Window) -> Result<()>
+    where
+        Conn: Connection,
+    {
+        if let Some(zoom) = self.zoom.take() {
+            return self.unzoom(zoom);
+        }
+        let client = match self.clients.get_focus() {
+            Some(client) => client,
+            None => return Ok(()),
+        };
+        let window = client.window;
+        let was_fullscreen = match client.state {
+            Some(ref st) if !st.locked => st.fullscreen,
+            _ => return Ok(()),
+        };
+        let hidden = self.clients.others_to_hide(window);
+        for &other in &hidden {
+            self.iconify(other)?;
+        }
+        self.set_fullscreen(window, true)?;
+        self.zoom = Some(Zoom {
+            window,
+            hidden,
+            was_fullscreen,
+        });
+        Ok(())
+    }
+
+    /// Undo a zoom, showing the windows that it hid and restoring the zoomed
+    /// window, if they're still around.
+    fn unzoom(&mut self, zoom: Zoom) -> Result<()>
+    where
+        Conn: Connection,
+    {
+        if self.clients.has_client(zoom.window) && !zoom.was_fullscreen {
+            self.set_fullscreen(zoom.window, false)?;
+        }
+        self.unhide(zoom.hidden)
+    }
+
+    /// Iconify every other window, leaving the focused one on its own.
+    /// `restore_others` brings them back.
+    fn minimize_others(&mut self, _: xproto::Window) -> Result<()>
+    where
+        Conn: Connection,
+    {
+        let window = match self.clients.get_focus() {
+            Some(client) if client.state.is_some() => client.window,
+            _ => return Ok(()),
+        };
+        for other in self.clients.others_to_hide(window) {
+            self.iconify(other)?;
+            if !self.minimized_others.contains(&other) {
+                self.minimized_others.push(other);
+            }
+        }
+        Ok(())
+    }
+
+    /// Map the windows that `minimize_others` iconified again.
+    fn restore_others(&mut self, _: xproto::Window) -> Result<()>
+    where
+        Conn: Connection,
+    {
+        let hidden = std::mem::take(&mut self.minimized_others);
+        self.unhide(hidden)
+    }
+
+    /// Map windows that we hid again, if they're still around and haven't
+    /// been mapped in the meantime.
+    fn unhide(&self, windows: Vec<xproto::Window>) -> Result<()>
+    where
+        Conn: Connection,
+    {
+        for window in windows {
+            if !self.clients.has_client(window) || self.clients.get(window).is_viewable() {
+                continue;
+            }
+            self.conn.map_window(window)?.check()?;
+        }
+        Ok(())
+    }
+
+    /// Swap the focused window's position and size with those of the window
+    /// above it in the stack (or below it, if `above` is false).
+    fn swap_with_neighbor(&mut self, above: bool) -> Result<()>
+    where
+        Conn: Connection,
+    {
+        let window = match self.clients.get_focus() {
+            Some(client) if client.state.is_some() => client.window,
+            _ => return Ok(()),
+        };
+        let other = match self.clients.neighbor(window, above) {
+            Some(other) => other,
+            None => return Ok(()),
+        };
+        let locked = |window| self.clients.get(window).state.as_ref().unwrap().locked;
+        if locked(window) || locked(other) {
+            return Ok(());
+        }
+        self.clients.swap(window, other);
+        for &window in &[window, other] {
+            let st = self.clients.get(window).state.as_ref().unwrap();
+            self.conn
+                .configure_window(
+                    window,
+                    &ConfigureWindowAux::new()
+                        .x(i32::from(st.x))
+                        .y(i32::from(st.y))
+                        .width(u32::from(st.width))
+                        .height(u32::from(st.height)),
+                )?
+                .check()?;
+        }
+        Ok(())
+    }
+
+    /// Swap the focused window's geometry with the window above it.
+    fn swap_with_next(&mut self, _: xproto::Window) -> Result<()>
+    where
+        Conn: Connection,
+    {
+        self.swap_with_neighbor(true)
+    }
+
+    /// Swap the focused window's geometry with the window below it.
+    fn swap_with_prev(&mut self, _: xproto::Window) -> Result<()>
+    where
+        Conn: Connection,
+    {
+        self.swap_with_neighbor(false)
+    }
+
+    /// Lock the focused window in place, so that it can't be moved or resized,
+    /// or unlock it if it's already locked.
+    fn toggle_locked(&mut self, _: xproto::Window) -> Result<()>
+    where
+        Conn: Connection,
+    {
+        let client = match self.clients.get_focus_mut() {
+            Some(client) => client,
+            None => return Ok(()),
+        };
+        let window = client.window;
+        let st = match client.state {
+            Some(ref mut st) => st,
+            None => return Ok(()),
+        };
+        st.locked = !st.locked;
+        let st = self.clients.get(window).state.as_ref().unwrap();
+        self.conn
+            .change_window_attributes(
+                window,
+                &xproto::ChangeWindowAttributesAux::new().border_pixel(self.border_pixel_for(st)),
+            )?
+            .check()?;
+        self.atoms
+            .set_net_wm_allowed_actions(&self.conn, window, st.locked)?;
+        // Don't let a drag that's already underway carry on.
+        if let Some(ref drag) = self.drag {
+            if drag.window == window && st.locked {
+                self.drag = None;
+            }
+        }
+        Ok(())
+    }
+
+    /// Reload the config file, but only apply the border widths and colors
+    /// from it.
+    fn refresh_appearance(&mut self, _: xproto::Window) -> Result<()>
+    where
+        Conn: Connection,
+    {
+        let mut fresh = match Config::<Conn>::load(Purpose::Run) {
+            Ok(config) => config,
+            Err(err) => {
+                log::warn!("Not refreshing the appearance: {}", err);
+                return Ok(());
+            }
+        };
+        apply_x_resources(&mut fresh, &self.conn, &self.atoms, self.root())?;
+        self.config.take_appearance(&fresh);
+        self.for_each_screen(|wm| wm.restyle_borders())
+    }
+
+    /// Reallocate the border colors for the current screen, and apply them and
+    /// the border width to every managed window on it.
+    fn restyle_borders(&mut self) -> Result<()>
+    where
+        Conn: Connection,
+    {
+        self.border_pixel = alloc_color(&self.conn, self.screen, self.config.border_color)?;
+        self.locked_border_pixel =
+            alloc_color(&self.conn, self.screen, self.config.locked_border_color)?;
+        self.urgent_border_pixel =
+            alloc_color(&self.conn, self.screen, self.config.urgent_border_color)?;
+        let screen = &self.conn.setup().roots[self.screen];
+        self.border_width = self
+            .config
+            .scaled_border_width(screen.width_in_pixels, screen.width_in_millimeters);
+        let width = self.border_width;
+        let mut changes = Vec::new();
+        for client in self.clients.iter_mut() {
+            if let Some(ref mut st) = client.state {
+                if let Some(width) = st.restyle_border(width) {
+                    changes.push((client.window, width));
+                }
+            }
+        }
+        for client in self.clients.iter() {
+            if let Some(ref st) = client.state {
+                self.conn
+                    .change_window_attributes(
+                        client.window,
+                        &xproto::ChangeWindowAttributesAux::new()
+                            .border_pixel(self.border_pixel_for(st)),
+                    )?
+                    .check()?;
+            }
+        }
+        self.set_border_widths(changes)?;
+        self.update_smart_borders()
+    }
+
+    /// Focus and raise the nearest window in a direction from the focused one.
+    fn focus_direction(&mut self, direction: Direction) -> Result<()>
+    where
+        Conn: Connection,
+    {
+        let center = |st: &ClientState| {
+            let border = 2 * i32::from(st.border_width);
+            (
+                i32::from(st.x) + (i32::from(st.width) + border) / 2,
+                i32::from(st.y) + (i32::from(st.height) + border) / 2,
+            )
+        };
+        let (focus, from) = match self.clients.get_focus() {
+            Some(Client {
+                window,
+                state: Some(ref st),
+                ..
+            }) => (*window, center(st)),
+            _ => return Ok(()),
+        };
+        let candidates: Vec<_> = self
+            .clients
+            .iter()
+            .filter(|client| client.window != focus)
+            .filter_map(|client| match client.state {
+                Some(ref st) if st.is_viewable && st.focusable => Some((client.window, center(st))),
+                _ => None,
+            })
+            .collect();
+        let wrap = self.config.focus_direction_wraps;
+        if let Some(window) = direction.nearest(from, &candidates, wrap) {
+            self.focus(window)?;
+            self.raise(window)?;
+        }
+        Ok(())
+    }
+
+    /// Focus and raise the nearest window to the left of the focused one.
+    fn focus_left(&mut self, _: xproto::Window) -> Result<()>
+    where
+        Conn: Connection,
+    {
+        self.focus_direction(Direction::Left)
+    }
+
+    /// Focus and raise the nearest window to the right of the focused one.
+    fn focus_right(&mut self, _: xproto::Window) -> Result<()>
+    where
+        Conn: Connection,
+    {
+        self.focus_direction(Direction::Right)
+    }
+
+    /// Focus and raise the nearest window above the focused one.
+    fn focus_up(&mut self, _: xproto::Window) -> Result<()>
+    where
+        Conn: Connection,
+    {
+        self.focus_direction(Direction::Up)
+    }
+
+    /// Focus and raise the nearest window below the focused one.
+    fn focus_down(&mut self, _: xproto::Window) -> Result<()>
+    where
+        Conn: Connection,
+    {
+        self.focus_direction(Direction::Down)
+    }
+
+    /// Focus and raise the next window with the same WM_CLASS as the focused
+    /// one.
+    fn focus_next_same_class(&mut self, _: xproto::Window) -> Result<()>
+    where
+        Conn: Connection,
+    {
+        let window = match self.clients.get_focus() {
+            Some(client) => client.window,
+            None => return Ok(()),
+        };
+        if let Some(next) = self.clients.next_same_class(window) {
+            self.focus(next)?;
+            self.raise(next)?;
+        }
+        Ok(())
+    }
+
+    /// Focus and raise the window that the focused one is transient for.
+    fn focus_parent(&mut self, _: xproto::Window) -> Result<()>
+    where
+        Conn: Connection,
+    {
+        let window = match self.clients.get_focus() {
+            Some(client) => client.window,
+            None => return Ok(()),
+        };
+        match self.clients.transient_parent(window) {
+            Some(parent) => {
+                self.focus(parent)?;
+                self.raise(parent)?;
+            }
+            None => log::info!("Window {} isn't transient for a focusable window.", window),
+        }
+        Ok(())
+    }
+
+    /// Focus and raise the topmost window that's transient for the focused
+    /// one.
+    fn focus_child(&mut self, _: xproto::Window) -> Result<()>
+    where
+        Conn: Connection,
+    {
+        let window = match self.clients.get_focus() {
+            Some(client) => client.window,
+            None => return Ok(()),
+        };
+        match self.clients.transient_child(window) {
+            Some(child) => {
+                self.focus(child)?;
+                self.raise(child)?;
+            }
+            None => log::info!("No focusable window is transient for window {}.", window),
+        }
+        Ok(())
+    }
+
+    /// Raise the bottom-most window, moving every other window down one place.
+    fn rotate_stack_forward(&mut self, _: xproto::Window) -> Result<()>
+    where
+        Conn: Connection,
+    {
+        match self.clients.rotation_target(true) {
+            Some(window) => self.raise(window),
+            None => Ok(()),
+        }
+    }
+
+    /// Lower the top-most window, moving every other window up one place.
+    fn rotate_stack_backward(&mut self, _: xproto::Window) -> Result<()>
+    where
+        Conn: Connection,
+    {
+        match self.clients.rotation_target(false) {
+            Some(window) => self.lower(window),
+            None => Ok(()),
+        }
+    }
+
+    /// Raise the bottom-most of the windows under the pointer.
+    fn circulate_up(&mut self, _: xproto::Window) -> Result<()>
+    where
+        Conn: Connection,
+    {
+        self.circulate(true)
+    }
+
+    /// Lower the top-most of the windows under the pointer beneath the others.
+    fn circulate_down(&mut self, _: xproto::Window) -> Result<()>
+    where
+        Conn: Connection,
+    {
+        self.circulate(false)
+    }
+
+    /// Rotate the pile of windows under the pointer by one place, raising the
+    /// bottom one if `up` is set, and otherwise lowering the top one.
+    fn circulate(&mut self, up: bool) -> Result<()>
+    where
+        Conn: Connection,
+    {
+        let pointer = self.conn.query_pointer(self.root())?.reply()?;
+        let target = self
+            .clients
+            .circulation_target(up, pointer.root_x, pointer.root_y);
+        match target {
+            Some(window) if up => self.raise(window),
+            Some(window) => self.lower(window),
+            None => Ok(()),
+        }
+    }
+
+    /// Label the focused window with a mark, named by the next key press.
+    fn mark_window(&mut self, _: xproto::Window) -> Result<()>
+    where
+        Conn: Connection,
+    {
+        let window = match self.clients.get_focus() {
+            Some(client) => client.window,
+            None => return Ok(()),
+        };
+        self.start_mark(PendingMark::Set(window))
+    }
+
+    /// Focus and raise the window with the mark named by the next key press.
+    fn goto_mark(&mut self, _: xproto::Window) -> Result<()>
+    where
+        Conn: Connection,
+    {
+        self.start_mark(PendingMark::Goto)
+    }
+
+    /// Grab the keyboard, so that we see the next key press whatever it is,
+    /// and wait for it to name a mark.
+    fn start_mark(&mut self, pending: PendingMark) -> Result<()>
+    where
+        Conn: Connection,
+    {
+        if self.grab_keyboard()? {
+            self.marks.start(pending);
+        }
+        Ok(())
+    }
+
+    /// Handle the key press that names a mark. Modifier keys are skipped over,
+    /// and any other key that can't be a mark cancels.
+    fn finish_mark(&mut self, keycode: xproto::Keycode) -> Result<()>
+    where
+        Conn: Connection,
+    {
+        let mapping = self.conn.get_keyboard_mapping(keycode, 1)?.reply()?;
+        let keysym = mapping.keysyms.first().copied().unwrap_or(0);
+        let mark = match mark_key(keysym) {
+            MarkKey::Modifier => return Ok(()),
+            MarkKey::Mark(mark) => Some(mark),
+            MarkKey::Cancel => None,
+        };
+        let pending = self.marks.take_pending();
+        self.conn.ungrab_keyboard(x11rb::CURRENT_TIME)?.check()?;
+        match (pending, mark) {
+            (Some(PendingMark::Set(window)), Some(mark)) => {
+                log::debug!("Marking window {} with {}.", window, mark);
+                self.marks.set(mark, window);
+            }
+            (Some(PendingMark::Goto), Some(mark)) => match self.marks.get(mark) {
+                Some(window) => {
+                    self.focus(window)?;
+                    self.raise(window)?;
+                }
+                None => log::info!("No window has the mark {}.", mark),
+            },
+            _ => (),
+        }
+        Ok(())
+    }
+
+    /// Add the focused window to the group that `group_cycle` cycles through.
+    fn group_add(&mut self, _: xproto::Window) -> Result<()>
+    where
+        Conn: Connection,
+    {
+        if let Some(client) = self.clients.get_focus() {
+            self.group.add(client.window);
+        }
+        Ok(())
+    }
+
+    /// Empty the group that `group_cycle` cycles through.
+    fn group_clear(&mut self, _: xproto::Window) -> Result<()>
+    where
+        Conn: Connection,
+    {
+        self.group.clear();
+        Ok(())
+    }
+
+    /// Focus and raise the next viewable window in the group.
+    fn group_cycle(&mut self, _: xproto::Window) -> Result<()>
+    where
+        Conn: Connection,
+    {
+        let focus = self.clients.get_focus().map(|client| client.window);
+        let clients = &self.clients;
+        let next = self.group.next(focus, |window| {
+            clients.has_client(window)
+                && match clients.get(window).state {
+                    Some(ref st) => st.is_viewable && st.focusable,
+                    None => false,
+                }
+        });
+        if let Some(window) = next {
+            self.focus(window)?;
+            self.raise(window)?;
+        }
+        Ok(())
+    }
+
+    /// Designate the focused window as the one that `toggle_scratch_focus`
+    /// flips to.
+    fn set_scratch(&mut self, _: xproto::Window) -> Result<()>
+    where
+        Conn: Connection,
+    {
+        if let Some(client) = self.clients.get_focus() {
+            self.scratch.set(client.window);
+        }
+        Ok(())
+    }
+
+    /// Focus and raise the scratch window, or if it's focused already, go
+    /// back to the window that was focused before.
+    fn toggle_scratch_focus(&mut self, _: xproto::Window) -> Result<()>
+    where
+        Conn: Connection,
+    {
+        let focus = self.clients.get_focus().map(|client| client.window);
+        if let Some(window) = self.scratch.toggle(focus) {
+            // It may have been iconified or withdrawn in the meantime.
+            if !self.clients.has_client(window) || !self.clients.get(window).is_viewable() {
+                return Ok(());
+            }
+            self.focus(window)?;
+            self.raise(window)?;
+        }
+        Ok(())
+    }
+
+    /// Enter prefix mode: grab the keyboard, so that the next key press is
+    /// looked up in `prefix_keybinds` without needing `mod_mask`.
+    fn prefix(&mut self, _: xproto::Window) -> Result<()>
+    where
+        Conn: Connection,
+    {
+        if self.grab_keyboard()? {
+            self.prefix.enter();
+        }
+        Ok(())
+    }
+
+    /// Handle a key press in prefix mode. Modifier keys are skipped over;
+    /// any other key leaves prefix mode, running its binding if it has one.
+    fn prefix_key_press(&mut self, keycode: xproto::Keycode, window: xproto::Window) -> Result<()>
+    where
+        Conn: Connection,
+    {
+        let mapping = self.conn.get_keyboard_mapping(keycode, 1)?.reply()?;
+        let keysym = mapping.keysyms.first().copied().unwrap_or(0);
+        let dispatch = self.prefix.press(prefix_key(keysym));
+        if self.prefix.is_active() {
+            return Ok(());
+        }
+        self.conn.ungrab_keyboard(x11rb::CURRENT_TIME)?.check()?;
+        if dispatch {
+            match self.config.prefix_keybinds.get(&keycode) {
+                Some(action) => action(self, window)?,
+                None => log::debug!("Keycode {} isn't bound in prefix mode.", keycode),
+            }
+        }
+        Ok(())
+    }
+
+    /// Freeze the screen, e.g. for taking a clean screenshot, or thaw it if
+    /// it's frozen. While frozen, windows aren't mapped, clients can't move,
+    /// resize, restack, fullscreen or iconify windows that are on screen,
+    /// drags don't move anything, and nothing is auto-raised; windows that
+    /// asked to be mapped are mapped on thawing. Keybound actions still work.
+    /// The server isn't grabbed, so clients keep running.
+    fn toggle_freeze(&mut self, _: xproto::Window) -> Result<()>
+    where
+        Conn: Connection,
+    {
+        match self.freeze.toggle() {
+            None => log::warn!("Screen frozen; toggle_freeze again to thaw it."),
+            Some(deferred) => {
+                log::warn!("Screen thawed.");
+                let current = self.screen;
+                for window in deferred {
+                    // The window may be on another screen, or gone.
+                    match self.screen_of(window) {
+                        Some(screen) => self.switch_screen(screen),
+                        None => continue,
+                    }
+                    self.map_request(xproto::MapRequestEvent {
+                        response_type: xproto::MAP_REQUEST_EVENT,
+                        sequence: 0,
+                        parent: self.root(),
+                        window,
+                    })?;
+                }
+                self.switch_screen(current);
+            }
+        }
+        Ok(())
+    }
+
+    /// Switch between click-to-focus and focus-follows-mouse, reselecting
+    /// events on every managed window to suit.
+    fn toggle_focus_model(&mut self, _: xproto::Window) -> Result<()>
+    where
+        Conn: Connection,
+    {
+        let focus_model = self.config.focus_model.toggled();
+        log::info!("Switching to the {:?} focus model.", focus_model);
+        self.config.focus_model = focus_model;
+        let event_mask = focus_model.client_event_mask();
+        self.for_each_screen(|wm| {
+            for client in wm
+                .clients
+                .iter()
+                .filter(|client| !client.override_redirect())
+            {
+                if let Err(err) = wm
+                    .conn
+                    .change_window_attributes(
+                        client.window,
+                        &xproto::ChangeWindowAttributesAux::new().event_mask(event_mask),
+                    )?
+                    .check()
+                {
+                    // The window might have already been destroyed!
+                    log::warn!("{:?}", err);
+                }
+            }
+            Ok(())
+        })
+    }
+
+    /// Poison the window manager, causing it to die promptly. With
+    /// `quit_confirm`, this only happens on the second of two quick presses.
+    fn poison(&mut self, _: xproto::Window) -> Result<()> {
+        let now = Instant::now();
+        if !self.config.quit_confirm || quit_confirmed(self.quit_pending, now) {
+            self.keep_going = false;
+        } else {
+            log::warn!(
+                "Press the quit binding again within {} seconds to quit.",
+                QUIT_CONFIRM_TIMEOUT.as_secs()
+            );
+            self.quit_pending = Some(now);
+        }
+        Ok(())
+    }
+
+    /// Stop the window manager and start a fresh instance in its place.
+    fn restart(&mut self, _: xproto::Window) -> Result<()> {
+        self.restart = true;
+        self.keep_going = false;
+        Ok(())
+    }
+
+    // Simple utility stuff goes here.
+
+    /// Publish the client list, in both map and stacking order, on the root
+    /// window.
+    fn update_client_list(&self) -> Result<()>
+    where
+        Conn: Connection,
+    {
+        self.atoms.set_net_client_list(
+            &self.conn,
+            self.root(),
+            self.clients.client_list(),
+            &self.clients.client_list_stacking(),
+        )
+    }
+
+    /// Get the root window.
+    fn root(&self) -> xproto::Window
+    where
+        Conn: Connection,
+    {
+        self.conn.setup().roots[self.screen].root
+    }
+}
+
+/// Decide whether a WM_CHANGE_STATE message, with the given requested state,
+/// asks us to iconify the window. ICCCM 4.1.4 says that Iconic is the only
+/// state that clients may ask for this way.
+fn change_state_iconifies(state: u32) -> bool {
+    WmStateState::try_from(state) == Ok(WmStateState::Iconic)
+}
+
+/// Find the visible part of a window with the given outer size, once its
+/// client-side shadow (per _GTK_FRAME_EXTENTS) is taken off. Returns the
+/// visible size, and the offset of the visible part from the window's origin.
+fn visible_part(width: u32, height: u32, extents: [u32; 4]) -> (u32, u32, i32, i32) {
+    let [left, right, top, bottom] = extents;
+    (
+        width.saturating_sub(left + right),
+        height.saturating_sub(top + bottom),
+        left as i32,
+        top as i32,
+    )
+}
+
+/// Find the size preset after `current`, out of `len`, wrapping around.
+fn next_size_preset(current: Option<usize>, len: usize) -> Option<usize> {
+    if len == 0 {
+        return None;
+    }
+    Some(match current {
+        Some(index) => (index + 1) % len,
+        None => 0,
+    })
+}
+
+/// Decide whether a window's border should be hidden by `smart_borders`,
+/// given how many managed windows are viewable.
+fn smart_border_hidden(viewable: usize, is_viewable: bool) -> bool {
+    viewable == 1 && is_viewable
+}
+
+/// A direction on the screen.
+#[derive(PartialEq, Eq, Clone, Copy, Hash, Debug)]
+enum Direction {
+    /// Towards the left edge.
+    Left,
+    /// Towards the right edge.
+    Right,
+    /// Towards the top edge.
+    Up,
+    /// Towards the bottom edge.
+    Down,
+}
+
+impl Direction {
+    /// How far `to` is from `from` along this direction, and how far it is
+    /// across it.
+    fn offsets(self, from: (i32, i32), to: (i32, i32)) -> (i32, i32) {
+        let (dx, dy) = (to.0 - from.0, to.1 - from.1);
+        match self {
+            Direction::Left => (-dx, dy.abs()),
+            Direction::Right => (dx, dy.abs()),
+            Direction::Up => (-dy, dx.abs()),
+            Direction::Down => (dy, dx.abs()),
+        }
+    }
+
+    /// Pick the window whose center is nearest to `from` among those that lie
+    /// mostly in this direction from it. If there are none and `wrap` is set,
+    /// pick the window furthest the other way instead, preferring the one most
+    /// nearly in line with `from`.
+    fn nearest(
+        self,
+        from: (i32, i32),
+        candidates: &[(xproto::Window, (i32, i32))],
+        wrap: bool,
+    ) -> Option<xproto::Window> {
+        let nearest = candidates
+            .iter()
+            .filter_map(|&(window, center)| {
+                let (along, across) = self.offsets(from, center);
+                if along > 0 && along >= across {
+                    let (along, across) = (i64::from(along), i64::from(across));
+                    Some((along * along + across * across, window))
+                } else {
+                    None
+                }
+            })
+            .min();
+        match nearest {
+            Some((_, window)) => Some(window),
+            None if wrap => candidates
+                .iter()
+                .map(|&(window, center)| {
+                    let (along, across) = self.offsets(from, center);
+                    ((along, across), window)
+                })
+                .min()
+                .map(|(_, window)| window),
+            None => None,
+        }
+    }
+}
+
+/// A corner.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug)]
+enum Corner {
+    /// The top-left corner.
+    LeftTop,
+    /// The bottom-left corner.
+    LeftBottom,
+    /// The top-right corner.
+    RightTop,
+    /// The bottom-right corner.
+    RightBottom,
+}
+
+impl Corner {
+    /// Obtain the relative location of a corner for a given client window.
+    fn relative(&self, st: &ClientState) -> (i16, i16) {
+        match self {
+            Self::LeftTop => (0, 0),
+            Self::LeftBottom => (0, st.height as i16),
+            Self::RightTop => (st.width as i16, 0),
+            Self::RightBottom => (st.width as i16, st.height as i16),
+        }
+    }
+
+    /// Find the origin that puts a window, with the given outer size, flush
+    /// against this corner of a `screen_width` by `screen_height` screen, leaving
+    /// `margin` pixels to the edges.
+    fn pinned_origin(
+        &self,
+        screen_width: u16,
+        screen_height: u16,
+        width: u32,
+        height: u32,
+        margin: u16,
+    ) -> (i32, i32) {
+        let margin = i32::from(margin);
+        let left = margin;
+        let top = margin;
+        let right = i32::from(screen_width) - width as i32 - margin;
+        let bottom = i32::from(screen_height) - height as i32 - margin;
+        match self {
+            Self::LeftTop => (left, top),
+            Self::LeftBottom => (left, bottom),
+            Self::RightTop => (right, top),
+            Self::RightBottom => (right, bottom),
+        }
+    }
+}
+
+/// A type of drag: either moving or resizing from a particular corner.
 #[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Debug)]
 enum DragType {
     /// A moving drag.
@@ -822,34 +3036,1467 @@ enum DragType {
     Resize(Corner),
 }
 
-/// The state of a window drag.
+/// The state of a window drag.
+#[derive(Clone, Debug)]
+struct Drag {
+    /// The type of drag.
+    type_: DragType,
+    /// The window that is being dragged.
+    window: xproto::Window,
+    /// The x-position of the pointer relative to (a certain corner of) the
+    /// window.
+    x: i16,
+    /// The x-position of the pointer relative to (a certain corner of) the
+    /// window.
+    y: i16,
+}
+
+impl Drag {
+    /// Start a drag on a window, given the button that was pressed and the
+    /// pointer position relative to the window. Returns `None` if the button
+    /// doesn't drag, or the window is locked in place.
+    fn start(
+        window: xproto::Window,
+        st: &ClientState,
+        button: xproto::Button,
+        x: i16,
+        y: i16,
+    ) -> Option<Drag> {
+        if st.locked {
+            return None;
+        }
+        let (type_, corner) = match button {
+            1 => (DragType::Move, Corner::LeftTop),
+            3 => {
+                // We resize from whatever corner the pointer is
+                // closest to.
+                let mid_x = (st.width / 2) as i16;
+                let mid_y = (st.height / 2) as i16;
+                let corner = match (x >= mid_x, y >= mid_y) {
+                    (false, false) => Corner::LeftTop,
+                    (false, true) => Corner::LeftBottom,
+                    (true, false) => Corner::RightTop,
+                    (true, true) => Corner::RightBottom,
+                };
+                (DragType::Resize(corner), corner)
+            }
+            _ => {
+                log::error!("Invalid button.");
+                return None;
+            }
+        };
+        let (cx, cy) = corner.relative(st);
+        let x = x - (cx as i16);
+        let y = y - (cy as i16);
+        Some(Drag {
+            type_,
+            window,
+            x,
+            y,
+        })
+    }
+}
+
+/// Decide how to carry out a ConfigureRequest. Managed windows have their size
+/// clamped to their hints (unless `clamp` is false), and locked windows can't
+/// be moved or resized at all, although they may still be restacked.
+fn configure_request_aux(
+    ev: &xproto::ConfigureRequestEvent,
+    client: &Client,
+    clamp: bool,
+) -> ConfigureWindowAux {
+    let mut value_list = ConfigureWindowAux::from_configure_request(ev);
+    // Windows that have override-redirect set can do whatever they want.
+    if client.override_redirect() {
+        return value_list;
+    }
+    let st = client.state.as_ref().unwrap();
+    if st.locked {
+        value_list.x = None;
+        value_list.y = None;
+        value_list.width = None;
+        value_list.height = None;
+        value_list.border_width = None;
+        return value_list;
+    }
+    if !clamp {
+        return value_list;
+    }
+    let ((min_width, min_height), (max_width, max_height)) = size_limits(&st.wm_normal_hints);
+    value_list.width = value_list
+        .width
+        .map(|w| w.max(min_width as u32).min(max_width as u32));
+    value_list.height = value_list
+        .height
+        .map(|h| h.max(min_height as u32).min(max_height as u32));
+    value_list
+}
+
+/// How a key press is treated while waiting for the name of a mark.
+#[derive(PartialEq, Eq, Clone, Copy, Hash, Debug)]
+enum MarkKey {
+    /// A modifier, which we skip over so that it can be combined with the
+    /// next key.
+    Modifier,
+    /// A letter or digit, which names a mark.
+    Mark(char),
+    /// Anything else, which cancels.
+    Cancel,
+}
+
+/// Decide what a key press means while waiting for the name of a mark.
+fn mark_key(keysym: xproto::Keysym) -> MarkKey {
+    // Shift_L through Hyper_R.
+    if (0xffe1..=0xffee).contains(&keysym) {
+        return MarkKey::Modifier;
+    }
+    // Latin-1 keysyms are the same as the characters.
+    match char::from_u32(keysym) {
+        Some(c) if c.is_ascii_alphanumeric() => MarkKey::Mark(c),
+        _ => MarkKey::Cancel,
+    }
+}
+
+/// The state of `toggle_freeze`.
+#[derive(Clone, Debug, Default)]
+struct Freeze {
+    /// Whether the screen is frozen.
+    active: bool,
+    /// Windows that asked to be mapped while the screen was frozen.
+    deferred_maps: Vec<xproto::Window>,
+}
+
+impl Freeze {
+    /// Indicates whether the screen is frozen.
+    fn is_active(&self) -> bool {
+        self.active
+    }
+
+    /// Freeze or thaw the screen. On thawing, return the windows whose
+    /// mapping was deferred.
+    fn toggle(&mut self) -> Option<Vec<xproto::Window>> {
+        self.active = !self.active;
+        if self.active {
+            None
+        } else {
+            Some(std::mem::take(&mut self.deferred_maps))
+        }
+    }
+
+    /// Indicates whether a change to the screen should be held back because
+    /// it's frozen.
+    fn holds(&self, change: Change) -> bool {
+        self.is_active()
+            && match change {
+                Change::Configure { viewable } => viewable,
+                _ => true,
+            }
+    }
+
+    /// Defer mapping a window if the screen is frozen, returning whether it
+    /// was deferred.
+    fn defer_map(&mut self, window: xproto::Window) -> bool {
+        if self.active && !self.deferred_maps.contains(&window) {
+            self.deferred_maps.push(window);
+        }
+        self.active
+    }
+}
+
+/// Something that would change the screen, which `toggle_freeze` may hold
+/// back. Map requests are deferred instead; see `Freeze::defer_map`.
+#[derive(PartialEq, Eq, Clone, Copy, Hash, Debug)]
+enum Change {
+    /// A client asking to move, resize or restack a window, which may or may
+    /// not be on screen yet.
+    Configure { viewable: bool },
+    /// A client asking to go fullscreen or back.
+    Fullscreen,
+    /// A client asking to be focused and raised.
+    Activate,
+    /// A client asking to be iconified.
+    Iconify,
+    /// The pointer dragging a window.
+    Drag,
+    /// A pending automatic raise.
+    AutoRaise,
+}
+
+/// How a key press is treated in prefix mode.
+#[derive(PartialEq, Eq, Clone, Copy, Hash, Debug)]
+enum PrefixKey {
+    /// A modifier, which we skip over so that it can be combined with the
+    /// next key.
+    Modifier,
+    /// Escape, which leaves prefix mode without doing anything.
+    Cancel,
+    /// Anything else, which is looked up in `prefix_keybinds`.
+    Key,
+}
+
+/// Decide what a key press means in prefix mode.
+fn prefix_key(keysym: xproto::Keysym) -> PrefixKey {
+    match keysym {
+        // Shift_L through Hyper_R.
+        0xffe1..=0xffee => PrefixKey::Modifier,
+        // Escape
+        0xff1b => PrefixKey::Cancel,
+        _ => PrefixKey::Key,
+    }
+}
+
+/// The state of prefix mode, which lasts for one key press after the
+/// `prefix` action.
+#[derive(Clone, Debug, Default)]
+struct Prefix {
+    /// Whether we're in prefix mode.
+    active: bool,
+}
+
+impl Prefix {
+    /// Enter prefix mode.
+    fn enter(&mut self) {
+        self.active = true;
+    }
+
+    /// Indicates whether we're in prefix mode.
+    fn is_active(&self) -> bool {
+        self.active
+    }
+
+    /// Handle a key press, returning whether it should be looked up in
+    /// `prefix_keybinds`. Anything but a modifier leaves prefix mode.
+    fn press(&mut self, key: PrefixKey) -> bool {
+        if !self.active {
+            return false;
+        }
+        match key {
+            PrefixKey::Modifier => false,
+            PrefixKey::Cancel => {
+                self.active = false;
+                false
+            }
+            PrefixKey::Key => {
+                self.active = false;
+                true
+            }
+        }
+    }
+}
+
+/// Deal with the result of loading the config file. If there isn't one, the
+/// defaults are used and saved; if it can't be parsed or isn't valid, the
+/// built-in config is used instead, so that there's still a working window
+/// manager. Other errors (e.g., not being allowed to read the file) are fatal.
+/// The defaults are only saved if we're going to run with them.
+fn config_or_fallback<Conn>(loaded: Result<Config<Conn>>, purpose: Purpose) -> Result<Config<Conn>>
+where
+    Conn: Connection,
+{
+    let err = match loaded {
+        Ok(config) => return Ok(config),
+        Err(err) => err,
+    };
+    //File access errors
+    if let Some(io_error) = err.downcast_ref::<std::io::Error>() {
+        match io_error.kind() {
+            std::io::ErrorKind::NotFound => log::info!("Configuration file not found."),
+            std::io::ErrorKind::PermissionDenied => {
+                log::error!("Permission denied trying to read configuration file, aborting");
+                return Err(err);
+            }
+            _ => return Err(err),
+        }
+    }
+    // Deserialization format errors
+    if let Some(de_error) = err.downcast_ref::<toml::de::Error>() {
+        log::error!("Failed to parse config.toml: {}", de_error);
+    // Config.toml content errors
+    } else if let Some(config_error) = err.downcast_ref::<ConfigError>() {
+        log::error!("{}", config_error);
+    } else {
+        log::info!("Applying default configuration.");
+        let default_config = Config::new(purpose)?;
+        if purpose == Purpose::Run {
+            default_config.save().map_err(|save_err| {
+                log::error!("{}", save_err);
+                save_err
+            })?;
+        }
+        return Ok(default_config);
+    }
+    log::warn!("**********************************************************************");
+    log::warn!("Your config.toml can't be used, so OxWM is running in safe mode with");
+    log::warn!("its built-in configuration. Fix the errors above and restart.");
+    log::warn!("**********************************************************************");
+    Ok(Config::built_in(purpose))
+}
+
+/// Fill in settings that the config file didn't set from the X resource
+/// database on `root`, if there is one.
+fn apply_x_resources<Conn>(
+    config: &mut Config<Conn>,
+    conn: &Conn,
+    atoms: &Atoms,
+    root: xproto::Window,
+) -> Result<()>
+where
+    Conn: Connection,
+{
+    if let Some(db) = atoms.get_resource_manager(conn, root)? {
+        log::debug!("Reading the X resource database.");
+        config.apply_resources(&parse_resources(&db));
+    }
+    Ok(())
+}
+
+/// Decide whether a window that's just been mapped should get the focus.
+/// Override-redirect windows never do.
+fn focus_on_map(
+    client: &Client,
+    focus_new_windows: FocusNew,
+    user_time: Option<xproto::Timestamp>,
+    last_user_time: xproto::Timestamp,
+) -> bool {
+    match client.state {
+        Some(ref st) => focus_new_windows.should_focus(st.modal, user_time, last_user_time),
+        None => false,
+    }
+}
+
+/// Decide whether to honor a _NET_ACTIVE_WINDOW request, given its source
+/// indication and timestamp. Requests from pagers (source 2) act for the user,
+/// so they're always honored; those from applications (source 1), or from
+/// clients too old to say (source 0), are subject to the same policy as
+/// focusing newly-mapped windows.
+fn activation_allowed(
+    source: u32,
+    timestamp: xproto::Timestamp,
+    focus_new_windows: FocusNew,
+    last_user_time: xproto::Timestamp,
+) -> bool {
+    if source == 2 {
+        return true;
+    }
+    // A timestamp of zero means that the client doesn't know.
+    let timestamp = Some(timestamp).filter(|&time| time != 0);
+    focus_new_windows.should_focus(false, timestamp, last_user_time)
+}
+
+/// Decide whether a click on a window should be passed on to it, or swallowed
+/// because it was only meant to focus the window.
+fn replay_click(passes_click: bool, was_focused: bool) -> bool {
+    passes_click || was_focused
+}
+
+/// Decide whether a MappingNotify with the given request field means that our
+/// button grabs need refreshing. Only pointer mapping changes affect them.
+fn mapping_changes_buttons(request: xproto::Mapping) -> bool {
+    request == xproto::Mapping::POINTER
+}
+
+/// The minimum and maximum sizes of a window, from its WM_NORMAL_HINTS or our
+/// defaults. A hinted minimum is respected even if it's below our default,
+/// but never below `MIN_SIZE_FLOOR`.
+fn size_limits(hints: &WmSizeHints) -> ((i32, i32), (i32, i32)) {
+    let (min_width, min_height) = match hints.min_size {
+        Some((width, height)) => (width.max(MIN_SIZE_FLOOR), height.max(MIN_SIZE_FLOOR)),
+        None => (i32::from(MIN_WIDTH), i32::from(MIN_HEIGHT)),
+    };
+    let max = hints
+        .max_size
+        .unwrap_or((i32::from(MAX_WIDTH), i32::from(MAX_HEIGHT)));
+    ((min_width, min_height), max)
+}
+
+/// Clamp a window's size to the minimum and maximum in its WM_NORMAL_HINTS,
+/// or to our defaults if it doesn't give any.
+fn clamp_size(hints: &WmSizeHints, width: u16, height: u16) -> (u16, u16) {
+    let ((min_width, min_height), (max_width, max_height)) = size_limits(hints);
+    let clamp = |size: u16, min: i32, max: i32| {
+        let size = i32::from(size).max(min).min(max);
+        u16::try_from(size.max(0)).unwrap_or(u16::MAX)
+    };
+    (
+        clamp(width, min_width, max_width),
+        clamp(height, min_height, max_height),
+    )
+}
+
+/// Scale a window's geometry by `percent` percent about its center. The new
+/// size is clamped to the window's size hints and then to `max_size`, the
+/// largest size that fits on the screen.
+fn scale_geometry(
+    hints: &WmSizeHints,
+    (x, y, width, height): (i16, i16, u16, u16),
+    (max_width, max_height): (u16, u16),
+    percent: i32,
+) -> (i32, i32, u16, u16) {
+    let scale = |size: u16| {
+        let size = i32::from(size) * (100 + percent) / 100;
+        u16::try_from(size.max(0)).unwrap_or(u16::MAX)
+    };
+    let (new_width, new_height) = clamp_size(hints, scale(width), scale(height));
+    let (new_width, new_height) = (new_width.min(max_width), new_height.min(max_height));
+    let recenter =
+        |pos: i16, old: u16, new: u16| i32::from(pos) + (i32::from(old) - i32::from(new)) / 2;
+    (
+        recenter(x, width, new_width),
+        recenter(y, height, new_height),
+        new_width,
+        new_height,
+    )
+}
+
+/// Allocate a color in a screen's default colormap, returning its pixel value.
+fn alloc_color<Conn>(conn: &Conn, screen: usize, color: Color) -> Result<u32>
+where
+    Conn: Connection,
+{
+    let colormap = conn.setup().roots[screen].default_colormap;
+    // X color components are 16 bits; scale ours up so that 0xff is full.
+    let scale = |c: u8| u16::from(c) * 0x101;
+    let reply = conn
+        .alloc_color(
+            colormap,
+            scale(color.red),
+            scale(color.green),
+            scale(color.blue),
+        )?
+        .reply()?;
+    Ok(reply.pixel)
+}
+
+/// Check whether a child process has already exited unsuccessfully, returning
+/// its exit status if so. This doesn't wait for the child.
+fn check_exited(
+    child: &mut std::process::Child,
+) -> std::io::Result<Option<std::process::ExitStatus>> {
+    Ok(child.try_wait()?.filter(|status| !status.success()))
+}
+
+/// Check whether we were started by a restart, and clear the flag so that our
+/// own children don't inherit it.
+fn take_restart_flag() -> bool {
+    let restarted = std::env::var_os(RESTART_ENV_VAR).is_some();
+    std::env::remove_var(RESTART_ENV_VAR);
+    restarted
+}
+
+/// Replace the current process with a fresh instance of OxWM.
+fn restart_wm() -> Result<()> {
+    log::info!("Restarting.");
+    let err = Command::new(std::env::current_exe()?)
+        .args(std::env::args_os().skip(1))
+        .env(RESTART_ENV_VAR, "1")
+        .exec();
+    // `exec` only returns if it failed.
+    Err(Box::new(err))
+}
+
+/// Decide whether a press of the quit binding at time `now` confirms an
+/// earlier press at time `pending`.
+fn quit_confirmed(pending: Option<Instant>, now: Instant) -> bool {
+    match pending {
+        Some(pending) => now.saturating_duration_since(pending) <= QUIT_CONFIRM_TIMEOUT,
+        None => false,
+    }
+}
+
+/// A window zoomed by `toggle_zoom_focus`.
 #[derive(Clone, Debug)]
-struct Drag {
-    /// The type of drag.
-    type_: DragType,
-    /// The window that is being dragged.
+struct Zoom {
+    /// The zoomed window.
     window: xproto::Window,
-    /// The x-position of the pointer relative to (a certain corner of) the
-    /// window.
-    x: i16,
-    /// The x-position of the pointer relative to (a certain corner of) the
-    /// window.
-    y: i16,
+    /// The windows that were hidden to zoom it.
+    hidden: Vec<xproto::Window>,
+    /// Whether the zoomed window was already fullscreen, in which case it
+    /// stays that way afterwards.
+    was_fullscreen: bool,
+}
+
+/// The state that we keep separately for each screen that we manage. The
+/// current screen's state lives in the same-named fields of `OxWM`, and the
+/// others' are parked in `OxWM::other_screens`.
+struct ScreenState {
+    /// Local client data.
+    clients: Clients,
+    /// Recently-focused windows.
+    focus_history: FocusHistory,
+    /// Windows labelled by `mark_window`.
+    marks: Marks,
+    /// The windows that `group_cycle` moves the focus around.
+    group: Group,
+    /// The window that `toggle_scratch_focus` flips to.
+    scratch: Scratch,
+    /// The windows that `minimize_others` iconified, for `restore_others`.
+    minimized_others: Vec<xproto::Window>,
+    /// The window zoomed by `toggle_zoom_focus`, if any.
+    zoom: Option<Zoom>,
+    /// Whether every window's border is hidden by `toggle_all_borders`.
+    borders_hidden: bool,
+    /// The built-in status line, if enabled.
+    bar: Option<Bar>,
+    /// `config.border_width`, scaled for this screen if `border_width_mm` is
+    /// set.
+    border_width: u16,
+    /// The pixel value for `config.border_color`.
+    border_pixel: u32,
+    /// The pixel value for `config.locked_border_color`.
+    locked_border_pixel: u32,
+    /// The pixel value for `config.urgent_border_color`.
+    urgent_border_pixel: u32,
+}
+
+impl ScreenState {
+    /// Query the server for a screen's windows and allocate its colors.
+    fn new<Conn>(conn: &Conn, screen: usize, atoms: &Atoms, config: &Config<Conn>) -> Result<Self>
+    where
+        Conn: Connection,
+    {
+        let size = &conn.setup().roots[screen];
+        Ok(ScreenState {
+            clients: Clients::new(
+                conn,
+                screen,
+                atoms,
+                config.track_override_redirect,
+                config.title_encoding,
+            )?,
+            focus_history: FocusHistory::new(),
+            marks: Marks::new(),
+            group: Group::new(),
+            scratch: Scratch::new(),
+            minimized_others: Vec::new(),
+            zoom: None,
+            borders_hidden: false,
+            bar: None,
+            border_width: config
+                .scaled_border_width(size.width_in_pixels, size.width_in_millimeters),
+            border_pixel: alloc_color(conn, screen, config.border_color)?,
+            locked_border_pixel: alloc_color(conn, screen, config.locked_border_color)?,
+            urgent_border_pixel: alloc_color(conn, screen, config.urgent_border_color)?,
+        })
+    }
+}
+
+/// The root windows to select SubstructureRedirect on to become the window
+/// manager, by screen index: every screen's, starting with `home`, the one that
+/// we connected on.
+fn roots_to_redirect(roots: &[xproto::Screen], home: usize) -> Vec<(usize, xproto::Window)> {
+    let home_root = (home, roots[home].root);
+    let others = roots
+        .iter()
+        .enumerate()
+        .filter(|&(screen, _)| screen != home)
+        .map(|(screen, s)| (screen, s.root));
+    std::iter::once(home_root).chain(others).collect()
+}
+
+/// Make `screen` the current screen: take its state out of `parked`, let `swap`
+/// exchange it with the live state, and park the result under the old current
+/// screen. Returns whether we manage `screen`; if not, nothing changes.
+fn swap_screen<T, F>(
+    parked: &mut HashMap<usize, T>,
+    current: &mut usize,
+    screen: usize,
+    swap: F,
+) -> bool
+where
+    F: FnOnce(&mut T),
+{
+    if screen == *current {
+        return true;
+    }
+    let mut state = match parked.remove(&screen) {
+        Some(state) => state,
+        None => return false,
+    };
+    swap(&mut state);
+    parked.insert(*current, state);
+    *current = screen;
+    true
+}
+
+/// Find the managed screen that a window belongs to: the screen whose root it
+/// is, if we manage that screen, or else the screen that has it as a client.
+/// `in_current` says whether the current screen has it, and `in_parked` checks
+/// a parked screen's state.
+fn find_screen<T, F>(
+    roots: &[xproto::Window],
+    current: usize,
+    parked: &HashMap<usize, T>,
+    window: xproto::Window,
+    in_current: bool,
+    in_parked: F,
+) -> Option<usize>
+where
+    F: Fn(&T) -> bool,
+{
+    if let Some(screen) = roots.iter().position(|&root| root == window) {
+        return Some(screen).filter(|s| *s == current || parked.contains_key(s));
+    }
+    if in_current {
+        return Some(current);
+    }
+    parked
+        .iter()
+        .find(|(_, state)| in_parked(state))
+        .map(|(&screen, _)| screen)
+}
+
+/// Every screen that we manage, in order.
+fn screen_order<T>(current: usize, parked: &HashMap<usize, T>) -> Vec<usize> {
+    let mut screens: Vec<usize> = parked.keys().copied().collect();
+    screens.push(current);
+    screens.sort_unstable();
+    screens
+}
+
+/// The window that tells us which screen an event is for: a root window, or a
+/// client on that screen. Returns `None` for events that aren't tied to a
+/// screen.
+fn event_window(ev: &Event) -> Option<xproto::Window> {
+    match ev {
+        ButtonPress(ev) | ButtonRelease(ev) => Some(ev.event),
+        MotionNotify(ev) => Some(ev.event),
+        KeyPress(ev) | KeyRelease(ev) => Some(ev.event),
+        EnterNotify(ev) | LeaveNotify(ev) => Some(ev.event),
+        FocusIn(ev) | FocusOut(ev) => Some(ev.event),
+        Expose(ev) => Some(ev.window),
+        ClientMessage(ev) => Some(ev.window),
+        ConfigureNotify(ev) => Some(ev.event),
+        ConfigureRequest(ev) => Some(ev.parent),
+        CreateNotify(ev) => Some(ev.parent),
+        DestroyNotify(ev) => Some(ev.event),
+        MapNotify(ev) => Some(ev.event),
+        MapRequest(ev) => Some(ev.parent),
+        UnmapNotify(ev) => Some(ev.event),
+        PropertyNotify(ev) => Some(ev.window),
+        VisibilityNotify(ev) => Some(ev.window),
+        _ => None,
+    }
+}
+
+/// A pending automatic raise of a window that has been focused by the pointer.
+#[derive(Clone, Debug)]
+struct AutoRaise {
+    /// The window to raise.
+    window: xproto::Window,
+    /// When to raise it.
+    deadline: Instant,
+}
+
+impl AutoRaise {
+    /// Decide whether to schedule an automatic raise for a window that was just
+    /// focused at time `now`. Auto-raising is disabled if `delay_ms` is zero,
+    /// and never happens in the middle of a drag.
+    fn schedule(
+        window: xproto::Window,
+        delay_ms: u64,
+        now: Instant,
+        dragging: bool,
+    ) -> Option<AutoRaise> {
+        if delay_ms == 0 || dragging {
+            return None;
+        }
+        Some(AutoRaise {
+            window,
+            deadline: now + Duration::from_millis(delay_ms),
+        })
+    }
+
+    /// Indicates whether the raise should happen at time `now`.
+    fn is_due(&self, now: Instant) -> bool {
+        now >= self.deadline
+    }
+}
+
+/// Startup programs to check on once `startup_check_ms` has passed, to report
+/// any that have already failed.
+#[derive(Debug)]
+struct StartupCheck {
+    /// The programs, by name, and their processes.
+    children: Vec<(String, std::process::Child)>,
+    /// When to check on them.
+    deadline: Instant,
+}
+
+impl StartupCheck {
+    /// Decide whether to check on startup programs that were spawned at time
+    /// `now`. Checking is disabled if `delay_ms` is zero.
+    fn schedule(
+        children: Vec<(String, std::process::Child)>,
+        delay_ms: u64,
+        now: Instant,
+    ) -> Option<StartupCheck> {
+        if delay_ms == 0 || children.is_empty() {
+            return None;
+        }
+        Some(StartupCheck {
+            children,
+            deadline: now + Duration::from_millis(delay_ms),
+        })
+    }
+
+    /// Indicates whether the check should happen at time `now`.
+    fn is_due(&self, now: Instant) -> bool {
+        now >= self.deadline
+    }
+
+    /// Log an error for each program that has already failed.
+    fn report(self) {
+        for (program, mut child) in self.children {
+            match check_exited(&mut child) {
+                Ok(Some(status)) => {
+                    log::error!("Startup program `{}' failed: {}", program, status)
+                }
+                Ok(None) => (),
+                Err(err) => log::warn!("Unable to check on `{}': {:?}", program, err),
+            }
+        }
+    }
 }
 
-/// Run the window manager.
+/// Run the window manager. If the connection to the X server is lost, either
+/// reconnect and start over (with `reconnect`) or exit with
+/// `CONNECTION_LOST_EXIT_CODE`, so that a supervisor can tell what happened.
 fn run_wm() -> Result<()> {
-    log::debug!("Connecting to the X server.");
-    let (conn, screen) = x11rb::connect(None)?;
-    log::info!("Connected on screen {}.", screen);
-    log::debug!("Initializing OxWM.");
-    let oxwm = OxWM::new(conn, screen)?;
-    log::debug!("Running OxWM.");
-    oxwm.run()
+    let mut restarted = take_restart_flag();
+    let mut attempts = 0;
+    loop {
+        log::debug!("Connecting to the X server.");
+        let (conn, screen) = match x11rb::rust_connection::RustConnection::connect(None) {
+            Ok(connected) => connected,
+            // Only retry if we've been connected before, and so have been
+            // asked to reconnect.
+            Err(err) if attempts > 0 && attempts < RECONNECT_ATTEMPTS => {
+                log::warn!("Failed to reconnect to the X server: {}", err);
+                attempts += 1;
+                std::thread::sleep(RECONNECT_DELAY);
+                continue;
+            }
+            Err(err) if attempts > 0 => {
+                log::error!("Giving up on reconnecting to the X server: {}", err);
+                std::process::exit(CONNECTION_LOST_EXIT_CODE);
+            }
+            Err(err) => return Err(err.into()),
+        };
+        log::info!("Connected on screen {}.", screen);
+        let conn_fd = conn.stream().as_raw_fd();
+        log::debug!("Initializing OxWM.");
+        let oxwm = OxWM::new(conn, conn_fd, screen, restarted)?;
+        let reconnect = oxwm.config.reconnect;
+        log::debug!("Running OxWM.");
+        match oxwm.run() {
+            Ok(true) => return restart_wm(),
+            Ok(false) => return Ok(()),
+            Err(err) if connection_lost(err.as_ref()) => {
+                log::error!("Lost the connection to the X server: {}", err);
+                if !reconnect {
+                    std::process::exit(CONNECTION_LOST_EXIT_CODE);
+                }
+                log::info!("Reconnecting.");
+                // Don't run the startup programs again.
+                restarted = true;
+                attempts = 1;
+            }
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+/// Decide whether an error means that the connection to the X server has
+/// been lost, as opposed to some request having failed.
+fn connection_lost(err: &(dyn Error + 'static)) -> bool {
+    let conn_error = if let Some(err) = err.downcast_ref::<ConnectionError>() {
+        err
+    } else if let Some(ReplyError::ConnectionError(err)) = err.downcast_ref::<ReplyError>() {
+        err
+    } else if let Some(ReplyOrIdError::ConnectionError(err)) = err.downcast_ref::<ReplyOrIdError>()
+    {
+        err
+    } else {
+        return false;
+    };
+    matches!(conn_error, ConnectionError::IOError(_))
+}
+
+/// Print the configuration that OxWM would run with, with defaults and X
+/// resources filled in, as TOML. If the config file can't be used, that's the
+/// built-in config. The border width is the one for the default screen.
+fn print_config() -> Result<()> {
+    type Conn = x11rb::rust_connection::RustConnection;
+    let mut config = config_or_fallback(Config::<Conn>::load(Purpose::Print), Purpose::Print)?;
+    // Without a server, there are just no resources to merge in, and no
+    // screen to scale the border width for.
+    match x11rb::rust_connection::RustConnection::connect(None) {
+        Ok((conn, screen)) => {
+            let atoms = Atoms::new(&conn)?;
+            let root_screen = &conn.setup().roots[screen];
+            apply_x_resources(&mut config, &conn, &atoms, root_screen.root)?;
+            config.border_width = config.scaled_border_width(
+                root_screen.width_in_pixels,
+                root_screen.width_in_millimeters,
+            );
+        }
+        Err(err) => log::warn!("Not reading X resources: {}", err),
+    }
+    print!("{}", config.to_toml()?);
+    Ok(())
+}
+
+/// Print which keys are bound to each modifier, to help with choosing a
+/// `mod_mask`.
+fn print_modifier_map() -> Result<()> {
+    let (conn, _) = x11rb::rust_connection::RustConnection::connect(None)?;
+    let modmap = conn.get_modifier_mapping()?.reply()?;
+    let setup = conn.setup();
+    let count = setup.max_keycode - setup.min_keycode + 1;
+    let keymap = conn
+        .get_keyboard_mapping(setup.min_keycode, count)?
+        .reply()?;
+    let per = keymap.keysyms_per_keycode as usize;
+    let keycode_name = |keycode: xproto::Keycode| {
+        let i = keycode.checked_sub(setup.min_keycode)? as usize * per;
+        keymap
+            .keysyms
+            .get(i)
+            .filter(|&&keysym| keysym != 0)
+            .and_then(|&keysym| keysym_name(keysym))
+    };
+    print!(
+        "{}",
+        format_modifier_map(
+            modmap.keycodes_per_modifier(),
+            &modmap.keycodes,
+            keycode_name
+        )
+    );
+    Ok(())
 }
 
 /// Run the program.
 fn main() -> Result<()> {
     simple_logger::SimpleLogger::new().init()?;
-    run_wm()
+    match std::env::args().nth(1).as_deref() {
+        None => run_wm(),
+        Some("--modmap") => print_modifier_map(),
+        Some("--print-config") => print_config(),
+        Some(arg) => Err(format!("Unrecognized argument `{}'", arg).into()),
+    }
+}
+
+/// Confirm that only I/O errors on the connection count as losing it.
+#[test]
+fn check_connection_lost() {
+    let io_error = || std::io::Error::from(std::io::ErrorKind::UnexpectedEof);
+    let lost: Box<dyn Error> = Box::new(ConnectionError::IOError(io_error()));
+    assert!(connection_lost(lost.as_ref()));
+    let lost: Box<dyn Error> = Box::new(ReplyError::ConnectionError(ConnectionError::IOError(
+        io_error(),
+    )));
+    assert!(connection_lost(lost.as_ref()));
+    let lost: Box<dyn Error> = Box::new(ReplyOrIdError::ConnectionError(ConnectionError::IOError(
+        io_error(),
+    )));
+    assert!(connection_lost(lost.as_ref()));
+
+    let other: Box<dyn Error> = Box::new(ConnectionError::UnknownError);
+    assert!(!connection_lost(other.as_ref()));
+    let other: Box<dyn Error> = Box::new(ReplyOrIdError::IdsExhausted);
+    assert!(!connection_lost(other.as_ref()));
+    let other: Box<dyn Error> = Box::new(io_error());
+    assert!(!connection_lost(other.as_ref()));
+}
+
+/// Confirm that the restart flag is detected from the environment, and that it
+/// isn't passed on once it's been read.
+#[test]
+fn check_take_restart_flag() {
+    std::env::remove_var(RESTART_ENV_VAR);
+    assert!(!take_restart_flag());
+    std::env::set_var(RESTART_ENV_VAR, "1");
+    assert!(take_restart_flag());
+    assert!(std::env::var_os(RESTART_ENV_VAR).is_none());
+    assert!(!take_restart_flag());
+}
+
+/// Confirm that auto-raises are only scheduled when enabled and not dragging,
+/// and that they become due after the configured delay.
+#[test]
+fn check_auto_raise_schedule() {
+    let now = Instant::now();
+    assert!(AutoRaise::schedule(100, 0, now, false).is_none());
+    assert!(AutoRaise::schedule(100, 500, now, true).is_none());
+    let auto_raise = AutoRaise::schedule(100, 500, now, false).unwrap();
+    assert_eq!(auto_raise.window, 100);
+    assert!(!auto_raise.is_due(now));
+    assert!(!auto_raise.is_due(now + Duration::from_millis(499)));
+    assert!(auto_raise.is_due(now + Duration::from_millis(500)));
+    assert!(auto_raise.is_due(now + Duration::from_millis(1000)));
+}
+
+/// Confirm that quitting needs a second press, and that it has to come
+/// before the timeout.
+#[test]
+fn check_quit_confirmed() {
+    let now = Instant::now();
+    assert!(!quit_confirmed(None, now));
+    assert!(quit_confirmed(Some(now), now + Duration::from_millis(500)));
+    assert!(quit_confirmed(Some(now), now + QUIT_CONFIRM_TIMEOUT));
+    assert!(!quit_confirmed(
+        Some(now),
+        now + QUIT_CONFIRM_TIMEOUT + Duration::from_millis(1)
+    ));
+}
+
+/// Confirm that letters and digits name marks, that modifiers are skipped,
+/// and that anything else cancels.
+#[test]
+fn check_mark_key() {
+    assert_eq!(mark_key(0x61), MarkKey::Mark('a'));
+    assert_eq!(mark_key(0x5a), MarkKey::Mark('Z'));
+    assert_eq!(mark_key(0x37), MarkKey::Mark('7'));
+    // Shift_L, Super_L
+    assert_eq!(mark_key(0xffe1), MarkKey::Modifier);
+    assert_eq!(mark_key(0xffeb), MarkKey::Modifier);
+    // Escape, space
+    assert_eq!(mark_key(0xff1b), MarkKey::Cancel);
+    assert_eq!(mark_key(0x20), MarkKey::Cancel);
+}
+
+/// Confirm that maps are only deferred while frozen, and are handed back on
+/// thawing.
+#[test]
+fn check_freeze() {
+    let mut freeze = Freeze::default();
+    assert!(!freeze.is_active());
+    assert!(!freeze.defer_map(100));
+    assert_eq!(freeze.toggle(), None);
+    assert!(freeze.is_active());
+    assert!(freeze.defer_map(100));
+    assert!(freeze.defer_map(200));
+    assert!(freeze.defer_map(100));
+    assert_eq!(freeze.toggle(), Some(vec![100, 200]));
+    assert!(!freeze.is_active());
+    assert!(!freeze.defer_map(300));
+    assert_eq!(freeze.toggle(), None);
+    assert_eq!(freeze.toggle(), Some(vec![]));
+}
+
+/// Confirm that freezing holds back everything that would change the screen,
+/// except for windows that aren't on screen yet configuring themselves, and
+/// that thawing lets it all through again.
+#[test]
+fn check_freeze_holds() {
+    let changes = [
+        Change::Configure { viewable: true },
+        Change::Fullscreen,
+        Change::Activate,
+        Change::Iconify,
+        Change::Drag,
+        Change::AutoRaise,
+    ];
+    let mut freeze = Freeze::default();
+    for &change in &changes {
+        assert!(!freeze.holds(change), "{:?}", change);
+    }
+    assert!(!freeze.holds(Change::Configure { viewable: false }));
+    freeze.toggle();
+    for &change in &changes {
+        assert!(freeze.holds(change), "{:?}", change);
+    }
+    assert!(!freeze.holds(Change::Configure { viewable: false }));
+    freeze.toggle();
+    for &change in &changes {
+        assert!(!freeze.holds(change), "{:?}", change);
+    }
+}
+
+/// Confirm that pagers can always activate windows, but applications only
+/// under the focus-stealing policy.
+#[test]
+fn check_activation_allowed() {
+    for &policy in &[FocusNew::Always, FocusNew::Never, FocusNew::Smart] {
+        assert!(activation_allowed(2, 0, policy, 1000));
+        assert!(activation_allowed(2, 500, policy, 1000));
+    }
+    for &source in &[0, 1] {
+        assert!(activation_allowed(source, 500, FocusNew::Always, 1000));
+        assert!(!activation_allowed(source, 2000, FocusNew::Never, 1000));
+        // Smart only lets through requests made since the last user input.
+        assert!(activation_allowed(source, 2000, FocusNew::Smart, 1000));
+        assert!(!activation_allowed(source, 500, FocusNew::Smart, 1000));
+        assert!(activation_allowed(source, 0, FocusNew::Smart, 1000));
+    }
+}
+
+/// Confirm that prefix mode lasts for one key press, skipping modifiers, and
+/// that Escape leaves it without dispatching anything.
+#[test]
+fn check_prefix() {
+    // a, Shift_L, Escape
+    assert_eq!(prefix_key(0x61), PrefixKey::Key);
+    assert_eq!(prefix_key(0xffe1), PrefixKey::Modifier);
+    assert_eq!(prefix_key(0xff1b), PrefixKey::Cancel);
+
+    let mut prefix = Prefix::default();
+    assert!(!prefix.is_active());
+    assert!(!prefix.press(PrefixKey::Key));
+
+    prefix.enter();
+    assert!(prefix.is_active());
+    assert!(!prefix.press(PrefixKey::Modifier));
+    assert!(prefix.is_active());
+    assert!(prefix.press(PrefixKey::Key));
+    assert!(!prefix.is_active());
+    assert!(!prefix.press(PrefixKey::Key));
+
+    prefix.enter();
+    assert!(!prefix.press(PrefixKey::Cancel));
+    assert!(!prefix.is_active());
+}
+
+/// Confirm that proportional resizing scales about the window's center, and
+/// stops at the minimum size and at the screen's size.
+#[test]
+fn check_scale_geometry() {
+    let hints = WmSizeHints::new();
+    let screen = (1920, 1080);
+    assert_eq!(
+        scale_geometry(&hints, (100, 100, 400, 300), screen, 10),
+        (80, 85, 440, 330)
+    );
+    assert_eq!(
+        scale_geometry(&hints, (100, 100, 400, 300), screen, -10),
+        (120, 115, 360, 270)
+    );
+    assert_eq!(
+        scale_geometry(&hints, (100, 100, 130, 130), screen, -10),
+        (101, 101, MIN_WIDTH, MIN_HEIGHT)
+    );
+    assert_eq!(
+        scale_geometry(&hints, (0, 0, 1900, 1000), screen, 10),
+        (-10, -40, 1920, 1080)
+    );
+}
+
+/// Confirm that a malformed or invalid config falls back on the built-in one
+/// rather than failing, but that other errors are still fatal.
+#[test]
+fn check_config_or_fallback() {
+    type Conn = x11rb::rust_connection::RustConnection;
+    let malformed = toml::from_str::<Config<Conn>>("border_width = ")
+        .map_err(|err| -> Box<dyn Error> { Box::new(err) });
+    let config = config_or_fallback(malformed, Purpose::Run).unwrap();
+    assert_eq!(
+        config.border_width,
+        Config::<Conn>::built_in(Purpose::Run).border_width
+    );
+
+    let invalid: Result<Config<Conn>> = Err(Box::new(ConfigError::InvalidAction(
+        "launch_rockets".to_string(),
+    )));
+    assert!(config_or_fallback(invalid, Purpose::Print).is_ok());
+
+    let denied: Result<Config<Conn>> = Err(Box::new(std::io::Error::from(
+        std::io::ErrorKind::PermissionDenied,
+    )));
+    assert!(config_or_fallback(denied, Purpose::Run).is_err());
+}
+
+/// Confirm that mapping an override-redirect window never moves the focus,
+/// even when new windows are always focused.
+#[test]
+fn check_focus_on_map() {
+    let menu = dummy_override_redirect(100);
+    assert!(!focus_on_map(&menu, FocusNew::Always, None, 0));
+    let mut dialog = dummy_client(200, true);
+    assert!(focus_on_map(&dialog, FocusNew::Always, None, 0));
+    assert!(!focus_on_map(&dialog, FocusNew::Never, None, 0));
+    dialog.state.as_mut().unwrap().modal = true;
+    assert!(focus_on_map(&dialog, FocusNew::Never, None, 0));
+}
+
+/// Confirm that only the click that focuses a window can be swallowed.
+#[test]
+fn check_replay_click() {
+    assert!(replay_click(true, false));
+    assert!(replay_click(true, true));
+    assert!(!replay_click(false, false));
+    assert!(replay_click(false, true));
+}
+
+/// Confirm that only pointer mapping changes refresh the button grabs.
+#[test]
+fn check_mapping_changes_buttons() {
+    assert!(mapping_changes_buttons(xproto::Mapping::POINTER));
+    assert!(!mapping_changes_buttons(xproto::Mapping::KEYBOARD));
+    assert!(!mapping_changes_buttons(xproto::Mapping::MODIFIER));
+}
+
+/// Confirm that a hinted minimum below our default is respected, down to the
+/// absolute floor.
+#[test]
+fn check_size_limits() {
+    let mut hints = WmSizeHints::new();
+    assert_eq!(
+        size_limits(&hints),
+        (
+            (i32::from(MIN_WIDTH), i32::from(MIN_HEIGHT)),
+            (i32::from(MAX_WIDTH), i32::from(MAX_HEIGHT))
+        )
+    );
+    hints.min_size = Some((32, 16));
+    assert_eq!(size_limits(&hints).0, (32, 16));
+    assert_eq!(clamp_size(&hints, 40, 10), (40, 16));
+    hints.min_size = Some((0, -5));
+    assert_eq!(size_limits(&hints).0, (MIN_SIZE_FLOOR, MIN_SIZE_FLOOR));
+    assert_eq!(clamp_size(&hints, 0, 0), (1, 1));
+}
+
+/// Confirm that window sizes are clamped to their hints, and to our defaults
+/// without them.
+#[test]
+fn check_clamp_size() {
+    let mut hints = WmSizeHints::new();
+    assert_eq!(clamp_size(&hints, 300, 200), (300, 200));
+    assert_eq!(clamp_size(&hints, 10, 20), (MIN_WIDTH, MIN_HEIGHT));
+    hints.min_size = Some((400, 100));
+    hints.max_size = Some((800, 150));
+    assert_eq!(clamp_size(&hints, 300, 200), (400, 150));
+    assert_eq!(clamp_size(&hints, 500, 120), (500, 120));
+    hints.min_size = None;
+    hints.max_size = None;
+    assert_eq!(clamp_size(&hints, 400, 150), (400, 150));
+}
+
+/// Confirm that directional focus picks the nearest window that's mostly in
+/// the right direction, and wraps around only when asked to.
+#[test]
+fn check_direction_nearest() {
+    //     200
+    // 100  *  300 400
+    //     500
+    let candidates = [
+        (100, (0, 100)),
+        (200, (100, 0)),
+        (300, (220, 110)),
+        (400, (400, 100)),
+        (500, (90, 200)),
+    ];
+    let from = (100, 100);
+    assert_eq!(Direction::Left.nearest(from, &candidates, false), Some(100));
+    assert_eq!(
+        Direction::Right.nearest(from, &candidates, false),
+        Some(300)
+    );
+    assert_eq!(Direction::Up.nearest(from, &candidates, false), Some(200));
+    assert_eq!(Direction::Down.nearest(from, &candidates, false), Some(500));
+
+    let from = (400, 100);
+    let others = &candidates[..3];
+    assert_eq!(Direction::Right.nearest(from, others, false), None);
+    assert_eq!(Direction::Right.nearest(from, others, true), Some(100));
+    assert_eq!(Direction::Left.nearest(from, &[], true), None);
+
+    // Off the left edge, wrap to the window on the far right that's most
+    // nearly level.
+    let from = (0, 100);
+    let others = [(600, (400, 300)), (700, (400, 120)), (800, (200, 100))];
+    assert_eq!(Direction::Left.nearest(from, &others, false), None);
+    assert_eq!(Direction::Left.nearest(from, &others, true), Some(700));
+}
+
+/// Confirm that pinned windows end up flush against the right corner.
+#[test]
+fn check_pinned_origin() {
+    let origin = |corner: Corner, margin| corner.pinned_origin(1920, 1080, 320, 180, margin);
+    assert_eq!(origin(Corner::LeftTop, 0), (0, 0));
+    assert_eq!(origin(Corner::RightTop, 0), (1600, 0));
+    assert_eq!(origin(Corner::LeftBottom, 0), (0, 900));
+    assert_eq!(origin(Corner::RightBottom, 0), (1600, 900));
+    assert_eq!(origin(Corner::LeftTop, 10), (10, 10));
+    assert_eq!(origin(Corner::RightTop, 10), (1590, 10));
+    assert_eq!(origin(Corner::LeftBottom, 10), (10, 890));
+    assert_eq!(origin(Corner::RightBottom, 10), (1590, 890));
+}
+
+/// Confirm that smart borders are hidden only for a lone visible window.
+#[test]
+fn check_smart_border_hidden() {
+    assert!(!smart_border_hidden(0, false));
+    assert!(smart_border_hidden(1, true));
+    assert!(!smart_border_hidden(1, false));
+    assert!(!smart_border_hidden(2, true));
+    assert!(!smart_border_hidden(2, false));
+}
+
+/// Confirm that cycling through size presets starts at the first and wraps.
+#[test]
+fn check_next_size_preset() {
+    assert_eq!(next_size_preset(None, 0), None);
+    assert_eq!(next_size_preset(None, 3), Some(0));
+    assert_eq!(next_size_preset(Some(0), 3), Some(1));
+    assert_eq!(next_size_preset(Some(1), 3), Some(2));
+    assert_eq!(next_size_preset(Some(2), 3), Some(0));
+    // The list might have shrunk since the last press.
+    assert_eq!(next_size_preset(Some(5), 3), Some(0));
+}
+
+/// Confirm that client-side shadows are left out when pinning a window.
+#[test]
+fn check_visible_part() {
+    assert_eq!(visible_part(320, 180, [0; 4]), (320, 180, 0, 0));
+    assert_eq!(visible_part(340, 210, [10, 10, 8, 22]), (320, 180, 10, 8));
+    assert_eq!(visible_part(10, 10, [20, 20, 20, 20]), (0, 0, 20, 20));
+
+    // Pinning to the bottom right puts the visible part flush with the corner,
+    // with the shadow hanging off the screen.
+    let (width, height, dx, dy) = visible_part(340, 210, [10, 10, 8, 22]);
+    let (x, y) = Corner::RightBottom.pinned_origin(1920, 1080, width, height, 0);
+    assert_eq!((x - dx, y - dy), (1590, 892));
+    assert_eq!(x - dx + 340 - 10, 1920);
+    assert_eq!(y - dy + 210 - 22, 1080);
+}
+
+/// Confirm that locked windows can't be dragged.
+#[test]
+fn check_locked_drag() {
+    let mut client = dummy_client(100, true);
+    let st = client.state.as_mut().unwrap();
+    let drag = Drag::start(100, st, 1, 5, 5).unwrap();
+    assert_eq!(drag.type_, DragType::Move);
+    assert!(Drag::start(100, st, 3, 5, 5).is_some());
+    st.locked = true;
+    assert!(Drag::start(100, st, 1, 5, 5).is_none());
+    assert!(Drag::start(100, st, 3, 5, 5).is_none());
+}
+
+/// Confirm that, with clamping turned off, ConfigureRequests for managed
+/// windows go through unmodified, even below the minimum size.
+#[test]
+fn check_unclamped_configure_request() {
+    let ev = xproto::ConfigureRequestEvent {
+        response_type: xproto::CONFIGURE_REQUEST_EVENT,
+        stack_mode: xproto::StackMode::ABOVE,
+        sequence: 0,
+        parent: 1,
+        window: 100,
+        sibling: x11rb::NONE,
+        x: 50,
+        y: 60,
+        width: 300,
+        height: 1,
+        border_width: 0,
+        value_mask: u16::from(
+            xproto::ConfigWindow::X
+                | xproto::ConfigWindow::Y
+                | xproto::ConfigWindow::WIDTH
+                | xproto::ConfigWindow::HEIGHT,
+        ),
+    };
+    let client = dummy_client(100, true);
+    let value_list = configure_request_aux(&ev, &client, true);
+    assert_eq!(value_list.height, Some(u32::from(MIN_HEIGHT)));
+    let value_list = configure_request_aux(&ev, &client, false);
+    assert_eq!(value_list, ConfigureWindowAux::from_configure_request(&ev));
+    assert_eq!(value_list.height, Some(1));
+}
+
+/// Confirm that ConfigureRequests can't move or resize locked windows, but
+/// that other windows are configured as usual.
+#[test]
+fn check_locked_configure_request() {
+    let ev = xproto::ConfigureRequestEvent {
+        response_type: xproto::CONFIGURE_REQUEST_EVENT,
+        stack_mode: xproto::StackMode::ABOVE,
+        sequence: 0,
+        parent: 1,
+        window: 100,
+        sibling: x11rb::NONE,
+        x: 50,
+        y: 60,
+        width: 300,
+        height: 200,
+        border_width: 0,
+        value_mask: u16::from(
+            xproto::ConfigWindow::X
+                | xproto::ConfigWindow::Y
+                | xproto::ConfigWindow::WIDTH
+                | xproto::ConfigWindow::HEIGHT
+                | xproto::ConfigWindow::STACK_MODE,
+        ),
+    };
+    let mut client = dummy_client(100, true);
+    let value_list = configure_request_aux(&ev, &client, true);
+    assert_eq!(value_list.x, Some(50));
+    assert_eq!(value_list.width, Some(300));
+
+    client.state.as_mut().unwrap().locked = true;
+    let value_list = configure_request_aux(&ev, &client, true);
+    assert_eq!(value_list.x, None);
+    assert_eq!(value_list.y, None);
+    assert_eq!(value_list.width, None);
+    assert_eq!(value_list.height, None);
+    assert_eq!(value_list.stack_mode, Some(xproto::StackMode::ABOVE));
+
+    // Override-redirect windows get what they ask for.
+    let client = dummy_override_redirect(100);
+    let value_list = configure_request_aux(&ev, &client, true);
+    assert_eq!(value_list.width, Some(300));
+}
+
+/// Confirm that WM_CHANGE_STATE only iconifies when asked for Iconic.
+#[test]
+fn check_change_state_iconifies() {
+    assert!(change_state_iconifies(u32::from(WmStateState::Iconic)));
+    assert!(!change_state_iconifies(u32::from(WmStateState::Normal)));
+    assert!(!change_state_iconifies(u32::from(WmStateState::Withdrawn)));
+    assert!(!change_state_iconifies(2));
+}
+
+/// Confirm that startup programs that have failed are detected, and that ones
+/// that succeeded or are still running aren't.
+#[test]
+fn check_check_exited() {
+    let mut child = Command::new("/bin/false").spawn().unwrap();
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let status = loop {
+        if let Some(status) = check_exited(&mut child).unwrap() {
+            break status;
+        }
+        assert!(Instant::now() < deadline);
+        std::thread::sleep(Duration::from_millis(10));
+    };
+    assert!(!status.success());
+
+    let mut child = Command::new("/bin/true").spawn().unwrap();
+    child.wait().unwrap();
+    assert_eq!(check_exited(&mut child).unwrap(), None);
+
+    let mut child = Command::new("/bin/sleep").arg("10").spawn().unwrap();
+    assert_eq!(check_exited(&mut child).unwrap(), None);
+    child.kill().unwrap();
+    child.wait().unwrap();
+}
+
+/// Confirm that startup programs are only checked on when `startup_check_ms`
+/// is set, and only once it has passed.
+#[test]
+fn check_startup_check_schedule() {
+    let now = Instant::now();
+    assert!(StartupCheck::schedule(vec![], 500, now).is_none());
+    let child = Command::new("/bin/true").spawn().unwrap();
+    let children = vec![("/bin/true".to_owned(), child)];
+    assert!(StartupCheck::schedule(children, 0, now).is_none());
+    let mut child = Command::new("/bin/true").spawn().unwrap();
+    child.wait().unwrap();
+    let children = vec![("/bin/true".to_owned(), child)];
+    let check = StartupCheck::schedule(children, 500, now).unwrap();
+    assert!(!check.is_due(now));
+    assert!(!check.is_due(now + Duration::from_millis(499)));
+    assert!(check.is_due(now + Duration::from_millis(500)));
+    check.report();
+}
+
+/// Confirm that setup selects SubstructureRedirect on every screen's root
+/// window, starting with the screen that we connected on.
+#[test]
+fn check_roots_to_redirect() {
+    let screen = |root| xproto::Screen {
+        root,
+        default_colormap: 0,
+        white_pixel: 0,
+        black_pixel: 0,
+        current_input_masks: 0,
+        width_in_pixels: 1920,
+        height_in_pixels: 1080,
+        width_in_millimeters: 0,
+        height_in_millimeters: 0,
+        min_installed_maps: 1,
+        max_installed_maps: 1,
+        root_visual: 0,
+        backing_stores: xproto::BackingStore::NOT_USEFUL,
+        save_unders: false,
+        root_depth: 24,
+        allowed_depths: vec![],
+    };
+    let roots = [screen(0x100), screen(0x200), screen(0x300)];
+    assert_eq!(
+        roots_to_redirect(&roots, 0),
+        vec![(0, 0x100), (1, 0x200), (2, 0x300)]
+    );
+    assert_eq!(
+        roots_to_redirect(&roots, 1),
+        vec![(1, 0x200), (0, 0x100), (2, 0x300)]
+    );
+    assert_eq!(roots_to_redirect(&roots[..1], 0), vec![(0, 0x100)]);
+}
+
+/// Confirm that switching screens swaps the live state with the parked state,
+/// that switching back restores it, and that windows are found on the right
+/// screen.
+#[test]
+fn check_swap_screen() {
+    fn swap_with(live: &mut Vec<u32>) -> impl FnOnce(&mut Vec<u32>) + '_ {
+        move |state| std::mem::swap(live, state)
+    }
+    let mut parked: HashMap<usize, Vec<u32>> = HashMap::new();
+    parked.insert(2, vec![0x201]);
+    let mut current = 0;
+    let mut live = vec![0x101, 0x102];
+    assert_eq!(screen_order(current, &parked), vec![0, 2]);
+
+    // An unmanaged screen isn't switched to.
+    assert!(!swap_screen(
+        &mut parked,
+        &mut current,
+        1,
+        swap_with(&mut live)
+    ));
+    assert_eq!(current, 0);
+    assert_eq!(live, vec![0x101, 0x102]);
+
+    assert!(swap_screen(
+        &mut parked,
+        &mut current,
+        2,
+        swap_with(&mut live)
+    ));
+    assert_eq!(current, 2);
+    assert_eq!(live, vec![0x201]);
+    assert_eq!(parked[&0], vec![0x101, 0x102]);
+    assert!(!parked.contains_key(&2));
+    assert_eq!(screen_order(current, &parked), vec![0, 2]);
+
+    let roots = [0x100, 0x200, 0x300];
+    let find = |current, parked: &HashMap<usize, Vec<u32>>, live: &[u32], window| {
+        find_screen(
+            &roots,
+            current,
+            parked,
+            window,
+            live.contains(&window),
+            |state: &Vec<u32>| state.contains(&window),
+        )
+    };
+    assert_eq!(find(current, &parked, &live, 0x201), Some(2));
+    assert_eq!(find(current, &parked, &live, 0x102), Some(0));
+    assert_eq!(find(current, &parked, &live, 0x100), Some(0));
+    assert_eq!(find(current, &parked, &live, 0x300), Some(2));
+    // Screen 1 isn't managed, so neither is its root.
+    assert_eq!(find(current, &parked, &live, 0x200), None);
+    assert_eq!(find(current, &parked, &live, 0x999), None);
+
+    assert!(swap_screen(
+        &mut parked,
+        &mut current,
+        0,
+        swap_with(&mut live)
+    ));
+    assert_eq!(current, 0);
+    assert_eq!(live, vec![0x101, 0x102]);
+    assert_eq!(parked[&2], vec![0x201]);
 }
@@ -0,0 +1,143 @@
+//! Shutting down cleanly on SIGTERM and SIGINT.
+//!
+//! A signal handler can't safely do much, so ours just sets a flag and writes
+//! a byte to a pipe. The event loop polls the read end of the pipe alongside
+//! the X connection, so that a signal wakes it up even if it arrives just
+//! before we go to sleep.
+
+use std::os::unix::io::RawFd;
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+
+use crate::Result;
+
+/// Set when we've been asked to shut down.
+static SHUTDOWN: AtomicBool = AtomicBool::new(false);
+
+/// The write end of the pipe that the signal handler wakes us up with, or -1
+/// if there isn't one yet.
+static WAKE_FD: AtomicI32 = AtomicI32::new(-1);
+
+/// A pipe that lets a signal handler wake up the event loop.
+#[derive(Debug)]
+pub(crate) struct SelfPipe {
+    /// The read end, which the event loop polls.
+    read: RawFd,
+    /// The write end, which the signal handler writes to.
+    write: RawFd,
+}
+
+impl SelfPipe {
+    /// Create a new pipe. Both ends are non-blocking, so that neither the
+    /// signal handler nor `drain` can get stuck.
+    pub(crate) fn new() -> Result<SelfPipe> {
+        let mut fds = [0; 2];
+        // Safety: `fds` has room for the two file descriptors.
+        if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_NONBLOCK | libc::O_CLOEXEC) } < 0 {
+            return Err(Box::new(std::io::Error::last_os_error()));
+        }
+        Ok(SelfPipe {
+            read: fds[0],
+            write: fds[1],
+        })
+    }
+
+    /// The file descriptor to poll for wakeups.
+    pub(crate) fn fd(&self) -> RawFd {
+        self.read
+    }
+
+    /// Wake up whoever is polling the pipe.
+    #[cfg(test)]
+    fn wake(&self) {
+        wake(self.write);
+    }
+
+    /// Consume any pending wakeups. Returns whether there were any.
+    pub(crate) fn drain(&self) -> bool {
+        let mut buf = [0u8; 64];
+        let mut woken = false;
+        // Safety: `buf` is valid for writes of its whole length.
+        while unsafe { libc::read(self.read, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) } > 0
+        {
+            woken = true;
+        }
+        woken
+    }
+}
+
+impl Drop for SelfPipe {
+    fn drop(&mut self) {
+        // Don't let the signal handler write to a closed (or reused) fd.
+        let _ = WAKE_FD.compare_exchange(self.write, -1, Ordering::SeqCst, Ordering::SeqCst);
+        // Safety: we own both file descriptors, and nothing uses them after
+        // this.
+        unsafe {
+            libc::close(self.read);
+            libc::close(self.write);
+        }
+    }
+}
+
+/// Write a single byte to `fd`. This is async-signal-safe.
+fn wake(fd: RawFd) {
+    let byte = 1u8;
+    // Safety: `byte` is valid for reads of one byte. If the pipe is full,
+    // there's already a wakeup pending, so a failed write doesn't matter.
+    unsafe {
+        libc::write(fd, &byte as *const u8 as *const libc::c_void, 1);
+    }
+}
+
+/// The signal handler: note that we should shut down, and wake up the event
+/// loop.
+extern "C" fn handle_shutdown_signal(_: libc::c_int) {
+    SHUTDOWN.store(true, Ordering::SeqCst);
+    let fd = WAKE_FD.load(Ordering::SeqCst);
+    if fd >= 0 {
+        wake(fd);
+    }
+}
+
+/// Arrange for SIGTERM and SIGINT to request a shutdown, waking up `pipe`.
+pub(crate) fn install_shutdown_handlers(pipe: &SelfPipe) -> Result<()> {
+    WAKE_FD.store(pipe.write, Ordering::SeqCst);
+    for &signal in &[libc::SIGTERM, libc::SIGINT] {
+        // Safety: the handler only touches atomics and calls write(2), both of
+        // which are async-signal-safe, and `action` is fully initialized
+        // before it's used.
+        unsafe {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = handle_shutdown_signal as extern "C" fn(libc::c_int) as usize;
+            libc::sigemptyset(&mut action.sa_mask);
+            if libc::sigaction(signal, &action, std::ptr::null_mut()) < 0 {
+                return Err(Box::new(std::io::Error::last_os_error()));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Whether a signal has asked us to shut down.
+pub(crate) fn shutdown_requested() -> bool {
+    SHUTDOWN.load(Ordering::SeqCst)
+}
+
+/// Confirm that waking the pipe makes it readable, and that draining it
+/// consumes every pending wakeup.
+#[test]
+fn check_self_pipe() {
+    let pipe = SelfPipe::new().unwrap();
+    let mut fds = [libc::pollfd {
+        fd: pipe.fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    }];
+    assert!(!pipe.drain());
+    assert_eq!(unsafe { libc::poll(fds.as_mut_ptr(), 1, 0) }, 0);
+    pipe.wake();
+    pipe.wake();
+    assert_eq!(unsafe { libc::poll(fds.as_mut_ptr(), 1, 0) }, 1);
+    assert!(pipe.drain());
+    assert!(!pipe.drain());
+    assert_eq!(unsafe { libc::poll(fds.as_mut_ptr(), 1, 0) }, 0);
+}
@@ -6,7 +6,7 @@ use std::convert::TryInto;
 use x11rb::protocol::xproto;
 
 use libc::{c_char, c_ulong};
-use std::ffi::CString;
+use std::ffi::{CStr, CString};
 
 /// Convert an `EventMask` to a `u16`. Note that not every event mask is
 /// convertible
@@ -16,6 +16,59 @@ pub fn event_mask_to_u16(mask: xproto::EventMask) -> u16 {
     mask
 }
 
+/// Find the keycodes that are mapped to any of the modifiers in `mask`, given
+/// the contents of a `GetModifierMapping` reply.
+pub fn modifier_keycodes(
+    keycodes_per_modifier: u8,
+    keycodes: &[xproto::Keycode],
+    mask: u16,
+) -> Vec<xproto::Keycode> {
+    let per = keycodes_per_modifier as usize;
+    if per == 0 {
+        return Vec::new();
+    }
+    keycodes
+        .chunks(per)
+        .enumerate()
+        .filter(|(i, _)| mask & (1 << i) != 0)
+        .flat_map(|(_, chunk)| chunk.iter().copied())
+        .filter(|&keycode| keycode != 0)
+        .collect()
+}
+
+/// The names of the eight modifiers, in modifier-mapping order, as they're
+/// written for `mod_mask` in the config file.
+const MODIFIER_NAMES: [&str; 8] = [
+    "shift", "lock", "control", "mod1", "mod2", "mod3", "mod4", "mod5",
+];
+
+/// Format the contents of a `GetModifierMapping` reply for people to read: one
+/// line per modifier, listing its keycodes along with the names that
+/// `keycode_name` gives them.
+pub fn format_modifier_map<F>(
+    keycodes_per_modifier: u8,
+    keycodes: &[xproto::Keycode],
+    keycode_name: F,
+) -> String
+where
+    F: Fn(xproto::Keycode) -> Option<String>,
+{
+    let mut ret = String::new();
+    for (i, name) in MODIFIER_NAMES.iter().enumerate() {
+        let keys = modifier_keycodes(keycodes_per_modifier, keycodes, 1 << i)
+            .into_iter()
+            .map(|keycode| match keycode_name(keycode) {
+                Some(key_name) => format!("{} ({})", keycode, key_name),
+                None => keycode.to_string(),
+            })
+            .collect::<Vec<_>>();
+        let line = format!("{:<8} {}", name, keys.join(", "));
+        ret.push_str(line.trim_end());
+        ret.push('\n');
+    }
+    ret
+}
+
 /// Lookup the numeric value for a given `Keysym`'s text name, e.g. "Shift_L" -> 50
 /// Returns `None` if `key_name` is not the name of a valid Keysym or contains
 /// `null` values.
@@ -65,12 +118,30 @@ pub fn keysym_from_name(key_name: &str) -> Option<xproto::Keysym> {
     }
 }
 
+/// Lookup the text name of a `Keysym`, e.g. 50 -> "Shift_L". Returns `None`
+/// if the Keysym has no name.
+pub fn keysym_name(keysym: xproto::Keysym) -> Option<String> {
+    // Safety: XKeysymToString returns either a null pointer or a pointer to
+    //         a null-terminated string in static storage, which must not be
+    //         freed or modified. We copy the string before returning.
+    unsafe {
+        let name = XKeysymToString(c_ulong::from(keysym));
+        if name.is_null() {
+            None
+        } else {
+            Some(CStr::from_ptr(name).to_string_lossy().into_owned())
+        }
+    }
+}
+
 /// An FFI call to the X11 C library function for converting from Keysym names
 /// to Keysym values. This is unsafe code. 'symbol' _must_ be a pointer to a
 /// null terminated C style string such as is produced by `std::ffi::Cstring`.
+/// `XKeysymToString` does the reverse; its result _must not_ be freed.
 #[link(name = "X11")]
 extern "C" {
     fn XStringToKeysym(symbol_name: *const c_char) -> c_ulong;
+    fn XKeysymToString(keysym: c_ulong) -> *const c_char;
 }
 
 /// Query the running X11 server for the Keycode currently mapped, if any, to a Keysym.
@@ -88,3 +159,47 @@ pub fn keycode_from_keysym(keysym_value: xproto::Keysym) -> Option<xproto::Keyco
         None
     }
 }
+
+/// Confirm that modifier keycodes are extracted from the right rows of a
+/// modifier mapping.
+#[test]
+fn check_modifier_keycodes() {
+    // Two keycodes per modifier: Shift, Lock, Control, Mod1, ..., Mod5.
+    let keycodes = [
+        50, 62, 66, 0, 37, 105, 64, 108, 77, 0, 0, 0, 133, 134, 92, 0,
+    ];
+    let shift = u16::from(xproto::ModMask::SHIFT);
+    let mod1 = u16::from(xproto::ModMask::M1);
+    let mod4 = u16::from(xproto::ModMask::M4);
+    assert_eq!(modifier_keycodes(2, &keycodes, shift), vec![50, 62]);
+    assert_eq!(modifier_keycodes(2, &keycodes, mod4), vec![133, 134]);
+    assert_eq!(
+        modifier_keycodes(2, &keycodes, mod1 | mod4),
+        vec![64, 108, 133, 134]
+    );
+    assert!(modifier_keycodes(0, &[], mod4).is_empty());
+}
+
+/// Confirm that a modifier mapping is formatted one modifier per line.
+#[test]
+fn check_format_modifier_map() {
+    let keycodes = [
+        50, 62, 66, 0, 37, 105, 64, 108, 77, 0, 0, 0, 133, 134, 92, 0,
+    ];
+    let names = |keycode| match keycode {
+        50 => Some("Shift_L".to_string()),
+        133 => Some("Super_L".to_string()),
+        _ => None,
+    };
+    assert_eq!(
+        format_modifier_map(2, &keycodes, names),
+        "shift    50 (Shift_L), 62\n\
+         lock     66\n\
+         control  37, 105\n\
+         mod1     64, 108\n\
+         mod2     77\n\
+         mod3\n\
+         mod4     133 (Super_L), 134\n\
+         mod5     92\n"
+    );
+}